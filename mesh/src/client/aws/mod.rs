@@ -1,3 +1,7 @@
+mod presign;
+
+pub use presign::{default_sts_endpoint, presign_get_caller_identity};
+
 use anyhow::Result;
 use aws_sdk_ecs::Client as EcsClient;
 use aws_sdk_sts::Client as StsClient;
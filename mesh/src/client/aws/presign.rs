@@ -0,0 +1,182 @@
+//! Presigned STS `GetCallerIdentity` URLs for the IAM auth handshake.
+
+use crate::common::auth::MESH_AUTH_NONCE_HEADER;
+use anyhow::{Context, Result};
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{sign, SignableRequest, SignatureLocation, SigningSettings};
+use aws_sigv4::SigningParams;
+use hyper::Request;
+use std::time::{Duration, SystemTime};
+
+/// How long a presigned URL stays valid; the ingress rejects anything longer
+/// than its `presigned_url_max_expires_secs` (60 by default)
+const PRESIGNED_URL_EXPIRES: Duration = Duration::from_secs(60);
+
+/// Regional STS endpoint for `region`
+pub fn default_sts_endpoint(region: &str) -> String {
+    if region.starts_with("cn-") {
+        format!("https://sts.{}.amazonaws.com.cn", region)
+    } else {
+        format!("https://sts.{}.amazonaws.com", region)
+    }
+}
+
+/// Presign a `GetCallerIdentity` call against `endpoint`, signing `nonce` as
+/// the `x-mesh-auth-nonce` header
+pub fn presign_get_caller_identity(
+    credentials: &Credentials,
+    region: &str,
+    endpoint: &str,
+    nonce: &str,
+    time: SystemTime,
+) -> Result<String> {
+    let mut request = Request::get(format!(
+        "{}/?Action=GetCallerIdentity&Version=2011-06-15",
+        endpoint.trim_end_matches('/')
+    ))
+    .header(MESH_AUTH_NONCE_HEADER, nonce)
+    .body(Vec::<u8>::new())
+    .with_context(|| format!("Invalid STS endpoint {}", endpoint))?;
+
+    let mut settings = SigningSettings::default();
+    settings.signature_location = SignatureLocation::QueryParams;
+    settings.expires_in = Some(PRESIGNED_URL_EXPIRES);
+
+    let mut params = SigningParams::builder()
+        .access_key(credentials.access_key_id())
+        .secret_key(credentials.secret_access_key())
+        .region(region)
+        .service_name("sts")
+        .time(time)
+        .settings(settings);
+    params.set_security_token(credentials.session_token());
+    let params = params.build()?;
+
+    let (instructions, _signature) = sign(SignableRequest::from(&request), &params)?.into_parts();
+    instructions.apply_to_request(&mut request);
+
+    Ok(request.uri().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // 2024-05-01T12:00:00Z
+    const SIGNING_TIME: u64 = 1_714_564_800;
+
+    fn credentials(session_token: Option<&str>) -> Credentials {
+        Credentials::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            session_token.map(str::to_string),
+            None,
+            "test",
+        )
+    }
+
+    fn presign(credentials: &Credentials, region: &str, endpoint: &str, nonce: &str) -> String {
+        presign_get_caller_identity(
+            credentials,
+            region,
+            endpoint,
+            nonce,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(SIGNING_TIME),
+        )
+        .unwrap()
+    }
+
+    fn query(url: &str) -> HashMap<String, String> {
+        let uri: hyper::Uri = url.parse().unwrap();
+        uri.query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| {
+                (
+                    k.to_string(),
+                    percent_encoding::percent_decode_str(v)
+                        .decode_utf8()
+                        .unwrap()
+                        .into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_default_sts_endpoint() {
+        assert_eq!(
+            default_sts_endpoint("eu-west-1"),
+            "https://sts.eu-west-1.amazonaws.com"
+        );
+        assert_eq!(
+            default_sts_endpoint("cn-north-1"),
+            "https://sts.cn-north-1.amazonaws.com.cn"
+        );
+    }
+
+    #[test]
+    fn test_presigned_url_signs_region_and_challenge() {
+        let url = presign(
+            &credentials(None),
+            "eu-west-1",
+            &default_sts_endpoint("eu-west-1"),
+            "nonce-1",
+        );
+        assert!(url.starts_with("https://sts.eu-west-1.amazonaws.com/?"));
+
+        let params = query(&url);
+        assert_eq!(params["Action"], "GetCallerIdentity");
+        assert_eq!(params["Version"], "2011-06-15");
+        assert_eq!(params["X-Amz-Algorithm"], "AWS4-HMAC-SHA256");
+        assert_eq!(
+            params["X-Amz-Credential"],
+            "AKIDEXAMPLE/20240501/eu-west-1/sts/aws4_request"
+        );
+        assert_eq!(params["X-Amz-Date"], "20240501T120000Z");
+        assert_eq!(params["X-Amz-Expires"], "60");
+        assert_eq!(params["X-Amz-SignedHeaders"], "host;x-mesh-auth-nonce");
+        assert_eq!(
+            params["X-Amz-Signature"],
+            "9195a9bf69c3bbeaafd211749768837d15a4cb16e206d96952d8ce04102d7e1b"
+        );
+        assert!(!params.contains_key("X-Amz-Security-Token"));
+
+        // The signature covers the challenge
+        let other = query(&presign(
+            &credentials(None),
+            "eu-west-1",
+            &default_sts_endpoint("eu-west-1"),
+            "nonce-2",
+        ));
+        assert_ne!(params["X-Amz-Signature"], other["X-Amz-Signature"]);
+    }
+
+    #[test]
+    fn test_presigned_url_with_session_token_and_endpoint_override() {
+        let url = presign(
+            &credentials(Some("session/token+value")),
+            "us-gov-west-1",
+            "https://sts-fips.us-gov-west-1.amazonaws.com/",
+            "nonce",
+        );
+        assert!(url.starts_with("https://sts-fips.us-gov-west-1.amazonaws.com/?"));
+
+        let params = query(&url);
+        assert_eq!(params["X-Amz-Security-Token"], "session/token+value");
+        assert_eq!(
+            params["X-Amz-Credential"],
+            "AKIDEXAMPLE/20240501/us-gov-west-1/sts/aws4_request"
+        );
+
+        let url = presign(
+            &credentials(None),
+            "us-east-1",
+            "http://localhost:4566",
+            "nonce",
+        );
+        assert!(url.starts_with("http://localhost:4566/?"));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::common::{IngressMessage, ServiceRegistration};
+use crate::common::{IngressMessage, PathRoute, ServiceRegistration};
 use anyhow::Result;
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_ecs::Client as EcsClient;
@@ -7,23 +7,25 @@ use futures_util::{SinkExt, StreamExt};
 use hyper::{Body, Client};
 use hyper_tls::HttpsConnector;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 use tokio::time::{interval, sleep, Duration};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_tungstenite::{
+    connect_async, connect_async_tls_with_config, tungstenite::Message, Connector,
+};
 use tracing::{debug, error, info, instrument, warn};
 use uuid::Uuid;
 
-use super::aws::AwsService;
+use super::aws::{default_sts_endpoint, presign_get_caller_identity, AwsService};
 use super::proxy::ws::WebSocketReverseProxy;
 use super::proxy::ProxyHandler;
+use super::tls::build_connector;
+use crate::common::config::ClientTlsConfig;
 // use crate::common::auth::IamAuthHelper;
 use aws_sdk_ecs::config::ProvideCredentials;
-use chrono::Utc;
-use hmac::{Hmac, Mac};
-use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
-use sha2::{Digest, Sha256};
 
 pub struct EcsAnywhereClient {
     pub client_id: Uuid,
@@ -32,8 +34,23 @@ pub struct EcsAnywhereClient {
     pub host: String,
     pub port: u16,
     pub health_check_path: String,
-    #[allow(dead_code)]
+    pub attributes: HashMap<String, String>,
+    /// Paths to route to this service under `host`; empty for the whole host
+    pub paths: Vec<PathRoute>,
+    /// Mesh token to authenticate with instead of IAM
+    pub auth_token: Option<String>,
+    /// File holding a workload identity JWT to authenticate with instead of IAM
+    pub jwt_file: Option<PathBuf>,
+    /// TLS connector for `wss://` endpoints (custom CA and/or client certificate)
+    pub tls_connector: Option<native_tls::TlsConnector>,
+    /// Whether the connection is authenticated by a client certificate
+    pub client_cert_auth: bool,
     pub local_endpoint: String,
+    /// Region presigned STS URLs are signed for
+    pub region: String,
+    /// STS endpoint presigned URLs target (regional, FIPS or a local mock)
+    pub sts_endpoint: String,
+    pub sdk_config: aws_config::SdkConfig,
     #[allow(dead_code)]
     pub http_client: Client<HttpsConnector<hyper::client::HttpConnector>>,
     pub aws_service: AwsService,
@@ -41,146 +58,109 @@ pub struct EcsAnywhereClient {
     pub ws_proxy: WebSocketReverseProxy,
 }
 
-const AWS_QUERY_ENCODE_SET: &AsciiSet = &CONTROLS
-    .add(b' ') // space
-    .add(b'!')
-    .add(b'"')
-    .add(b'#')
-    .add(b'$')
-    .add(b'%')
-    .add(b'&')
-    .add(b'\'')
-    .add(b'(')
-    .add(b')')
-    .add(b'*')
-    .add(b'+')
-    .add(b',')
-    .add(b'/')
-    .add(b':')
-    .add(b';')
-    .add(b'=')
-    .add(b'?')
-    .add(b'@')
-    .add(b'[')
-    .add(b']');
-
 impl EcsAnywhereClient {
-    async fn build_presigned_sts_url(&self) -> Result<String> {
-        // Region and credentials from default chain
-        let cfg = aws_config::defaults(aws_config::BehaviorVersion::latest())
-            .load()
-            .await;
-        let region = cfg
-            .region()
-            .map(|r| r.as_ref().to_string())
-            .unwrap_or_else(|| "us-east-1".to_string());
-        let creds = cfg
+    /// Presign a `GetCallerIdentity` call that also signs the server's auth challenge
+    async fn build_presigned_sts_url(&self, nonce: &str) -> Result<String> {
+        let credentials = self
+            .sdk_config
             .credentials_provider()
             .ok_or_else(|| anyhow::anyhow!("No AWS credentials provider available"))?
             .provide_credentials()
             .await?;
 
-        let access_key = creds.access_key_id();
-        let secret_key = creds.secret_access_key();
-        let session_token = creds.session_token().map(|s| s.to_string());
-
-        let service = "sts";
-        let host = format!("sts.{}.amazonaws.com", region);
-        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
-        let date_stamp = &amz_date[..8];
-
-        // Canonical query base
-        let mut query: Vec<(String, String)> = vec![
-            ("Action".to_string(), "GetCallerIdentity".to_string()),
-            ("Version".to_string(), "2011-06-15".to_string()),
-            (
-                "X-Amz-Algorithm".to_string(),
-                "AWS4-HMAC-SHA256".to_string(),
-            ),
-            (
-                "X-Amz-Credential".to_string(),
-                format!(
-                    "{}/{}/{}/{}/aws4_request",
-                    access_key, date_stamp, region, service
-                ),
-            ),
-            ("X-Amz-Date".to_string(), amz_date.clone()),
-            ("X-Amz-Expires".to_string(), "60".to_string()),
-            ("X-Amz-SignedHeaders".to_string(), "host".to_string()),
-        ];
-        if let Some(token) = &session_token {
-            query.push(("X-Amz-Security-Token".to_string(), token.clone()));
-        }
-
-        // Encode keys and values, then sort by encoded key and encoded value
-        let enc = |s: &str| utf8_percent_encode(s, AWS_QUERY_ENCODE_SET).to_string();
-        let mut encoded: Vec<(String, String)> =
-            query.iter().map(|(k, v)| (enc(k), enc(v))).collect();
-        encoded.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
-        let canonical_query = encoded
-            .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<_>>()
-            .join("&");
-
-        let canonical_headers = format!("host:{}\n", host);
-        let signed_headers = "host";
-        let payload_hash = {
-            let mut hasher = Sha256::new();
-            hasher.update(b""); // Empty body for GET
-            hex::encode(hasher.finalize())
-        };
-        let canonical_request = format!(
-            "GET\n/\n{}\n{}\n{}\n{}",
-            canonical_query, canonical_headers, signed_headers, payload_hash
-        );
-
-        // String to sign
-        let scope = format!("{}/{}/{}/aws4_request", date_stamp, region, service);
-        let mut hasher = Sha256::new();
-        hasher.update(canonical_request.as_bytes());
-        let canonical_request_hash = hex::encode(hasher.finalize());
-        let string_to_sign = format!(
-            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
-            amz_date, scope, canonical_request_hash
-        );
+        presign_get_caller_identity(
+            &credentials,
+            &self.region,
+            &self.sts_endpoint,
+            nonce,
+            SystemTime::now(),
+        )
+    }
 
-        // Derive signing key
-        type HmacSha256 = Hmac<Sha256>;
-        let k_date = {
-            let mut mac = HmacSha256::new_from_slice(format!("AWS4{}", secret_key).as_bytes())?;
-            mac.update(date_stamp.as_bytes());
-            mac.finalize().into_bytes()
-        };
-        let k_region = {
-            let mut mac = HmacSha256::new_from_slice(&k_date)?;
-            mac.update(region.as_bytes());
-            mac.finalize().into_bytes()
-        };
-        let k_service = {
-            let mut mac = HmacSha256::new_from_slice(&k_region)?;
-            mac.update(service.as_bytes());
-            mac.finalize().into_bytes()
-        };
-        let k_signing = {
-            let mut mac = HmacSha256::new_from_slice(&k_service)?;
-            mac.update(b"aws4_request");
-            mac.finalize().into_bytes()
-        };
+    /// Build the auth handshake message: a mesh token or JWT if one is
+    /// configured, nothing when the client certificate already authenticated
+    /// the connection, otherwise IAM signing `nonce`
+    async fn build_auth_message(&self, nonce: Option<&str>) -> Result<Option<IngressMessage>> {
+        let auth_message = match (&self.auth_token, &self.jwt_file) {
+            (Some(token), _) => {
+                info!("Authenticating with mesh token");
+                Some(IngressMessage::TokenAuth(
+                    crate::common::auth::TokenAuthRequest {
+                        token: token.clone(),
+                    },
+                ))
+            }
+            (None, Some(jwt_file)) => {
+                // Re-read on every handshake so rotated tokens are picked up
+                let token = tokio::fs::read_to_string(jwt_file).await.map_err(|e| {
+                    anyhow::anyhow!("Failed to read JWT from {}: {}", jwt_file.display(), e)
+                })?;
+                info!("Authenticating with JWT from {}", jwt_file.display());
+                Some(IngressMessage::JwtAuth(
+                    crate::common::auth::JwtAuthRequest {
+                        token: token.trim().to_string(),
+                    },
+                ))
+            }
+            (None, None) if self.client_cert_auth => {
+                info!("Authenticating with client certificate");
+                None
+            }
+            (None, None) => {
+                let nonce = nonce.ok_or_else(|| anyhow::anyhow!("IAM auth needs a challenge"))?;
+                let url = match self.build_presigned_sts_url(nonce).await {
+                    Ok(url) => {
+                        info!("Generated presigned STS URL for IAM authentication");
+                        Some(url)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to generate presigned STS URL: {}. IAM authentication will be skipped.",
+                            e
+                        );
+                        None
+                    }
+                };
 
-        let signature = {
-            let mut mac = HmacSha256::new_from_slice(&k_signing)?;
-            mac.update(string_to_sign.as_bytes());
-            hex::encode(mac.finalize().into_bytes())
+                Some(IngressMessage::IamAuth(
+                    crate::common::auth::IamAuthRequest {
+                        presigned_url: url,
+                        region: self.region.clone(),
+                        arn: None,
+                        account_id: None,
+                        user_id: None,
+                    },
+                ))
+            }
         };
 
-        let final_url = format!(
-            "https://{}?{}&X-Amz-Signature={}",
-            host, canonical_query, signature
-        );
+        Ok(auth_message)
+    }
 
-        Ok(final_url)
+    /// Wait for the nonce the ingress issues when the connection opens
+    async fn wait_for_auth_challenge<S>(ws_receiver: &mut S) -> Result<String>
+    where
+        S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+            + Unpin,
+    {
+        loop {
+            match ws_receiver.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(IngressMessage::AuthChallenge { nonce }) =
+                        serde_json::from_str::<IngressMessage>(&text)
+                    {
+                        return Ok(nonce);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err(anyhow::anyhow!("connection closed before auth challenge"));
+                }
+                Some(Err(e)) => return Err(e.into()),
+                Some(_) => {}
+            }
+        }
     }
+
     fn websocket_to_http_health_url(ingress_endpoint: &str) -> Option<String> {
         // Convert ws[s]://host[:port][/path] to http[s]://host[:port]/health
         let url = if ingress_endpoint.starts_with("wss://") {
@@ -209,8 +189,11 @@ impl EcsAnywhereClient {
         port: u16,
         local_endpoint: String,
         health_check_path: String,
+        region: Option<String>,
     ) -> Result<Self> {
-        let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+        let region_provider = RegionProviderChain::first_try(region.map(aws_config::Region::new))
+            .or_default_provider()
+            .or_else("us-east-1");
         let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
             .region(region_provider)
             .load()
@@ -226,6 +209,12 @@ impl EcsAnywhereClient {
         let proxy_handler = ProxyHandler::new(http_client.clone(), local_endpoint.clone());
         let ws_proxy = WebSocketReverseProxy::new(local_endpoint.clone());
 
+        let region = config
+            .region()
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let sts_endpoint = default_sts_endpoint(&region);
+
         Ok(Self {
             client_id: Uuid::new_v4(),
             cluster_name,
@@ -233,7 +222,16 @@ impl EcsAnywhereClient {
             host,
             port,
             health_check_path,
+            attributes: HashMap::new(),
+            paths: Vec::new(),
+            auth_token: None,
+            jwt_file: None,
+            tls_connector: None,
+            client_cert_auth: false,
             local_endpoint,
+            region,
+            sts_endpoint,
+            sdk_config: config,
             http_client,
             aws_service,
             proxy_handler,
@@ -241,6 +239,63 @@ impl EcsAnywhereClient {
         })
     }
 
+    /// Attach static attributes to advertise with the service registration
+    pub fn with_attributes(mut self, attributes: HashMap<String, String>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Only route these paths under the host to this service
+    pub fn with_paths(mut self, paths: Vec<PathRoute>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Authenticate with a mesh token instead of a presigned STS URL
+    pub fn with_auth_token(mut self, auth_token: Option<String>) -> Self {
+        self.auth_token = auth_token;
+        self
+    }
+
+    /// Authenticate with the workload identity JWT stored in `jwt_file`
+    pub fn with_jwt_file(mut self, jwt_file: Option<PathBuf>) -> Self {
+        self.jwt_file = jwt_file;
+        self
+    }
+
+    /// Presign STS URLs against `sts_endpoint` instead of the regional endpoint
+    pub fn with_sts_endpoint(mut self, sts_endpoint: Option<String>) -> Self {
+        if let Some(sts_endpoint) = sts_endpoint {
+            self.sts_endpoint = sts_endpoint;
+        }
+        self
+    }
+
+    /// Whether the connection authenticates with a presigned STS URL
+    pub fn uses_iam_auth(&self) -> bool {
+        self.auth_token.is_none() && self.jwt_file.is_none() && !self.client_cert_auth
+    }
+
+    /// Use custom TLS settings (CA bundle, client certificate) for the ingress connection
+    pub fn with_tls(mut self, config: Option<&ClientTlsConfig>) -> Result<Self> {
+        if let Some(config) = config {
+            let connector = build_connector(config)?;
+
+            // The ingress health monitor talks to the same TLS listener
+            let mut http = hyper::client::HttpConnector::new();
+            http.enforce_http(false);
+            let https = HttpsConnector::from((
+                http,
+                tokio_native_tls::TlsConnector::from(connector.clone()),
+            ));
+            self.http_client = Client::builder().build::<_, Body>(https);
+
+            self.tls_connector = Some(connector);
+            self.client_cert_auth = config.has_client_cert();
+        }
+        Ok(self)
+    }
+
     pub async fn get_service_attributes(&self) -> Result<HashMap<String, String>> {
         let mut attributes = HashMap::new();
 
@@ -251,6 +306,9 @@ impl EcsAnywhereClient {
             attributes.insert("task_arn".to_string(), task_arn.clone());
         }
 
+        // Configured attributes override the detected defaults
+        attributes.extend(self.attributes.clone());
+
         Ok(attributes)
     }
 
@@ -266,6 +324,7 @@ impl EcsAnywhereClient {
             task_arn: self.aws_service.task_arn.clone().unwrap_or_default(),
             attributes,
             health_check_path: Some(self.health_check_path.clone()),
+            paths: self.paths.clone(),
         }
     }
 
@@ -289,7 +348,18 @@ impl EcsAnywhereClient {
     }
 
     async fn connect_and_handle(&self, ingress_endpoint: &str) -> Result<()> {
-        let (ws_stream, _) = connect_async(ingress_endpoint).await?;
+        let (ws_stream, _) = match &self.tls_connector {
+            Some(connector) => {
+                connect_async_tls_with_config(
+                    ingress_endpoint,
+                    None,
+                    false,
+                    Some(Connector::NativeTls(connector.clone())),
+                )
+                .await?
+            }
+            None => connect_async(ingress_endpoint).await?,
+        };
         let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
         info!(
@@ -297,32 +367,19 @@ impl EcsAnywhereClient {
             self.client_id
         );
 
-        // Perform IAM authentication handshake first (identity payload)
-        let url = match self.build_presigned_sts_url().await {
-            Ok(url) => {
-                info!("Generated presigned STS URL for IAM authentication");
-                Some(url)
-            }
-            Err(e) => {
-                warn!(
-                    "Failed to generate presigned STS URL: {}. IAM authentication will be skipped.",
-                    e
-                );
-                None
-            }
-        };
-
-        let auth_request = crate::common::auth::IamAuthRequest {
-            presigned_url: url,
-            region: "us-east-1".to_string(),
-            arn: None,
-            account_id: None,
-            user_id: None,
+        // Perform the authentication handshake first; IAM signs the challenge
+        // the ingress sends when the connection opens
+        let nonce = if self.uses_iam_auth() {
+            Some(Self::wait_for_auth_challenge(&mut ws_receiver).await?)
+        } else {
+            None
         };
+        let auth_message = self.build_auth_message(nonce.as_deref()).await?;
 
-        let auth_message = IngressMessage::IamAuth(auth_request);
-        let auth_json = serde_json::to_string(&auth_message)?;
-        ws_sender.send(Message::Text(auth_json)).await?;
+        if let Some(auth_message) = auth_message {
+            let auth_json = serde_json::to_string(&auth_message)?;
+            ws_sender.send(Message::Text(auth_json)).await?;
+        }
 
         // Wait for auth response before registering
         loop {
@@ -334,10 +391,24 @@ impl EcsAnywhereClient {
                         if resp.success {
                             break;
                         } else {
-                            error!("IAM auth failed: {:?}", resp.error);
-                            return Err(anyhow::anyhow!("IAM auth failed"));
+                            error!("Auth failed: {:?}", resp.error);
+                            return Err(anyhow::anyhow!("auth failed"));
                         }
                     }
+                    // The ingress asks for IAM auth when it did not verify our
+                    // certificate, e.g. it has no server.tls.client_ca_file
+                    if self.client_cert_auth
+                        && matches!(
+                            serde_json::from_str::<IngressMessage>(&text),
+                            Ok(IngressMessage::AuthChallenge { .. })
+                        )
+                    {
+                        error!(
+                            "Ingress did not accept the client certificate; \
+                             it needs server.tls.client_ca_file set to the issuing CA"
+                        );
+                        return Err(anyhow::anyhow!("client certificate not accepted"));
+                    }
                     // Ignore non-auth messages until auth completes
                 }
                 Some(Ok(Message::Close(_))) => {
@@ -587,6 +658,24 @@ impl EcsAnywhereClient {
                     .handle_close_from_server(session_id, code, reason)
                     .await;
             }
+            IngressMessage::AuthChallenge { .. } => {
+                // Only IAM auth signs the challenge, during the handshake
+            }
+            IngressMessage::ReauthRequest { nonce } => {
+                info!("Ingress requested re-authentication");
+                if let Some(auth_message) = self.build_auth_message(Some(&nonce)).await? {
+                    let auth_json = serde_json::to_string(&auth_message)?;
+                    ws_sender.send(Message::Text(auth_json)).await?;
+                }
+            }
+            IngressMessage::IamAuthResponse(resp) => {
+                if resp.success {
+                    debug!("Re-authentication accepted");
+                } else {
+                    // The ingress drops the connection; the run loop reconnects
+                    error!("Re-authentication failed: {:?}", resp.error);
+                }
+            }
             IngressMessage::RegistrationAck {
                 success, message, ..
             } => {
@@ -608,6 +697,7 @@ impl EcsAnywhereClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::auth::MESH_AUTH_NONCE_HEADER;
     use aws_config::BehaviorVersion;
     use tokio::test;
 
@@ -652,6 +742,7 @@ mod tests {
         let aws_service = AwsService::new(ecs_client, sts_client).await.unwrap();
         let proxy_handler =
             ProxyHandler::new(http_client.clone(), "http://localhost:3000".to_string());
+        let region = aws_cfg.region().unwrap().to_string();
 
         let client = EcsAnywhereClient {
             client_id: Uuid::new_v4(),
@@ -660,7 +751,16 @@ mod tests {
             host: "test.local".to_string(),
             port: 3000,
             health_check_path: "/health".to_string(),
+            attributes: HashMap::new(),
+            paths: Vec::new(),
+            auth_token: None,
+            jwt_file: None,
+            tls_connector: None,
+            client_cert_auth: false,
             local_endpoint: "http://localhost:3000".to_string(),
+            region: region.clone(),
+            sts_endpoint: default_sts_endpoint(&region),
+            sdk_config: aws_cfg.clone(),
             http_client: http_client.clone(),
             aws_service,
             proxy_handler,
@@ -668,7 +768,8 @@ mod tests {
         };
 
         // Test presigned URL generation
-        let presigned_url = client.build_presigned_sts_url().await.unwrap();
+        let nonce = "test-nonce";
+        let presigned_url = client.build_presigned_sts_url(nonce).await.unwrap();
         println!("Generated presigned URL: {}", presigned_url);
 
         // Validate URL structure
@@ -681,11 +782,14 @@ mod tests {
         assert!(presigned_url.contains("X-Amz-Date="));
         assert!(presigned_url.contains("X-Amz-Expires=60"));
         assert!(presigned_url.contains("X-Amz-Signature="));
-        assert!(presigned_url.contains("X-Amz-SignedHeaders=host"));
-
-        // Test actual STS call using the presigned URL
-        let uri: hyper::Uri = presigned_url.parse().unwrap();
-        let response = http_client.get(uri).await.unwrap();
+        assert!(presigned_url.contains("X-Amz-SignedHeaders=host%3Bx-mesh-auth-nonce"));
+
+        // Test actual STS call using the presigned URL and the signed challenge
+        let request = hyper::Request::get(presigned_url.as_str())
+            .header(MESH_AUTH_NONCE_HEADER, nonce)
+            .body(Body::empty())
+            .unwrap();
+        let response = http_client.request(request).await.unwrap();
         let status = response.status();
         let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
         let body_str = String::from_utf8_lossy(&body);
@@ -785,6 +889,7 @@ mod tests {
         let aws_service = AwsService::new(ecs_client, sts_client_2).await.unwrap();
         let proxy_handler =
             ProxyHandler::new(http_client.clone(), "http://localhost:3000".to_string());
+        let region = aws_cfg.region().unwrap().to_string();
 
         let client = EcsAnywhereClient {
             client_id: Uuid::new_v4(),
@@ -793,14 +898,23 @@ mod tests {
             host: "test.local".to_string(),
             port: 3000,
             health_check_path: "/health".to_string(),
+            attributes: HashMap::new(),
+            paths: Vec::new(),
+            auth_token: None,
+            jwt_file: None,
+            tls_connector: None,
+            client_cert_auth: false,
             local_endpoint: "http://localhost:3000".to_string(),
+            region: region.clone(),
+            sts_endpoint: default_sts_endpoint(&region),
+            sdk_config: aws_cfg.clone(),
             http_client: http_client.clone(),
             aws_service,
             proxy_handler,
             ws_proxy: WebSocketReverseProxy::new("http://localhost:3000".to_string()),
         };
 
-        let presigned_url = client.build_presigned_sts_url().await.unwrap();
+        let presigned_url = client.build_presigned_sts_url("test-nonce").await.unwrap();
         println!("📋 Generated presigned URL structure looks correct:");
         println!("  ✓ URL: {}", presigned_url);
 
@@ -4,16 +4,27 @@ use tokio::time::{interval, Duration};
 use tracing::{error, info, warn};
 
 use crate::commands::ClientCommand;
+use crate::common::config::{ClientAuthOptions, ClientConfig};
+use crate::common::{PathMatchType, PathRoute};
 use client_impl::EcsAnywhereClient;
+use proxy::ProxyHandler;
 
 mod aws;
 mod client_impl;
 mod proxy;
+mod tls;
 
 pub async fn run(args: ClientCommand) -> Result<()> {
     println!("Starting  Mesh Client...");
 
     info!("🚀 Starting Anywhere Mesh Client");
+
+    if let Some(path) = &args.config {
+        info!("📄 Loading configuration from {}", path.display());
+        let config = ClientConfig::from_file(path)?;
+        return run_from_config(config, args.auth_options()).await;
+    }
+
     info!("📡 Ingress endpoint: {}", args.ingress_endpoint);
 
     // Check for IAM validation skip
@@ -37,11 +48,25 @@ pub async fn run(args: ClientCommand) -> Result<()> {
         args.port,
         args.local_endpoint.clone(),
         args.health_check_path.clone(),
+        args.aws_region.clone(),
     )
-    .await?;
+    .await?
+    .with_sts_endpoint(args.sts_endpoint.clone())
+    .with_paths(
+        args.path_prefixes
+            .iter()
+            .map(|prefix| PathRoute {
+                match_type: PathMatchType::Prefix,
+                path: prefix.clone(),
+            })
+            .collect(),
+    )
+    .with_auth_token(args.token.clone())
+    .with_jwt_file(args.jwt_file.clone())
+    .with_tls(args.tls_config().as_ref())?;
 
-    // Validate ECS cluster access (unless skipped)
-    if !args.skip_iam_validation {
+    // Validate ECS cluster access (unless skipped or not authenticating with IAM)
+    if !args.skip_iam_validation && client.uses_iam_auth() {
         println!("Validating ECS cluster access...");
         if let Err(e) = client
             .aws_service
@@ -55,27 +80,7 @@ pub async fn run(args: ClientCommand) -> Result<()> {
     }
 
     // Start health check monitoring
-    let health_client = client.proxy_handler.clone();
-    let health_path = args.health_check_path.clone();
-    tokio::spawn(async move {
-        let mut health_interval = interval(Duration::from_secs(30));
-
-        loop {
-            health_interval.tick().await;
-
-            match health_client.health_check(&health_path).await {
-                Ok(true) => {
-                    // Service is healthy
-                }
-                Ok(false) => {
-                    warn!("⚠️  Local service health check failed");
-                }
-                Err(e) => {
-                    error!("❌ Health check error: {}", e);
-                }
-            }
-        }
-    });
+    spawn_health_monitor(client.proxy_handler.clone(), args.health_check_path.clone());
 
     println!("✅ Client initialized successfully!");
 
@@ -96,3 +101,143 @@ pub async fn run(args: ClientCommand) -> Result<()> {
 
     Ok(())
 }
+
+/// Run one client connection per service declared in a `ClientConfig`
+async fn run_from_config(config: ClientConfig, options: ClientAuthOptions) -> Result<()> {
+    if config.services.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Client configuration does not declare any services"
+        ));
+    }
+
+    let skip_iam_validation = options.skip_iam_validation || config.aws.skip_iam_validation;
+    if skip_iam_validation || env::var("SKIP_IAM_VALIDATION").is_ok() {
+        warn!("⚠️  IAM validation is disabled - this should only be used in development!");
+    }
+
+    let auth_token = options
+        .auth_token
+        .or_else(|| config.connection.auth_token.clone());
+    let jwt_file = options
+        .jwt_file
+        .or_else(|| config.connection.jwt_file.clone());
+    let tls = options.tls.or_else(|| config.connection.tls.clone());
+    let aws_region = options.aws_region.or_else(|| config.aws.region.clone());
+    let sts_endpoint = options
+        .sts_endpoint
+        .or_else(|| config.aws.sts_endpoint.clone());
+    let ingress_endpoint = config.connection.ingress_endpoint.clone();
+    let cluster_name = config.cluster.cluster_name.clone();
+    info!("📡 Ingress endpoint: {}", ingress_endpoint);
+    info!("🏢 Cluster: {}", cluster_name);
+
+    let mut clients = Vec::with_capacity(config.services.len());
+    for service in &config.services {
+        let local_endpoint = config.local_endpoint_for(service);
+        let port = config.port_for(service);
+
+        info!(
+            "📦 Service {}: host={} port={} local={}",
+            service.service_name, service.host, port, local_endpoint
+        );
+
+        let client = EcsAnywhereClient::new(
+            cluster_name.clone(),
+            service.service_name.clone(),
+            service.host.clone(),
+            port,
+            local_endpoint,
+            service.health_check_path.clone(),
+            aws_region.clone(),
+        )
+        .await?
+        .with_sts_endpoint(sts_endpoint.clone())
+        .with_attributes(service.attributes.clone())
+        .with_paths(service.paths.clone())
+        .with_auth_token(auth_token.clone())
+        .with_jwt_file(jwt_file.clone())
+        .with_tls(tls.as_ref())?;
+
+        clients.push(client);
+    }
+
+    // Validate ECS cluster access once for the shared cluster (unless skipped or
+    // not authenticating with IAM)
+    if !skip_iam_validation && clients[0].uses_iam_auth() {
+        println!("Validating ECS cluster access...");
+        if let Err(e) = clients[0]
+            .aws_service
+            .check_ecs_cluster_access(&cluster_name)
+            .await
+        {
+            println!("❌ Failed to validate ECS cluster access");
+            error!("Failed to validate ECS cluster access: {}", e);
+            return Err(e);
+        }
+    }
+
+    println!("✅ Client initialized successfully!");
+
+    println!();
+    println!("🎯 Client Configuration:");
+    println!("   🏢 Cluster:         {}", cluster_name);
+    println!("   📡 Ingress:         {}", ingress_endpoint);
+    for client in &clients {
+        println!(
+            "   📦 {:<16} {} -> {}",
+            client.service_name, client.host, client.local_endpoint
+        );
+    }
+    println!();
+    println!("🔗 Connecting {} services to ingress...", clients.len());
+    println!();
+
+    // Each service keeps its own connection, registration and reconnect loop
+    let handles: Vec<_> = clients
+        .into_iter()
+        .map(|client| {
+            spawn_health_monitor(
+                client.proxy_handler.clone(),
+                client.health_check_path.clone(),
+            );
+
+            let ingress_endpoint = ingress_endpoint.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client.run(&ingress_endpoint).await {
+                    error!("Client for service {} exited: {}", client.service_name, e);
+                }
+            })
+        })
+        .collect();
+
+    for handle in futures_util::future::join_all(handles).await {
+        if let Err(e) = handle {
+            error!("Client task failed: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Periodically check the local service health and log failures
+fn spawn_health_monitor(health_client: ProxyHandler, health_path: String) {
+    tokio::spawn(async move {
+        let mut health_interval = interval(Duration::from_secs(30));
+
+        loop {
+            health_interval.tick().await;
+
+            match health_client.health_check(&health_path).await {
+                Ok(true) => {
+                    // Service is healthy
+                }
+                Ok(false) => {
+                    warn!("⚠️  Local service health check failed: {}", health_path);
+                }
+                Err(e) => {
+                    error!("❌ Health check error: {}", e);
+                }
+            }
+        }
+    });
+}
@@ -1,26 +1,84 @@
 pub mod ws;
-use crate::common::{ProxyRequest, ProxyResponse};
+use crate::common::{ProxyRequest, ProxyResponse, MESH_TIMEOUT_HEADER};
 use anyhow::Result;
+use hyper::client::HttpConnector;
 use hyper::{Body, Client, Method, Request, Uri};
 use hyper_tls::HttpsConnector;
 
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
 use tracing::{debug, error, info, instrument, warn};
 
+type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
 #[derive(Clone)]
 pub struct ProxyHandler {
-    http_client: Client<HttpsConnector<hyper::client::HttpConnector>>,
+    http_client: HttpsClient,
     local_endpoint: String,
+    // Clients with a connect timeout, keyed by the timeout in milliseconds
+    connect_timeout_clients: Arc<Mutex<HashMap<u64, HttpsClient>>>,
 }
 
 impl ProxyHandler {
-    pub fn new(
-        http_client: Client<HttpsConnector<hyper::client::HttpConnector>>,
-        local_endpoint: String,
-    ) -> Self {
+    pub fn new(http_client: HttpsClient, local_endpoint: String) -> Self {
         Self {
             http_client,
             local_endpoint,
+            connect_timeout_clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Get (or build) an HTTP client whose connector enforces a connect timeout
+    fn client_with_connect_timeout(&self, connect_ms: u64) -> HttpsClient {
+        let mut clients = self
+            .connect_timeout_clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        clients
+            .entry(connect_ms)
+            .or_insert_with(|| {
+                let mut http = HttpConnector::new();
+                http.enforce_http(false);
+                http.set_connect_timeout(Some(Duration::from_millis(connect_ms)));
+                Client::builder().build::<_, Body>(HttpsConnector::new_with_connector(http))
+            })
+            .clone()
+    }
+
+    /// Check whether a hyper error was caused by the connector timing out
+    fn is_connect_timeout(error: &hyper::Error) -> bool {
+        if !error.is_connect() {
+            return false;
+        }
+
+        let mut source = std::error::Error::source(error);
+        while let Some(err) = source {
+            if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                if io_err.kind() == std::io::ErrorKind::TimedOut {
+                    return true;
+                }
+            }
+            source = err.source();
+        }
+        false
+    }
+
+    /// Create a 504 response naming the timeout that fired
+    fn timeout_response(request_id: uuid::Uuid, kind: &str, limit_ms: u64) -> ProxyResponse {
+        ProxyResponse {
+            id: request_id,
+            status_code: 504,
+            headers: vec![(MESH_TIMEOUT_HEADER.to_string(), kind.to_string())],
+            body: Some(
+                format!(
+                    "Gateway Timeout: {} timeout of {}ms exceeded",
+                    kind, limit_ms
+                )
+                .into_bytes(),
+            ),
         }
     }
 
@@ -75,8 +133,49 @@ impl ProxyHandler {
 
         info!("Forwarding {} request to {}", proxy_request.method, url);
 
-        // Send the request
-        let response = self.http_client.request(request).await?;
+        // Send the request, honoring the connect and first-byte timeouts from the ingress
+        let timeouts = proxy_request.timeouts.unwrap_or_default();
+        let client = match timeouts.connect_ms {
+            Some(connect_ms) => self.client_with_connect_timeout(connect_ms),
+            None => self.http_client.clone(),
+        };
+
+        let send = client.request(request);
+        let result = match timeouts.first_byte_ms {
+            Some(first_byte_ms) => {
+                match timeout(Duration::from_millis(first_byte_ms), send).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        warn!(
+                            "First-byte timeout ({}ms) for request {}",
+                            first_byte_ms, proxy_request.id
+                        );
+                        return Ok(Self::timeout_response(
+                            proxy_request.id,
+                            "first-byte",
+                            first_byte_ms,
+                        ));
+                    }
+                }
+            }
+            None => send.await,
+        };
+
+        let response = match (result, timeouts.connect_ms) {
+            (Ok(response), _) => response,
+            (Err(e), Some(connect_ms)) if Self::is_connect_timeout(&e) => {
+                warn!(
+                    "Connect timeout ({}ms) for request {}",
+                    connect_ms, proxy_request.id
+                );
+                return Ok(Self::timeout_response(
+                    proxy_request.id,
+                    "connect",
+                    connect_ms,
+                ));
+            }
+            (Err(e), _) => return Err(e.into()),
+        };
 
         // Extract response data
         let status_code = response.status().as_u16();
@@ -0,0 +1,42 @@
+use anyhow::{Context, Result};
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::fs;
+
+use crate::common::config::ClientTlsConfig;
+
+/// Build the TLS connector used for `wss://` ingress endpoints
+pub fn build_connector(config: &ClientTlsConfig) -> Result<TlsConnector> {
+    let mut builder = TlsConnector::builder();
+
+    if let Some(ca_file) = &config.ca_file {
+        let pem = fs::read(ca_file)
+            .with_context(|| format!("Failed to read CA bundle {}", ca_file.display()))?;
+        // Bundles carry intermediates, roots or old and new CAs during a rotation
+        let certificates = rustls_pemfile::certs(&mut pem.as_slice())
+            .with_context(|| format!("Failed to parse CA bundle {}", ca_file.display()))?;
+        if certificates.is_empty() {
+            anyhow::bail!("No certificates found in {}", ca_file.display());
+        }
+        for der in certificates {
+            let certificate = Certificate::from_der(&der)
+                .with_context(|| format!("Invalid CA certificate in {}", ca_file.display()))?;
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let cert = fs::read(cert_file)
+                .with_context(|| format!("Failed to read {}", cert_file.display()))?;
+            let key = fs::read(key_file)
+                .with_context(|| format!("Failed to read {}", key_file.display()))?;
+            let identity = Identity::from_pkcs8(&cert, &key)
+                .context("Invalid client certificate or PKCS#8 key")?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => anyhow::bail!("Client TLS needs both a certificate and a key"),
+    }
+
+    Ok(builder.build()?)
+}
@@ -1,22 +1,38 @@
-use clap::Parser;
+use crate::common::config::{ClientAuthOptions, ClientTlsConfig};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
+/// Flags left unset fall back to the `--config` file, then to built-in defaults.
 #[derive(Parser, Debug, Clone)]
 pub struct ServerCommand {
-    /// Port to listen on for HTTP requests from ALB
-    #[arg(short, long, default_value = "8080")]
-    pub alb_port: u16,
+    /// Path to an ingress configuration file (YAML)
+    #[arg(short, long, env = "MESH_CONFIG")]
+    pub config: Option<PathBuf>,
 
-    /// Port to listen on for WebSocket connections
-    #[arg(short, long, default_value = "8082")]
-    pub websocket_port: u16,
+    /// Port to listen on for HTTP requests from ALB [default: 8080]
+    #[arg(short, long)]
+    pub alb_port: Option<u16>,
 
-    /// Request timeout in seconds
-    #[arg(long, default_value = "30")]
-    pub request_timeout: u64,
+    /// Port to listen on for WebSocket connections [default: 8082]
+    #[arg(short, long)]
+    pub websocket_port: Option<u16>,
+
+    /// Port to listen on for health checks and metrics [default: 8081]
+    #[arg(long)]
+    pub health_port: Option<u16>,
+
+    /// Request timeout in seconds [default: 30]
+    #[arg(long)]
+    pub request_timeout: Option<u64>,
 }
 
 #[derive(Parser, Debug, Clone)]
 pub struct ClientCommand {
+    /// Path to a client configuration file (YAML) declaring one or more services.
+    /// When set, the single-service flags below are ignored.
+    #[arg(long, env = "MESH_CONFIG")]
+    pub config: Option<PathBuf>,
+
     /// Ingress service WebSocket endpoint
     #[arg(
         short,
@@ -43,6 +59,10 @@ pub struct ClientCommand {
     #[arg(short, long, env = "PORT", default_value = "3000")]
     pub port: u16,
 
+    /// Only route paths under this prefix of the host to the service (repeatable)
+    #[arg(long = "path-prefix")]
+    pub path_prefixes: Vec<String>,
+
     /// Service name
     #[arg(long, env = "SERVICE_NAME", default_value = "my-service")]
     pub service_name: String,
@@ -58,4 +78,97 @@ pub struct ClientCommand {
     /// Skip IAM validation (for development)
     #[arg(long, env = "SKIP_IAM_VALIDATION")]
     pub skip_iam_validation: bool,
+
+    /// Mesh token (from `mesh token issue`) to authenticate with instead of IAM.
+    /// Needs no AWS credentials and skips the ECS cluster access check.
+    #[arg(long, env = "MESH_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// File holding a workload identity JWT (e.g. a projected SPIFFE or OIDC
+    /// token) to authenticate with instead of IAM. Re-read on every connect so
+    /// rotated tokens are picked up.
+    #[arg(long, env = "MESH_JWT_FILE")]
+    pub jwt_file: Option<PathBuf>,
+
+    /// AWS region to sign presigned STS URLs for. Detected from the
+    /// environment (`AWS_REGION`, profile, instance metadata) when unset.
+    #[arg(long)]
+    pub aws_region: Option<String>,
+
+    /// STS endpoint to presign against instead of the regional one
+    /// (e.g. a FIPS endpoint or a local mock); unless it is an official https
+    /// STS host, the ingress must list it in `auth.trusted_sts_endpoints`
+    #[arg(long, env = "MESH_STS_ENDPOINT")]
+    pub sts_endpoint: Option<String>,
+
+    /// PEM client certificate for `wss://` ingress endpoints that require one.
+    /// Authenticates the connection instead of IAM.
+    #[arg(long, env = "MESH_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for `--tls-cert`
+    #[arg(long, env = "MESH_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle used to verify the ingress certificate
+    #[arg(long, env = "MESH_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+}
+
+impl ClientCommand {
+    /// TLS settings from the `--tls-*` flags, if any were given
+    pub fn tls_config(&self) -> Option<ClientTlsConfig> {
+        if self.tls_cert.is_none() && self.tls_key.is_none() && self.tls_ca.is_none() {
+            return None;
+        }
+
+        Some(ClientTlsConfig {
+            cert_file: self.tls_cert.clone(),
+            key_file: self.tls_key.clone(),
+            ca_file: self.tls_ca.clone(),
+        })
+    }
+
+    /// Credential and TLS overrides from the command line
+    pub fn auth_options(&self) -> ClientAuthOptions {
+        ClientAuthOptions {
+            skip_iam_validation: self.skip_iam_validation,
+            auth_token: self.token.clone(),
+            jwt_file: self.jwt_file.clone(),
+            aws_region: self.aws_region.clone(),
+            sts_endpoint: self.sts_endpoint.clone(),
+            tls: self.tls_config(),
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct TokenCommand {
+    #[command(subcommand)]
+    pub command: TokenSubcommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TokenSubcommand {
+    /// Issue a signed token for a client without AWS credentials
+    Issue(TokenIssueCommand),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct TokenIssueCommand {
+    /// Secret shared with the ingress (`auth.token_secret`)
+    #[arg(long, env = "MESH_TOKEN_SECRET", hide_env_values = true)]
+    pub secret: String,
+
+    /// Who the token is issued to; shows up as `mesh:token/<subject>` on the ingress
+    #[arg(long)]
+    pub subject: String,
+
+    /// Host pattern the token may register (repeatable; exact, `*`/`**`/`{name}` labels or `~regex`)
+    #[arg(long = "host", required = true)]
+    pub hosts: Vec<String>,
+
+    /// Token lifetime in seconds
+    #[arg(long, default_value = "86400")]
+    pub ttl_secs: u64,
 }
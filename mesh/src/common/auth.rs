@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+/// Header carrying the server's auth challenge, signed into presigned STS URLs
+///
+/// The ingress sends the header with the nonce it issued to the connection when
+/// fetching the URL, so STS only accepts the signature on that connection.
+pub const MESH_AUTH_NONCE_HEADER: &str = "x-mesh-auth-nonce";
+
 /// IAM authentication request from client
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IamAuthRequest {
@@ -15,6 +21,18 @@ pub struct IamAuthRequest {
     pub user_id: Option<String>,
 }
 
+/// Authentication with a mesh-issued HMAC token (see `common::token`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenAuthRequest {
+    pub token: String,
+}
+
+/// Authentication with a workload identity JWT (SPIFFE, CI OIDC, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtAuthRequest {
+    pub token: String,
+}
+
 /// IAM authentication response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IamAuthResponse {
@@ -40,6 +58,29 @@ pub struct IamIdentity {
     /// User ID or role session name
     pub user_id: String,
 
-    /// Principal type (User, AssumedRole, etc.)
+    /// Principal type (User, AssumedRole, FederatedUser, Root, etc.)
     pub principal_type: String,
+
+    /// IAM role ARN behind an assumed-role session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+
+    /// Role session name, or the federated user name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_name: Option<String>,
+
+    /// When the presented credential expires, seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+
+    /// Host patterns this identity is scoped to; `None` means unrestricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+impl IamIdentity {
+    /// ARNs allow-lists are matched against: the caller ARN, then the role ARN
+    pub fn principal_arns(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.arn.as_str()).chain(self.role_arn.as_deref())
+    }
 }
@@ -1,13 +1,26 @@
+use super::routing::best_host_matches;
+use super::{PathRoute, ProxyTimeouts};
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 /// Configuration for the ingress service
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Every section is optional in YAML; missing sections and fields fall back to
+/// the defaults below. See `IngressConfig::apply_env_overrides` for how
+/// environment variables are layered on top of a loaded file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 #[allow(dead_code)]
 pub struct IngressConfig {
     /// Server configuration
     pub server: ServerConfig,
 
+    /// Client authentication configuration
+    pub auth: AuthConfig,
+
     /// ECS cluster-based authentication configuration
     pub ecs: EcsConfig,
 
@@ -18,6 +31,34 @@ pub struct IngressConfig {
     pub logging: LoggingConfig,
 }
 
+impl IngressConfig {
+    /// Load an ingress configuration from a YAML file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Apply the legacy environment variables on top of the file configuration
+    ///
+    /// `SKIP_IAM_VALIDATION` and `ALLOWED_ROLE_ARNS` take precedence over the
+    /// `auth` section when they are set.
+    pub fn apply_env_overrides(&mut self) {
+        if let Ok(value) = std::env::var("SKIP_IAM_VALIDATION") {
+            self.auth.skip_validation = value.to_lowercase() == "true";
+        }
+
+        if let Ok(value) = std::env::var("ALLOWED_ROLE_ARNS") {
+            self.auth.allowed_role_arns = value.split(',').map(|p| p.trim().to_string()).collect();
+        }
+
+        if let Ok(value) = std::env::var("MESH_TOKEN_SECRET") {
+            self.auth.token_secret = Some(value);
+        }
+    }
+}
+
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
@@ -41,6 +82,214 @@ pub struct ServerConfig {
     /// Maximum number of concurrent connections
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
+
+    /// Terminate TLS on the WebSocket port (for deployments without an ALB)
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            alb_port: default_alb_port(),
+            health_port: default_health_port(),
+            websocket_port: default_websocket_port(),
+            request_timeout: default_request_timeout(),
+            max_connections: default_max_connections(),
+            tls: None,
+        }
+    }
+}
+
+/// TLS settings for the WebSocket (control) listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain presented to clients
+    pub cert_file: PathBuf,
+
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1) for `cert_file`
+    pub key_file: PathBuf,
+
+    /// PEM bundle of CAs trusted to sign client certificates. When set, a
+    /// verified client certificate authenticates the connection.
+    #[serde(default)]
+    pub client_ca_file: Option<PathBuf>,
+
+    /// Refuse the TLS handshake unless the client presents a certificate
+    /// signed by `client_ca_file`
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+/// Client authentication configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct AuthConfig {
+    /// IAM ARN patterns allowed to connect (supports * wildcards)
+    #[serde(default = "default_allowed_role_arns")]
+    pub allowed_role_arns: Vec<String>,
+
+    /// Whether to skip IAM validation (for development)
+    #[serde(default)]
+    pub skip_validation: bool,
+
+    /// Seconds a new connection has to authenticate before it is closed; also
+    /// bounds the TLS handshake when the ingress terminates TLS
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout_secs: u64,
+
+    /// Seconds between re-authentication demands on established connections;
+    /// 0 disables them. Connections are also re-authenticated when their
+    /// credential expires and when the allow-list is reloaded (SIGHUP).
+    #[serde(default = "default_reauth_interval")]
+    pub reauth_interval_secs: u64,
+
+    /// Largest `X-Amz-Expires` accepted on presigned STS URLs, in seconds
+    #[serde(default = "default_presigned_url_max_expires")]
+    pub presigned_url_max_expires_secs: u64,
+
+    /// Clock skew tolerated when checking presigned URL freshness, in seconds
+    #[serde(default = "default_presigned_url_clock_skew")]
+    pub presigned_url_clock_skew_secs: u64,
+
+    /// Seconds IAM temporary credentials are trusted after they signed the
+    /// presigned URL (`X-Amz-Date`), when it carries `X-Amz-Security-Token`;
+    /// the connection has to re-authenticate with fresh credentials by then.
+    /// The URL does not say when the session really expires, so this is an
+    /// assumed lifetime
+    #[serde(default = "default_iam_session_ttl")]
+    pub iam_session_ttl_secs: u64,
+
+    /// STS endpoints (`scheme://host[:port]`) presigned URLs may target
+    /// besides the official https hosts, matching clients' `sts_endpoint`
+    #[serde(default)]
+    pub trusted_sts_endpoints: Vec<String>,
+
+    /// Secret used to verify mesh tokens (`mesh token issue`), at least 32
+    /// bytes; token auth is disabled when unset. Also read from
+    /// `MESH_TOKEN_SECRET`.
+    #[serde(default)]
+    pub token_secret: Option<String>,
+
+    /// Workload identity (JWT) authentication; disabled when unset
+    #[serde(default)]
+    pub jwt: Option<JwtAuthConfig>,
+
+    /// What each identity may register; empty allows any authenticated identity
+    /// to register anything
+    #[serde(default)]
+    pub registration_policy: Vec<RegistrationGrant>,
+}
+
+/// Verification settings for workload identity JWTs (SPIFFE JWT-SVIDs, CI OIDC tokens, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtAuthConfig {
+    /// Required `iss` claim
+    pub issuer: String,
+
+    /// Accepted `aud` values; the token must carry at least one
+    pub audience: Vec<String>,
+
+    /// JWKS document on disk (checked before `jwks_url`)
+    #[serde(default)]
+    pub jwks_file: Option<PathBuf>,
+
+    /// JWKS endpoint, e.g. `https://issuer.example.com/.well-known/jwks.json`
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+
+    /// Seconds a loaded JWKS is cached before it is reloaded
+    #[serde(default = "default_jwks_refresh")]
+    pub jwks_refresh_secs: u64,
+
+    /// Clock skew tolerated on `exp`/`nbf`, in seconds
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway_secs: u64,
+
+    /// Accepted signing algorithms; only asymmetric algorithms are allowed
+    #[serde(default = "default_jwt_algorithms")]
+    pub algorithms: Vec<String>,
+
+    /// Which claims populate the connection identity
+    #[serde(default)]
+    pub claims: JwtClaimMapping,
+}
+
+/// Maps JWT claims onto identity fields
+///
+/// The principal claim becomes the pseudo ARN `mesh:jwt/<value>`, which
+/// registration policies match like any other ARN.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtClaimMapping {
+    /// Claim naming the principal
+    #[serde(default = "default_jwt_principal_claim")]
+    pub principal: String,
+
+    /// Claim copied into `account_id`
+    #[serde(default)]
+    pub account_id: Option<String>,
+
+    /// Claim copied into `user_id` (defaults to the principal)
+    #[serde(default)]
+    pub user_id: Option<String>,
+
+    /// Claim listing the host patterns the holder may register (array or
+    /// space-separated string); unrestricted when unset
+    #[serde(default)]
+    pub allowed_hosts: Option<String>,
+}
+
+impl Default for JwtClaimMapping {
+    fn default() -> Self {
+        Self {
+            principal: default_jwt_principal_claim(),
+            account_id: None,
+            user_id: None,
+            allowed_hosts: None,
+        }
+    }
+}
+
+/// Grants identities matching `principal` the right to register services
+///
+/// Omitted lists allow any value. A registration is accepted when at least one
+/// grant matching the identity's ARN allows its host, service and cluster.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistrationGrant {
+    /// IAM ARN pattern (supports * wildcards)
+    pub principal: String,
+
+    /// Host patterns that may be registered (exact, `*`/`**`/`{name}` labels
+    /// or `~regex`); a pattern registration must fall entirely within one of
+    /// these
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    /// Service name patterns (supports * wildcards)
+    #[serde(default)]
+    pub services: Vec<String>,
+
+    /// Cluster name patterns (supports * wildcards)
+    #[serde(default)]
+    pub clusters: Vec<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            allowed_role_arns: default_allowed_role_arns(),
+            skip_validation: false,
+            handshake_timeout_secs: default_handshake_timeout(),
+            reauth_interval_secs: default_reauth_interval(),
+            presigned_url_max_expires_secs: default_presigned_url_max_expires(),
+            presigned_url_clock_skew_secs: default_presigned_url_clock_skew(),
+            iam_session_ttl_secs: default_iam_session_ttl(),
+            trusted_sts_endpoints: Vec::new(),
+            token_secret: None,
+            jwt: None,
+            registration_policy: Vec::new(),
+        }
+    }
 }
 
 /// ECS cluster-based authentication configuration
@@ -48,6 +297,7 @@ pub struct ServerConfig {
 #[allow(dead_code)]
 pub struct EcsConfig {
     /// List of allowed ECS cluster ARNs (supports wildcards)
+    #[serde(default)]
     pub allowed_clusters: Vec<String>,
 
     /// AWS region for ECS operations
@@ -63,9 +313,22 @@ pub struct EcsConfig {
     pub discovery_interval: u64,
 
     /// Required labels for service discovery
+    #[serde(default)]
     pub required_labels: Vec<String>,
 }
 
+impl Default for EcsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_clusters: Vec::new(),
+            region: default_aws_region(),
+            skip_validation: false,
+            discovery_interval: default_discovery_interval(),
+            required_labels: Vec::new(),
+        }
+    }
+}
+
 /// Routing configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
@@ -78,9 +341,480 @@ pub struct RoutingConfig {
     #[serde(default = "default_unhealthy_threshold")]
     pub unhealthy_threshold: u64,
 
-    /// Load balancing strategy
+    /// Default load balancing strategy across a host's healthy instances
     #[serde(default)]
     pub load_balancing: LoadBalancingStrategy,
+
+    /// Default connect/first-byte timeouts; the total defaults to `server.request_timeout`
+    #[serde(default)]
+    pub timeouts: ProxyTimeouts,
+
+    /// What to do when a registration claims a host already served by another cluster
+    #[serde(default)]
+    pub host_conflicts: HostConflictPolicy,
+
+    /// Default retry policy for requests whose instance fails
+    #[serde(default)]
+    pub retry: RequestRetryPolicy,
+
+    /// Cap on retries across all hosts
+    #[serde(default)]
+    pub retry_budget: RetryBudgetConfig,
+
+    /// Taking registrations that keep failing out of rotation
+    #[serde(default)]
+    pub outlier_detection: OutlierDetectionConfig,
+
+    /// Per-host overrides
+    #[serde(default)]
+    pub routes: Vec<RoutePolicy>,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            health_check_interval: default_health_check_interval(),
+            unhealthy_threshold: default_unhealthy_threshold(),
+            load_balancing: LoadBalancingStrategy::default(),
+            timeouts: ProxyTimeouts::default(),
+            host_conflicts: HostConflictPolicy::default(),
+            retry: RequestRetryPolicy::default(),
+            retry_budget: RetryBudgetConfig::default(),
+            outlier_detection: OutlierDetectionConfig::default(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl RoutingConfig {
+    /// Find the route policy for a host
+    ///
+    /// Routes follow the same precedence as registrations: an exact host wins,
+    /// then the most specific wildcard, then a regex.
+    pub fn route_for_host(&self, host: &str) -> Option<&RoutePolicy> {
+        best_host_matches(host, &self.routes, |r| r.host.as_str())
+            .first()
+            .copied()
+    }
+
+    /// Resolve the effective timeouts for a host
+    pub fn timeouts_for_host(&self, host: &str) -> ProxyTimeouts {
+        let route_timeouts = self
+            .route_for_host(host)
+            .map(|r| r.timeouts)
+            .unwrap_or_default();
+        route_timeouts.or(self.timeouts)
+    }
+
+    /// Routing rules configured for a host
+    pub fn rules_for_host(&self, host: &str) -> &[RoutingRule] {
+        self.route_for_host(host)
+            .map(|r| r.rules.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Session affinity configured for a host
+    pub fn affinity_for_host(&self, host: &str) -> Option<&SessionAffinityConfig> {
+        self.route_for_host(host).and_then(|r| r.affinity.as_ref())
+    }
+
+    /// Weighted split configured for a host
+    pub fn split_for_host(&self, host: &str) -> Option<&WeightedSplit> {
+        self.route_for_host(host).and_then(|r| r.split.as_ref())
+    }
+
+    /// Lowercased names of the request headers a host's rules and split read
+    pub fn routing_headers_for_host(&self, host: &str) -> Vec<String> {
+        let Some(route) = self.route_for_host(host) else {
+            return Vec::new();
+        };
+        let rule_headers = route
+            .rules
+            .iter()
+            .flat_map(|rule| rule.matches.headers.keys());
+        let split_header = route.split.iter().filter_map(|split| match &split.hash_on {
+            SplitHashKey::Header(name) => Some(name),
+            _ => None,
+        });
+        let mut names: Vec<_> = rule_headers
+            .chain(split_header)
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Resolve the effective load balancing strategy for a host
+    pub fn load_balancing_for_host(&self, host: &str) -> LoadBalancingStrategy {
+        self.route_for_host(host)
+            .and_then(|r| r.load_balancing)
+            .unwrap_or(self.load_balancing)
+    }
+
+    /// Resolve the effective retry policy for a host
+    pub fn retry_for_host(&self, host: &str) -> &RequestRetryPolicy {
+        self.route_for_host(host)
+            .and_then(|r| r.retry.as_ref())
+            .unwrap_or(&self.retry)
+    }
+}
+
+/// Routing settings read by everything that routes, replaced wholesale on reload
+///
+/// Clones share the same settings; a snapshot taken before a reload keeps the
+/// old settings until it is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRoutingConfig(Arc<RwLock<Arc<RoutingConfig>>>);
+
+impl SharedRoutingConfig {
+    pub fn new(routing_config: RoutingConfig) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(routing_config))))
+    }
+
+    /// Snapshot of the current settings
+    pub fn load(&self) -> Arc<RoutingConfig> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replace the settings for every holder
+    pub fn store(&self, routing_config: RoutingConfig) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(routing_config);
+    }
+}
+
+/// Per-host routing overrides
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct RoutePolicy {
+    /// Host pattern this policy applies to
+    pub host: String,
+
+    /// Timeout overrides for this host
+    #[serde(default)]
+    pub timeouts: ProxyTimeouts,
+
+    /// Load balancing strategy for this host
+    #[serde(default)]
+    pub load_balancing: Option<LoadBalancingStrategy>,
+
+    /// Require an end-user identity verified by the ALB's OIDC authentication
+    #[serde(default)]
+    pub alb_oidc: Option<AlbOidcConfig>,
+
+    /// Require an API key or basic-auth credentials before proxying
+    #[serde(default)]
+    pub access: Option<RouteAccessConfig>,
+
+    /// Rules sending matching requests to registrations with given attributes,
+    /// evaluated in order
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+
+    /// Weighted split between groups of the host's registrations
+    #[serde(default)]
+    pub split: Option<WeightedSplit>,
+
+    /// Keep each user on the registration that first served them
+    #[serde(default)]
+    pub affinity: Option<SessionAffinityConfig>,
+
+    /// Retry policy for this host
+    #[serde(default)]
+    pub retry: Option<RequestRetryPolicy>,
+}
+
+/// Retrying a request on another registration
+///
+/// GET, HEAD and OPTIONS requests, and those using one of `methods`, are sent
+/// to a different healthy registration when forwarding fails, the client's
+/// tunnel drops before it answers, or `per_try_timeout_ms` passes. The total
+/// timeout still bounds all attempts together.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestRetryPolicy {
+    /// Attempts per request, including the first; 1 disables retries
+    #[serde(default = "default_request_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Time one attempt may take before the next one starts
+    #[serde(default)]
+    pub per_try_timeout_ms: Option<u64>,
+
+    /// Further methods that are safe to retry, e.g. PUT or DELETE
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl Default for RequestRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_request_max_attempts(),
+            per_try_timeout_ms: None,
+            methods: Vec::new(),
+        }
+    }
+}
+
+impl RequestRetryPolicy {
+    /// Whether requests with this method may be retried
+    pub fn retries_method(&self, method: &str) -> bool {
+        ["GET", "HEAD", "OPTIONS"]
+            .into_iter()
+            .chain(self.methods.iter().map(String::as_str))
+            .any(|m| m.eq_ignore_ascii_case(method))
+    }
+}
+
+/// Limit on retries, so failing instances do not multiply the load
+///
+/// Over each 10-second window, retries may add `percent` of the requests seen,
+/// and at least `min_per_sec` per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryBudgetConfig {
+    #[serde(default = "default_retry_budget_percent")]
+    pub percent: u32,
+
+    #[serde(default = "default_retry_budget_min_per_sec")]
+    pub min_per_sec: u32,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            percent: default_retry_budget_percent(),
+            min_per_sec: default_retry_budget_min_per_sec(),
+        }
+    }
+}
+
+/// Temporarily ejecting registrations whose requests keep failing
+///
+/// A 5xx response, a timeout or a dropped tunnel counts as a failure. A
+/// registration is ejected after `consecutive_failures` failures in a row, or
+/// once `error_rate_percent` of at least `min_requests` requests within
+/// `interval_secs` fail. Ejections last `base_ejection_secs`, doubling with
+/// each repeat up to `max_ejection_secs`, and never take out more than
+/// `max_ejection_percent` of a route's registrations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutlierDetectionConfig {
+    /// Failures in a row that eject a registration; 0 turns this check off
+    #[serde(default = "default_outlier_consecutive_failures")]
+    pub consecutive_failures: u32,
+
+    /// Failure percentage that ejects a registration; unset turns this check off
+    #[serde(default)]
+    pub error_rate_percent: Option<u32>,
+
+    /// Requests needed within the interval before the error rate counts
+    #[serde(default = "default_outlier_min_requests")]
+    pub min_requests: u32,
+
+    #[serde(default = "default_outlier_interval")]
+    pub interval_secs: u64,
+
+    #[serde(default = "default_outlier_base_ejection")]
+    pub base_ejection_secs: u64,
+
+    #[serde(default = "default_outlier_max_ejection")]
+    pub max_ejection_secs: u64,
+
+    #[serde(default = "default_outlier_max_ejection_percent")]
+    pub max_ejection_percent: u32,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: default_outlier_consecutive_failures(),
+            error_rate_percent: None,
+            min_requests: default_outlier_min_requests(),
+            interval_secs: default_outlier_interval(),
+            base_ejection_secs: default_outlier_base_ejection(),
+            max_ejection_secs: default_outlier_max_ejection(),
+            max_ejection_percent: default_outlier_max_ejection_percent(),
+        }
+    }
+}
+
+/// Cookie-based sticky sessions
+///
+/// The ingress sets a cookie naming the registration it picked and sends
+/// later requests carrying it to the same registration while it is healthy,
+/// picking a new one otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionAffinityConfig {
+    #[serde(default = "default_affinity_cookie_name")]
+    pub cookie_name: String,
+
+    /// Cookie lifetime in seconds; a browser-session cookie when unset
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+/// Splits a host's traffic between groups of registrations by weight
+///
+/// Registrations are grouped by the value of `attribute`; those in no
+/// weighted group get no traffic. A request is hashed on `hash_on` so the
+/// same user keeps landing in the same group, and is placed at random by
+/// weight when the key is missing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightedSplit {
+    /// Registration attribute naming the group, e.g. `version`
+    pub attribute: String,
+
+    /// Relative weight per attribute value
+    pub weights: BTreeMap<String, u32>,
+
+    /// What a user's group is derived from
+    #[serde(default)]
+    pub hash_on: SplitHashKey,
+}
+
+/// Request value a weighted split hashes on: `client_ip` (first address in
+/// `x-forwarded-for`), `header:<name>`, `cookie:<name>` or `query:<name>`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SplitHashKey {
+    #[default]
+    ClientIp,
+    Header(String),
+    Cookie(String),
+    Query(String),
+}
+
+impl TryFrom<String> for SplitHashKey {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let key = match value.split_once(':') {
+            None if value == "client_ip" => SplitHashKey::ClientIp,
+            Some(("header", name)) if !name.is_empty() => SplitHashKey::Header(name.to_string()),
+            Some(("cookie", name)) if !name.is_empty() => SplitHashKey::Cookie(name.to_string()),
+            Some(("query", name)) if !name.is_empty() => SplitHashKey::Query(name.to_string()),
+            _ => {
+                return Err(format!(
+                    "invalid hash_on {:?}; expected client_ip, header:<name>, cookie:<name> or query:<name>",
+                    value
+                ))
+            }
+        };
+        Ok(key)
+    }
+}
+
+impl From<SplitHashKey> for String {
+    fn from(key: SplitHashKey) -> Self {
+        match key {
+            SplitHashKey::ClientIp => "client_ip".to_string(),
+            SplitHashKey::Header(name) => format!("header:{}", name),
+            SplitHashKey::Cookie(name) => format!("cookie:{}", name),
+            SplitHashKey::Query(name) => format!("query:{}", name),
+        }
+    }
+}
+
+/// Sends requests that match to the registrations carrying `attributes`
+///
+/// Registrations selected by any rule of a route are kept out of the default
+/// pool, which serves requests no rule matches. A matching rule with no
+/// registered targets falls back to the default pool.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingRule {
+    /// Conditions the request must meet; all of them must hold
+    #[serde(rename = "match", default)]
+    pub matches: RequestMatch,
+
+    /// Attributes a registration must advertise to receive the request
+    pub attributes: HashMap<String, String>,
+}
+
+/// Exact values expected on a request; names of headers are case-insensitive
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestMatch {
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub cookies: HashMap<String, String>,
+
+    /// Query parameters
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+}
+
+/// Credentials a route accepts; a request needs any one of them
+///
+/// Secrets are configured as hex SHA-256 digests, e.g.
+/// `printf %s "$SECRET" | sha256sum`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteAccessConfig {
+    /// Accept API keys
+    #[serde(default)]
+    pub api_keys: Option<ApiKeyAccessConfig>,
+
+    /// Accept HTTP basic auth
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuthConfig>,
+}
+
+/// Where API keys are read from and which ones are valid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyAccessConfig {
+    /// Header carrying the key
+    #[serde(default = "default_api_key_header")]
+    pub header: String,
+
+    /// Query parameter carrying the key, when keys may also be passed in the URL
+    #[serde(default)]
+    pub query_param: Option<String>,
+
+    /// Hex SHA-256 digests of the accepted keys
+    pub key_sha256: Vec<String>,
+}
+
+/// HTTP basic auth users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicAuthConfig {
+    /// Realm announced in the `WWW-Authenticate` challenge
+    #[serde(default = "default_basic_auth_realm")]
+    pub realm: String,
+
+    /// Username to bcrypt hash of the password, as `htpasswd -nB` writes it
+    pub users: HashMap<String, String>,
+}
+
+/// Verification of the `x-amzn-oidc-data` JWT the ALB adds after OIDC login
+///
+/// Requests without a valid token are rejected with 401. Forwarded claims
+/// reach the backend as `x-mesh-user-<claim>` headers; client-supplied
+/// `x-mesh-user-*` headers are always stripped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbOidcConfig {
+    /// ARNs of the load balancers whose tokens are accepted (`signer` header)
+    pub signer_arns: Vec<String>,
+
+    /// Public key URL with a `{kid}` placeholder; defaults to the ALB key
+    /// endpoint of the signer's region
+    #[serde(default)]
+    pub public_key_url: Option<String>,
+
+    /// PEM public key on disk, used for every `kid` (checked before `public_key_url`)
+    #[serde(default)]
+    pub public_key_file: Option<PathBuf>,
+
+    /// Clock skew tolerated on `exp`, in seconds
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway_secs: u64,
+
+    /// Claims forwarded to the backend
+    #[serde(default = "default_alb_oidc_forward_claims")]
+    pub forward_claims: Vec<String>,
 }
 
 /// Logging configuration
@@ -98,19 +832,55 @@ pub struct LoggingConfig {
     /// Whether to include request/response bodies in logs
     #[serde(default)]
     pub log_bodies: bool,
+
+    /// Write security audit events as JSON lines to this file (`-` for
+    /// stdout, which sends tracing to stderr); disabled when unset
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+}
+
+impl LoggingConfig {
+    /// Whether the audit log claims stdout, leaving stderr for everything else
+    pub fn audit_to_stdout(&self) -> bool {
+        self.audit_log.as_deref() == Some(Path::new("-"))
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            json_format: false,
+            log_bodies: false,
+            audit_log: None,
+        }
+    }
 }
 
 /// Load balancing strategies
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
-#[allow(dead_code)]
 pub enum LoadBalancingStrategy {
+    /// Rotate through the healthy instances in turn
     #[default]
     RoundRobin,
+    /// Pick a healthy instance uniformly at random
     Random,
+    /// Pick the healthy instance with the fewest requests in flight
     LeastConnections,
 }
 
+/// Handling of registrations whose host pattern is already served by another cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HostConflictPolicy {
+    /// Accept the registration and log and audit the conflict
+    #[default]
+    Warn,
+    /// Refuse the registration
+    Reject,
+}
+
 /// Configuration for the Anywhere Mesh cluster client
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
@@ -122,10 +892,80 @@ pub struct ClientConfig {
     pub cluster: ClusterConfig,
 
     /// AWS configuration
+    #[serde(default)]
     pub aws: AwsConfig,
 
     /// Logging configuration
+    #[serde(default)]
     pub logging: LoggingConfig,
+
+    /// Services served by this client; each is registered over its own connection
+    #[serde(default)]
+    pub services: Vec<ServiceConfig>,
+}
+
+impl ClientConfig {
+    /// Load a client configuration from a YAML file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Resolve the local endpoint a service proxies to
+    ///
+    /// Falls back to `connection.local_endpoint`, then to
+    /// `http://{cluster.local_host}:{port}`.
+    pub fn local_endpoint_for(&self, service: &ServiceConfig) -> String {
+        service
+            .local_endpoint
+            .clone()
+            .or_else(|| self.connection.local_endpoint.clone())
+            .unwrap_or_else(|| {
+                format!(
+                    "http://{}:{}",
+                    self.cluster.local_host,
+                    self.port_for(service)
+                )
+            })
+    }
+
+    /// Resolve the port a service registers with
+    pub fn port_for(&self, service: &ServiceConfig) -> u16 {
+        service.port.unwrap_or(self.cluster.default_local_port)
+    }
+}
+
+/// A single service served by the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct ServiceConfig {
+    /// Service name
+    pub service_name: String,
+
+    /// Host to register for routing
+    pub host: String,
+
+    /// Port to register for the service (defaults to `cluster.default_local_port`)
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// Local service endpoint to proxy to
+    #[serde(default)]
+    pub local_endpoint: Option<String>,
+
+    /// Health check path
+    #[serde(default = "default_health_check_path")]
+    pub health_check_path: String,
+
+    /// Attributes advertised with the registration
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+
+    /// Paths under `host` routed to this service; the whole host when empty
+    #[serde(default)]
+    pub paths: Vec<PathRoute>,
 }
 
 /// Connection configuration for client
@@ -135,15 +975,81 @@ pub struct ConnectionConfig {
     /// Ingress service WebSocket endpoint
     pub ingress_endpoint: String,
 
-    /// Local service endpoint to proxy to
-    pub local_endpoint: String,
+    /// Default local service endpoint for services that do not set one
+    #[serde(default)]
+    pub local_endpoint: Option<String>,
 
     /// Connection retry configuration
+    #[serde(default)]
     pub retry: RetryConfig,
 
     /// Heartbeat interval in seconds
     #[serde(default = "default_heartbeat_interval")]
     pub heartbeat_interval: u64,
+
+    /// Mesh token to authenticate with instead of IAM
+    #[serde(default)]
+    pub auth_token: Option<String>,
+
+    /// File holding a workload identity JWT to authenticate with instead of
+    /// IAM; re-read on every connect
+    #[serde(default)]
+    pub jwt_file: Option<PathBuf>,
+
+    /// TLS settings for `wss://` ingress endpoints
+    #[serde(default)]
+    pub tls: Option<ClientTlsConfig>,
+}
+
+/// Client-side TLS settings for the ingress connection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientTlsConfig {
+    /// PEM client certificate chain; together with `key_file`, it authenticates
+    /// the connection when the ingress requires client certificates
+    #[serde(default)]
+    pub cert_file: Option<PathBuf>,
+
+    /// PEM PKCS#8 private key for `cert_file`
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+
+    /// PEM CA bundle used to verify the ingress certificate, in addition to the
+    /// system roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+}
+
+impl ClientTlsConfig {
+    /// Whether a client certificate is configured
+    pub fn has_client_cert(&self) -> bool {
+        self.cert_file.is_some() && self.key_file.is_some()
+    }
+}
+
+/// How the client authenticates to the ingress and secures the connection,
+/// as given on the command line
+///
+/// In config file mode each option that is set overrides its counterpart in
+/// the file.
+#[derive(Debug, Clone, Default)]
+pub struct ClientAuthOptions {
+    /// Skip the ECS cluster access check
+    pub skip_iam_validation: bool,
+
+    /// Mesh token to authenticate with instead of IAM
+    pub auth_token: Option<String>,
+
+    /// File holding a workload identity JWT to authenticate with instead of IAM
+    pub jwt_file: Option<PathBuf>,
+
+    /// AWS region used to sign the IAM handshake
+    pub aws_region: Option<String>,
+
+    /// STS endpoint the IAM handshake is presigned for
+    pub sts_endpoint: Option<String>,
+
+    /// TLS settings for `wss://` ingress endpoints
+    pub tls: Option<ClientTlsConfig>,
 }
 
 /// ECS cluster configuration for the client
@@ -171,12 +1077,19 @@ pub struct ClusterConfig {
 }
 
 /// AWS configuration for client
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct AwsConfig {
-    /// AWS region
-    #[serde(default = "default_aws_region")]
-    pub region: String,
+    /// AWS region to sign presigned STS URLs for; detected from the
+    /// environment when unset
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// STS endpoint override (e.g. a FIPS endpoint or a local mock); defaults
+    /// to the regional endpoint. Anything but an official https STS host must
+    /// be listed in the ingress's `auth.trusted_sts_endpoints`
+    #[serde(default)]
+    pub sts_endpoint: Option<String>,
 
     /// Whether to skip IAM validation (for development)
     #[serde(default)]
@@ -207,6 +1120,17 @@ pub struct RetryConfig {
     pub backoff_multiplier: f64,
 }
 
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_retries(),
+            initial_delay_ms: default_initial_delay(),
+            max_delay_ms: default_max_delay(),
+            backoff_multiplier: default_backoff_multiplier(),
+        }
+    }
+}
+
 // Default value functions
 #[allow(dead_code)]
 fn default_alb_port() -> u16 {
@@ -229,6 +1153,82 @@ fn default_max_connections() -> usize {
     1000
 }
 #[allow(dead_code)]
+fn default_allowed_role_arns() -> Vec<String> {
+    vec!["*".to_string()]
+}
+#[allow(dead_code)]
+fn default_handshake_timeout() -> u64 {
+    10
+}
+fn default_reauth_interval() -> u64 {
+    900
+}
+fn default_presigned_url_max_expires() -> u64 {
+    60
+}
+fn default_presigned_url_clock_skew() -> u64 {
+    300
+}
+fn default_iam_session_ttl() -> u64 {
+    3600
+}
+fn default_jwks_refresh() -> u64 {
+    300
+}
+fn default_jwt_leeway() -> u64 {
+    60
+}
+fn default_jwt_algorithms() -> Vec<String> {
+    [
+        "RS256", "RS384", "RS512", "PS256", "PS384", "PS512", "ES256", "ES384", "EdDSA",
+    ]
+    .iter()
+    .map(|a| a.to_string())
+    .collect()
+}
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
+fn default_basic_auth_realm() -> String {
+    "anywhere-mesh".to_string()
+}
+fn default_affinity_cookie_name() -> String {
+    "mesh-affinity".to_string()
+}
+fn default_request_max_attempts() -> u32 {
+    2
+}
+fn default_retry_budget_percent() -> u32 {
+    20
+}
+fn default_retry_budget_min_per_sec() -> u32 {
+    10
+}
+fn default_outlier_consecutive_failures() -> u32 {
+    5
+}
+fn default_outlier_min_requests() -> u32 {
+    20
+}
+fn default_outlier_interval() -> u64 {
+    10
+}
+fn default_outlier_base_ejection() -> u64 {
+    30
+}
+fn default_outlier_max_ejection() -> u64 {
+    300
+}
+fn default_outlier_max_ejection_percent() -> u32 {
+    50
+}
+fn default_alb_oidc_forward_claims() -> Vec<String> {
+    vec!["sub".to_string(), "email".to_string()]
+}
+fn default_jwt_principal_claim() -> String {
+    "sub".to_string()
+}
+#[allow(dead_code)]
 fn default_aws_region() -> String {
     "us-east-1".to_string()
 }
@@ -243,7 +1243,7 @@ fn default_health_check_interval() -> u64 {
 }
 #[allow(dead_code)]
 fn default_unhealthy_threshold() -> u64 {
-    90
+    60
 }
 #[allow(dead_code)]
 fn default_log_level() -> String {
@@ -278,3 +1278,137 @@ fn default_local_port() -> u16 {
 fn default_local_host() -> String {
     "localhost".to_string()
 }
+#[allow(dead_code)]
+fn default_health_check_path() -> String {
+    "/health".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_ingress_config_uses_defaults() {
+        let yaml = r#"
+server:
+  alb_port: 9080
+auth:
+  allowed_role_arns:
+    - "arn:aws:iam::123456789012:role/MeshClient"
+"#;
+        let config: IngressConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(config.server.alb_port, 9080);
+        assert_eq!(config.server.websocket_port, 8082);
+        assert_eq!(config.server.health_port, 8081);
+        assert_eq!(config.server.request_timeout, 30);
+        assert_eq!(
+            config.auth.allowed_role_arns,
+            vec!["arn:aws:iam::123456789012:role/MeshClient".to_string()]
+        );
+        assert!(!config.auth.skip_validation);
+        assert_eq!(config.auth.reauth_interval_secs, 900);
+        assert_eq!(config.ecs.region, "us-east-1");
+        assert_eq!(config.routing.unhealthy_threshold, 60);
+    }
+
+    #[test]
+    fn test_empty_ingress_config_matches_default() {
+        let config: IngressConfig = serde_yaml::from_str("{}").unwrap();
+        let default = IngressConfig::default();
+
+        assert_eq!(config.server.alb_port, default.server.alb_port);
+        assert_eq!(
+            config.auth.allowed_role_arns,
+            default.auth.allowed_role_arns
+        );
+        assert_eq!(config.logging.level, default.logging.level);
+    }
+
+    #[test]
+    fn test_client_config_services() {
+        let yaml = r#"
+connection:
+  ingress_endpoint: "wss://mesh.example.com:8082"
+cluster:
+  cluster_name: edge-cluster
+  local_host: 127.0.0.1
+services:
+  - service_name: api
+    host: api.example.com
+    port: 3001
+    attributes:
+      version: "2"
+  - service_name: web
+    host: web.example.com
+    local_endpoint: "http://web:8080"
+    health_check_path: /healthz
+"#;
+        let config: ClientConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(config.services.len(), 2);
+        let api = &config.services[0];
+        assert_eq!(config.local_endpoint_for(api), "http://127.0.0.1:3001");
+        assert_eq!(api.health_check_path, "/health");
+        assert_eq!(api.attributes.get("version").map(String::as_str), Some("2"));
+
+        let web = &config.services[1];
+        assert_eq!(config.local_endpoint_for(web), "http://web:8080");
+        assert_eq!(config.port_for(web), 3000);
+        assert!(!config.aws.skip_iam_validation);
+    }
+
+    #[test]
+    fn test_route_timeouts_override_defaults() {
+        let yaml = r#"
+timeouts:
+  connect_ms: 1000
+  first_byte_ms: 5000
+routes:
+  - host: reports.example.com
+    timeouts:
+      first_byte_ms: 110000
+      total_ms: 120000
+  - host: "*.example.com"
+    timeouts:
+      total_ms: 5000
+"#;
+        let routing: RoutingConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let reports = routing.timeouts_for_host("reports.example.com");
+        assert_eq!(reports.connect_ms, Some(1000));
+        assert_eq!(reports.first_byte_ms, Some(110000));
+        assert_eq!(reports.total_ms, Some(120000));
+
+        let api = routing.timeouts_for_host("api.example.com");
+        assert_eq!(api.first_byte_ms, Some(5000));
+        assert_eq!(api.total_ms, Some(5000));
+
+        let other = routing.timeouts_for_host("other.test");
+        assert_eq!(other.total_ms, None);
+    }
+
+    #[test]
+    fn test_route_alb_oidc_defaults() {
+        let yaml = r#"
+routes:
+  - host: app.example.com
+    alb_oidc:
+      signer_arns:
+        - "arn:aws:elasticloadbalancing:us-east-1:123456789012:loadbalancer/app/edge/50dc6c495c0c9188"
+  - host: "*.example.com"
+"#;
+        let routing: RoutingConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let oidc = routing
+            .route_for_host("app.example.com")
+            .and_then(|r| r.alb_oidc.as_ref())
+            .unwrap();
+        assert_eq!(oidc.forward_claims, vec!["sub", "email"]);
+        assert_eq!(oidc.leeway_secs, 60);
+        assert!(oidc.public_key_url.is_none());
+
+        let other = routing.route_for_host("api.example.com").unwrap();
+        assert!(other.alb_oidc.is_none());
+    }
+}
@@ -1,6 +1,7 @@
 pub mod auth;
 pub mod config;
 pub mod routing;
+pub mod token;
 pub mod types;
 
 // Re-export everything for easy access
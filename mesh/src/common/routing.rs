@@ -1,41 +1,918 @@
-use super::{ConnectionInfo, ServiceRegistration};
+use super::config::{
+    RequestMatch, RoutingRule, SessionAffinityConfig, SplitHashKey, WeightedSplit,
+};
+use super::{ConnectionInfo, PathMatchType, PathRoute, ServiceRegistration};
+use percent_encoding::percent_decode_str;
+use rand::Rng;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 use uuid::Uuid;
 
-pub fn match_host_to_service<'a>(
+/// Header prefix carrying the labels captured by `{name}` in the matched host
+/// pattern, e.g. `x-mesh-host-tenant`
+pub const HOST_CAPTURE_HEADER_PREFIX: &str = "x-mesh-host-";
+
+/// A host pattern, split by kind
+///
+/// - `api.example.com`: exact host
+/// - `*.example.com`: `**` is one or more labels, `*` exactly one label (a
+///   leading `*` keeps its suffix meaning and is read as `**`) and `{name}`
+///   one label captured as `name`; any label position works
+/// - `~<regex>`: regular expression matching the whole host
+enum HostPattern<'a> {
+    Exact(&'a str),
+    Labels(Vec<&'a str>),
+    Regex(&'a str),
+}
+
+impl<'a> HostPattern<'a> {
+    fn parse(pattern: &'a str) -> Self {
+        if let Some(regex) = pattern.strip_prefix('~') {
+            HostPattern::Regex(regex)
+        } else if pattern.contains(['*', '{']) {
+            HostPattern::Labels(split_labels(pattern))
+        } else {
+            HostPattern::Exact(pattern)
+        }
+    }
+}
+
+/// Labels of a host or host pattern, reading a leading `*` as `**`
+fn split_labels(host: &str) -> Vec<&str> {
+    let mut labels: Vec<&str> = host.split('.').collect();
+    if labels[0] == "*" {
+        labels[0] = "**";
+    }
+    labels
+}
+
+/// Whether a host pattern is a plain host without wildcards or a regex
+pub fn is_exact_host(pattern: &str) -> bool {
+    matches!(HostPattern::parse(pattern), HostPattern::Exact(_))
+}
+
+/// Whether a request host is a plain DNS name, free of pattern syntax
+pub fn is_request_host(host: &str) -> bool {
+    host.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+/// Check whether a request host matches a host pattern; hosts that are not
+/// plain DNS names match nothing
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    host_captures(pattern, host).is_some()
+}
+
+/// Labels captured by the `{name}` segments of `pattern` when it matches the
+/// request host `host`
+pub fn host_captures(pattern: &str, host: &str) -> Option<Vec<(String, String)>> {
+    if !is_request_host(host) {
+        return None;
+    }
+    match_host(pattern, &host.to_ascii_lowercase())
+}
+
+/// Check whether `pattern` covers every host `host_pattern` matches, as when
+/// checking a registered host against policy patterns
+///
+/// `*` and `{name}` do not cover a `**` label, and a regex is only covered by
+/// an identical pattern or by `**`.
+pub fn pattern_covers(pattern: &str, host_pattern: &str) -> bool {
+    if host_pattern.starts_with('~') {
+        return pattern == host_pattern || pattern == "**";
+    }
+    match_host(pattern, &host_pattern.to_ascii_lowercase()).is_some()
+}
+
+fn match_host(pattern: &str, host: &str) -> Option<Vec<(String, String)>> {
+    match HostPattern::parse(pattern) {
+        HostPattern::Exact(exact) => exact.eq_ignore_ascii_case(host).then(Vec::new),
+        HostPattern::Regex(regex) => regex_matches(regex, host).then(Vec::new),
+        HostPattern::Labels(labels) => {
+            let host_labels = split_labels(host);
+            let mut captures = Vec::new();
+            match_labels(&labels, &host_labels, &mut captures).then_some(captures)
+        }
+    }
+}
+
+fn match_labels(pattern: &[&str], host: &[&str], captures: &mut Vec<(String, String)>) -> bool {
+    let Some((label, pattern_rest)) = pattern.split_first() else {
+        return host.is_empty();
+    };
+
+    if *label == "**" {
+        return (1..=host.len()).any(|taken| {
+            let mut attempt = captures.clone();
+            let matched = match_labels(pattern_rest, &host[taken..], &mut attempt);
+            if matched {
+                *captures = attempt;
+            }
+            matched
+        });
+    }
+
+    let Some((host_label, host_rest)) = host.split_first() else {
+        return false;
+    };
+    let label_matches = if *label == "*" {
+        *host_label != "**"
+    } else if let Some(name) = capture_name(label) {
+        if *host_label == "**" {
+            false
+        } else {
+            captures.push((name.to_string(), host_label.to_string()));
+            true
+        }
+    } else {
+        label.eq_ignore_ascii_case(host_label)
+    };
+    label_matches && match_labels(pattern_rest, host_rest, captures)
+}
+
+fn capture_name(label: &str) -> Option<&str> {
+    label.strip_prefix('{')?.strip_suffix('}')
+}
+
+/// How specific a host pattern is; greater wins. Exact hosts beat wildcards,
+/// wildcards with more literal labels (then fewer `**`, then more labels)
+/// beat broader ones, and regexes come last.
+fn host_specificity(pattern: &str) -> (u8, usize, std::cmp::Reverse<usize>, usize) {
+    match HostPattern::parse(pattern) {
+        HostPattern::Exact(_) => (2, 0, std::cmp::Reverse(0), 0),
+        HostPattern::Labels(labels) => {
+            let literals = labels
+                .iter()
+                .filter(|label| **label != "*" && **label != "**" && capture_name(label).is_none())
+                .count();
+            let multi = labels.iter().filter(|label| **label == "**").count();
+            (1, literals, std::cmp::Reverse(multi), labels.len())
+        }
+        HostPattern::Regex(_) => (0, 0, std::cmp::Reverse(0), 0),
+    }
+}
+
+/// Canonical form of a host pattern; patterns with the same form match the
+/// same hosts
+pub fn normalize_host_pattern(pattern: &str) -> String {
+    match HostPattern::parse(pattern) {
+        HostPattern::Regex(_) => pattern.to_string(),
+        HostPattern::Exact(exact) => exact.to_ascii_lowercase(),
+        HostPattern::Labels(labels) => labels
+            .iter()
+            .map(|label| match capture_name(label) {
+                Some(_) => "*".to_string(),
+                None => label.to_ascii_lowercase(),
+            })
+            .collect::<Vec<_>>()
+            .join("."),
+    }
+}
+
+/// Check that a host pattern is well-formed
+pub fn validate_host_pattern(pattern: &str) -> Result<(), String> {
+    match HostPattern::parse(pattern) {
+        HostPattern::Exact("") => Err("host is empty".to_string()),
+        HostPattern::Exact(_) => Ok(()),
+        HostPattern::Regex(regex) => compile_anchored(regex)
+            .map(|_| ())
+            .map_err(|e| format!("invalid host regex {}: {}", regex, e)),
+        HostPattern::Labels(labels) => {
+            if let Some(rest) = pattern
+                .strip_prefix('*')
+                .filter(|rest| !rest.is_empty() && !rest.starts_with(['.', '*']))
+            {
+                return Err(format!(
+                    "host {} no longer matches by suffix; use *.{} for subdomains or list exact hosts",
+                    pattern, rest
+                ));
+            }
+            let mut names = Vec::new();
+            for label in &labels {
+                if *label == "*" || *label == "**" {
+                    continue;
+                }
+                if let Some(name) = capture_name(label) {
+                    if name.is_empty()
+                        || !name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    {
+                        return Err(format!("invalid capture {} in host {}", label, pattern));
+                    }
+                    if names.contains(&name) {
+                        return Err(format!("capture {} repeats in host {}", label, pattern));
+                    }
+                    names.push(name);
+                } else if label.is_empty() || label.contains(['*', '{', '}']) {
+                    return Err(format!("invalid label {:?} in host {}", label, pattern));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Items whose host pattern matches `host` most specifically; ties between
+/// different patterns of equal specificity go to the lexically first one
+pub fn best_host_matches<'a, T>(
     host: &str,
+    items: impl IntoIterator<Item = &'a T>,
+    pattern_of: impl Fn(&T) -> &str,
+) -> Vec<&'a T> {
+    let matching: Vec<_> = items
+        .into_iter()
+        .filter(|item| host_matches(pattern_of(item), host))
+        .map(|item| {
+            let pattern = pattern_of(item);
+            (
+                host_specificity(pattern),
+                normalize_host_pattern(pattern),
+                item,
+            )
+        })
+        .collect();
+    let Some((specificity, normalized)) = matching
+        .iter()
+        .max_by(|(s1, n1, _), (s2, n2, _)| s1.cmp(s2).then_with(|| n2.cmp(n1)))
+        .map(|(specificity, normalized, _)| (*specificity, normalized.clone()))
+    else {
+        return Vec::new();
+    };
+    matching
+        .into_iter()
+        .filter(|(s, n, _)| *s == specificity && *n == normalized)
+        .map(|(_, _, item)| item)
+        .collect()
+}
+
+/// How specifically a registration matches a request path; later variants win
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PathMatch {
+    /// The registration declares no paths
+    Fallback,
+    Regex,
+    /// Prefix match, ranked by prefix length
+    Prefix(usize),
+    Exact,
+}
+
+/// Registrations whose paths best match `path`: exact beats the longest
+/// prefix, which beats a regex, which beats registrations without paths
+pub fn match_path_routes<'a>(
+    path: &str,
     registrations: &'a [ServiceRegistration],
-) -> Option<&'a ServiceRegistration> {
-    // First try exact match
-    if let Some(reg) = registrations.iter().find(|r| r.host == host) {
-        return Some(reg);
+) -> Vec<&'a ServiceRegistration> {
+    best_path_matches(path, registrations.iter())
+}
+
+fn best_path_matches<'a>(
+    path: &str,
+    registrations: impl Iterator<Item = &'a ServiceRegistration>,
+) -> Vec<&'a ServiceRegistration> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let ranked: Vec<_> = registrations
+        .filter_map(|reg| path_match(reg, path).map(|rank| (rank, reg)))
+        .collect();
+    let Some(best) = ranked.iter().map(|(rank, _)| *rank).max() else {
+        return Vec::new();
+    };
+    ranked
+        .into_iter()
+        .filter(|(rank, _)| *rank == best)
+        .map(|(_, reg)| reg)
+        .collect()
+}
+
+fn path_match(registration: &ServiceRegistration, path: &str) -> Option<PathMatch> {
+    if registration.paths.is_empty() {
+        return Some(PathMatch::Fallback);
+    }
+    registration
+        .paths
+        .iter()
+        .filter_map(|route| match route.match_type {
+            PathMatchType::Exact => (route.path == path).then_some(PathMatch::Exact),
+            PathMatchType::Prefix => {
+                prefix_matches(&route.path, path).then_some(PathMatch::Prefix(route.path.len()))
+            }
+            PathMatchType::Regex => regex_matches(&route.path, path).then_some(PathMatch::Regex),
+        })
+        .max()
+}
+
+/// Segment-aware prefix match: `/api` matches `/api` and `/api/x`, not `/apix`
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
     }
+}
 
-    // Then try wildcard matching
-    registrations.iter().find(|r| {
-        if r.host.starts_with('*') {
-            let pattern = &r.host[1..];
-            host.ends_with(pattern)
-        } else {
-            false
+fn compile_anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+fn regex_matches(pattern: &str, path: &str) -> bool {
+    static COMPILED: OnceLock<Mutex<HashMap<String, Option<Regex>>>> = OnceLock::new();
+    let mut compiled = COMPILED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    compiled
+        .entry(pattern.to_string())
+        .or_insert_with(|| compile_anchored(pattern).ok())
+        .as_ref()
+        .is_some_and(|regex| regex.is_match(path))
+}
+
+/// Check that exact and prefix paths are absolute and regexes compile
+pub fn validate_paths(paths: &[PathRoute]) -> Result<(), String> {
+    for route in paths {
+        match route.match_type {
+            PathMatchType::Exact | PathMatchType::Prefix if !route.path.starts_with('/') => {
+                return Err(format!("path {} must start with /", route.path));
+            }
+            PathMatchType::Regex => {
+                if let Err(e) = compile_anchored(&route.path) {
+                    return Err(format!("invalid path regex {}: {}", route.path, e));
+                }
+            }
+            _ => {}
         }
+    }
+    Ok(())
+}
+
+/// A route of `registration` that `other` also declares for the same host,
+/// leaving no way to pick between the two
+pub fn overlapping_route<'a>(
+    registration: &'a ServiceRegistration,
+    other: &ServiceRegistration,
+) -> Option<&'a PathRoute> {
+    if normalize_host_pattern(&registration.host) != normalize_host_pattern(&other.host) {
+        return None;
+    }
+    let normalized = |route: &PathRoute| match route.match_type {
+        PathMatchType::Prefix => (
+            route.match_type,
+            route.path.trim_end_matches('/').to_string(),
+        ),
+        _ => (route.match_type, route.path.clone()),
+    };
+    registration.paths.iter().find(|route| {
+        other
+            .paths
+            .iter()
+            .any(|o| normalized(o) == normalized(route))
     })
 }
 
-pub fn select_healthy_instance<'a>(
+/// Registrations the first matching rule targets, or the default pool of
+/// registrations no rule targets when no rule matches or a matching rule has
+/// no targets
+pub fn select_by_rules<'a>(
+    rules: &[RoutingRule],
+    headers: &HashMap<String, String>,
+    path: &str,
+    registrations: &'a [ServiceRegistration],
+) -> Vec<&'a ServiceRegistration> {
+    if rules.is_empty() {
+        return registrations.iter().collect();
+    }
+
+    if let Some(rule) = rules
+        .iter()
+        .find(|rule| request_matches(&rule.matches, headers, path))
+    {
+        let targets: Vec<_> = registrations
+            .iter()
+            .filter(|reg| has_attributes(reg, &rule.attributes))
+            .collect();
+        if !targets.is_empty() {
+            return targets;
+        }
+    }
+
+    registrations
+        .iter()
+        .filter(|reg| {
+            !rules
+                .iter()
+                .any(|rule| has_attributes(reg, &rule.attributes))
+        })
+        .collect()
+}
+
+fn has_attributes(
+    registration: &ServiceRegistration,
+    attributes: &HashMap<String, String>,
+) -> bool {
+    attributes
+        .iter()
+        .all(|(key, value)| registration.attributes.get(key) == Some(value))
+}
+
+/// Whether the request carries every header, cookie and query value of `matches`
+fn request_matches(matches: &RequestMatch, headers: &HashMap<String, String>, path: &str) -> bool {
+    let cookies = cookie_values(headers);
+    let query = query_values(path);
+    matches
+        .headers
+        .iter()
+        .all(|(name, value)| header_value(headers, name) == Some(value.as_str()))
+        && matches
+            .cookies
+            .iter()
+            .all(|(name, value)| cookies.get(name.as_str()) == Some(&value.as_str()))
+        && matches
+            .query
+            .iter()
+            .all(|(name, value)| query.get(name) == Some(value))
+}
+
+fn header_value<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn cookie_values(headers: &HashMap<String, String>) -> HashMap<&str, &str> {
+    header_value(headers, "cookie")
+        .into_iter()
+        .flat_map(|cookie| cookie.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .collect()
+}
+
+fn query_values(path: &str) -> HashMap<String, String> {
+    path.split_once('?')
+        .map(|(_, query)| query.split('#').next().unwrap_or_default())
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            (
+                percent_decode_str(key).decode_utf8_lossy().into_owned(),
+                percent_decode_str(value).decode_utf8_lossy().into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// The request value a weighted split hashes on, if present
+pub fn split_key(
+    hash_on: &SplitHashKey,
+    headers: &HashMap<String, String>,
+    path: &str,
+) -> Option<String> {
+    let key = match hash_on {
+        SplitHashKey::ClientIp => header_value(headers, "x-forwarded-for")
+            .and_then(|forwarded| forwarded.split(',').next())
+            .map(|ip| ip.trim().to_string()),
+        SplitHashKey::Header(name) => header_value(headers, name).map(str::to_string),
+        SplitHashKey::Cookie(name) => cookie_values(headers)
+            .get(name.as_str())
+            .map(|value| value.to_string()),
+        SplitHashKey::Query(name) => query_values(path).remove(name),
+    };
+    key.filter(|key| !key.is_empty())
+}
+
+/// Registrations in the group `key` falls into
+///
+/// Only groups with registrations take part; when none has any, every
+/// registration does. `key` is hashed to a fixed point in `[0, 1)`, laid over
+/// the groups' cumulative weight shares in attribute-value order. Raising a
+/// group's weight only widens its share, so users move into it and no one
+/// else changes group; without a key the group is picked at random by weight.
+pub fn select_split<'a>(
+    split: &WeightedSplit,
+    key: Option<&str>,
+    registrations: &[&'a ServiceRegistration],
+) -> Vec<&'a ServiceRegistration> {
+    let in_group = |reg: &ServiceRegistration, value: &str| {
+        reg.attributes.get(&split.attribute).map(String::as_str) == Some(value)
+    };
+    let groups: Vec<(&str, u64)> = split
+        .weights
+        .iter()
+        .filter(|(value, weight)| {
+            **weight > 0 && registrations.iter().any(|reg| in_group(reg, value))
+        })
+        .map(|(value, weight)| (value.as_str(), u64::from(*weight)))
+        .collect();
+    let total: u64 = groups.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+        return registrations.to_vec();
+    }
+
+    let mut point = match key {
+        Some(key) => {
+            let digest = Sha256::digest(key.as_bytes());
+            let hash = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+            // Scale rather than take the remainder, so a user's point keeps
+            // its place when the total changes
+            ((u128::from(hash) * u128::from(total)) >> 64) as u64
+        }
+        None => rand::thread_rng().gen_range(0..total),
+    };
+    for (value, weight) in groups {
+        if point < weight {
+            return registrations
+                .iter()
+                .copied()
+                .filter(|reg| in_group(reg, value))
+                .collect();
+        }
+        point -= weight;
+    }
+    unreachable!("point is below the total weight")
+}
+
+/// Registration named by the request's affinity cookie
+pub fn affinity_target(
+    affinity: &SessionAffinityConfig,
+    headers: &HashMap<String, String>,
+) -> Option<Uuid> {
+    cookie_values(headers)
+        .get(affinity.cookie_name.as_str())
+        .and_then(|value| Uuid::parse_str(value).ok())
+}
+
+/// `Set-Cookie` value pinning the user to `registration_id`; `secure` when
+/// the client reached the load balancer over https
+pub fn affinity_cookie(
+    affinity: &SessionAffinityConfig,
+    registration_id: Uuid,
+    secure: bool,
+) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax",
+        affinity.cookie_name, registration_id
+    );
+    if let Some(max_age) = affinity.max_age_secs {
+        cookie.push_str(&format!("; Max-Age={}", max_age));
+    }
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// Check whether a registration's connection has heartbeated within the threshold
+pub fn is_healthy(
+    registration: &ServiceRegistration,
+    connections: &HashMap<Uuid, ConnectionInfo>,
+    unhealthy_threshold: Duration,
+) -> bool {
+    connections
+        .get(&registration.id)
+        .and_then(|conn| conn.last_heartbeat.elapsed().ok())
+        .is_some_and(|elapsed| elapsed < unhealthy_threshold)
+}
+
+/// All healthy registrations, in the order given
+pub fn healthy_instances<'a>(
     registrations: &'a [ServiceRegistration],
     connections: &HashMap<Uuid, ConnectionInfo>,
-) -> Option<&'a ServiceRegistration> {
-    registrations.iter().find(|reg| {
-        if let Some(conn) = connections.get(&reg.id) {
-            // Check if connection is healthy (heartbeat within last 30 seconds)
-            if let Ok(elapsed) = conn.last_heartbeat.elapsed() {
-                elapsed.as_secs() < 60
-            } else {
-                false
+    unhealthy_threshold: Duration,
+) -> Vec<&'a ServiceRegistration> {
+    registrations
+        .iter()
+        .filter(|reg| is_healthy(reg, connections, unhealthy_threshold))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(
+        service_name: &str,
+        host: &str,
+        paths: &[(PathMatchType, &str)],
+    ) -> ServiceRegistration {
+        ServiceRegistration {
+            id: Uuid::new_v4(),
+            host: host.to_string(),
+            port: 8080,
+            service_name: service_name.to_string(),
+            cluster_name: "test-cluster".to_string(),
+            task_arn: String::new(),
+            attributes: HashMap::new(),
+            health_check_path: None,
+            paths: paths
+                .iter()
+                .map(|(match_type, path)| PathRoute {
+                    match_type: *match_type,
+                    path: path.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn match_host<'a>(
+        host: &str,
+        registrations: &'a [ServiceRegistration],
+    ) -> Vec<&'a ServiceRegistration> {
+        best_host_matches(host, registrations, |reg| reg.host.as_str())
+    }
+
+    fn routed(host: &str, path: &str, registrations: &[ServiceRegistration]) -> Vec<String> {
+        best_path_matches(path, match_host(host, registrations).into_iter())
+            .iter()
+            .map(|reg| reg.service_name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_route_prefers_most_specific_path() {
+        use PathMatchType::*;
+        let registrations = vec![
+            registration("frontend", "app.example.com", &[]),
+            registration("api", "app.example.com", &[(Prefix, "/api")]),
+            registration("api-v2", "app.example.com", &[(Prefix, "/api/v2/")]),
+            registration("login", "app.example.com", &[(Exact, "/api/login")]),
+            registration("events", "app.example.com", &[(Regex, "/[a-z]+/events")]),
+            registration("other", "other.example.com", &[(Prefix, "/")]),
+        ];
+
+        assert_eq!(routed("app.example.com", "/", &registrations), ["frontend"]);
+        assert_eq!(
+            routed("app.example.com", "/apix", &registrations),
+            ["frontend"]
+        );
+        assert_eq!(routed("app.example.com", "/api", &registrations), ["api"]);
+        assert_eq!(
+            routed("app.example.com", "/api/users?page=2", &registrations),
+            ["api"]
+        );
+        assert_eq!(
+            routed("app.example.com", "/api/v2/users", &registrations),
+            ["api-v2"]
+        );
+        assert_eq!(
+            routed("app.example.com", "/api/login", &registrations),
+            ["login"]
+        );
+        assert_eq!(
+            routed("app.example.com", "/app/events", &registrations),
+            ["events"]
+        );
+        // Regexes match the whole path
+        assert_eq!(
+            routed("app.example.com", "/app/events/1", &registrations),
+            ["frontend"]
+        );
+        // A prefix beats a regex
+        assert_eq!(
+            routed("app.example.com", "/api/events", &registrations),
+            ["api"]
+        );
+        assert!(routed("unknown.example.com", "/", &registrations).is_empty());
+    }
+
+    #[test]
+    fn test_host_patterns_and_captures() {
+        assert!(host_matches("api.example.com", "API.example.com"));
+        assert!(host_matches("*.example.com", "api.example.com"));
+        assert!(host_matches("*.example.com", "v1.api.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(host_matches("api.*.example.com", "api.eu.example.com"));
+        assert!(!host_matches("api.*.example.com", "api.v1.eu.example.com"));
+        assert!(host_matches("api.**.example.com", "api.v1.eu.example.com"));
+        assert!(host_matches(
+            r"~api-\d+\.example\.com",
+            "api-42.example.com"
+        ));
+        assert!(!host_matches(
+            r"~api-\d+\.example\.com",
+            "api-42.example.com.evil"
+        ));
+
+        assert_eq!(
+            host_captures("{tenant}.{region}.example.com", "acme.eu.example.com"),
+            Some(vec![
+                ("tenant".to_string(), "acme".to_string()),
+                ("region".to_string(), "eu".to_string()),
+            ])
+        );
+        assert_eq!(
+            host_captures("**.{tenant}.example.com", "a.b.acme.example.com"),
+            Some(vec![("tenant".to_string(), "acme".to_string())])
+        );
+
+        // Request hosts carrying pattern syntax match nothing
+        assert!(!host_matches("**.example.com", "**.example.com"));
+        assert!(!host_matches(
+            r"~api-\d+\.example\.com",
+            r"~api-\d+\.example\.com"
+        ));
+        assert!(!host_matches(
+            "{tenant}.example.com",
+            "{tenant}.example.com"
+        ));
+        assert!(!host_matches("*.example.com", "a..example.com"));
+        assert!(is_request_host("api-1.example.com"));
+        assert!(!is_request_host("*.example.com"));
+        assert!(!is_request_host(""));
+
+        // Registered patterns are only covered by patterns at least as broad
+        assert!(pattern_covers("*.example.com", "{tenant}.example.com"));
+        assert!(pattern_covers("**.example.com", "*.example.com"));
+        assert!(!pattern_covers("{tenant}.example.com", "*.example.com"));
+        assert!(!pattern_covers("api.*.example.com", "api.**.example.com"));
+        assert!(!pattern_covers("*.example.com", "~.*"));
+        assert!(pattern_covers("**", "~.*"));
+
+        assert_eq!(
+            normalize_host_pattern("{Tenant}.Example.com"),
+            normalize_host_pattern("{customer}.example.com")
+        );
+        assert_eq!(
+            normalize_host_pattern("*.example.com"),
+            normalize_host_pattern("**.example.com")
+        );
+        assert_ne!(
+            normalize_host_pattern("{tenant}.example.com"),
+            normalize_host_pattern("*.example.com")
+        );
+        assert!(validate_host_pattern("{tenant}.**.example.com").is_ok());
+        assert!(validate_host_pattern("").is_err());
+        assert!(validate_host_pattern("a*.example.com").is_err());
+        assert!(validate_host_pattern("*example.com")
+            .unwrap_err()
+            .contains("*.example.com"));
+        assert!(validate_host_pattern("{a}.{a}.example.com").is_err());
+        assert!(validate_host_pattern("{}.example.com").is_err());
+        assert!(validate_host_pattern("~(").is_err());
+    }
+
+    #[test]
+    fn test_match_host_precedence_is_deterministic() {
+        let registrations = vec![
+            registration("regex", r"~.*\.example\.com", &[]),
+            registration("deep", "**.example.com", &[]),
+            registration("tenant", "{tenant}.example.com", &[]),
+            registration("api-any", "api.*.com", &[]),
+            registration("eu", "*.eu.example.com", &[]),
+            registration("exact", "api.example.com", &[]),
+        ];
+        let matched = |host: &str| -> Vec<String> {
+            // Registration order must not matter
+            let mut reversed = registrations.clone();
+            reversed.reverse();
+            let forward: Vec<String> = match_host(host, &registrations)
+                .iter()
+                .map(|reg| reg.service_name.clone())
+                .collect();
+            let backward: Vec<String> = match_host(host, &reversed)
+                .iter()
+                .map(|reg| reg.service_name.clone())
+                .collect();
+            assert_eq!(forward, backward);
+            forward
+        };
+
+        assert_eq!(matched("api.example.com"), ["exact"]);
+        assert_eq!(matched("web.example.com"), ["tenant"]);
+        assert_eq!(matched("api.example.com.evil"), Vec::<String>::new());
+        assert_eq!(matched("acme.eu.example.com"), ["eu"]);
+        assert_eq!(matched("a.b.example.com"), ["deep"]);
+        assert_eq!(matched("example.com"), Vec::<String>::new());
+
+        // Equally specific patterns fall back to the lexically first one
+        let tied = [
+            registration("api-any", "api.*.com", &[]),
+            registration("tenant", "{tenant}.example.com", &[]),
+        ];
+        assert_eq!(
+            match_host("api.example.com", &tied)[0].service_name,
+            "tenant"
+        );
+
+        let regex_only = [registration("regex", r"~.*\.example\.com", &[])];
+        assert_eq!(match_host("a.b.example.com", &regex_only).len(), 1);
+    }
+
+    #[test]
+    fn test_overlapping_routes_and_validation() {
+        use PathMatchType::*;
+        let api = registration("api", "app.example.com", &[(Prefix, "/api/")]);
+        let admin = registration(
+            "admin",
+            "app.example.com",
+            &[(Exact, "/api"), (Prefix, "/api")],
+        );
+        let elsewhere = registration("admin", "other.example.com", &[(Prefix, "/api")]);
+
+        assert_eq!(
+            overlapping_route(&admin, &api).map(|r| r.match_type),
+            Some(Prefix)
+        );
+        assert!(overlapping_route(&elsewhere, &api).is_none());
+        assert!(
+            overlapping_route(&registration("frontend", "app.example.com", &[]), &api).is_none()
+        );
+
+        assert!(validate_paths(&admin.paths).is_ok());
+        assert!(validate_paths(&registration("x", "h", &[(Prefix, "api")]).paths).is_err());
+        assert!(validate_paths(&registration("x", "h", &[(Regex, "/(")]).paths).is_err());
+    }
+
+    #[test]
+    fn test_select_split_is_weighted_and_sticky() {
+        let mut v1 = registration("web", "app.example.com", &[]);
+        v1.attributes.insert("version".to_string(), "1".to_string());
+        let mut v2 = registration("web", "app.example.com", &[]);
+        v2.attributes.insert("version".to_string(), "2".to_string());
+        let untagged = registration("web", "app.example.com", &[]);
+        let registrations = [&v1, &v2, &untagged];
+        let split: WeightedSplit = serde_yaml::from_str(
+            r#"
+attribute: version
+weights: { "1": 90, "2": 10 }
+hash_on: "cookie:session"
+"#,
+        )
+        .unwrap();
+
+        let version = |key: Option<&str>, registrations: &[&ServiceRegistration]| {
+            let selected = select_split(&split, key, registrations);
+            assert_eq!(selected.len(), 1);
+            selected[0].attributes["version"].clone()
+        };
+
+        let mut on_v2 = 0;
+        for user in 0..1000 {
+            let key = format!("user-{}", user);
+            let first = version(Some(&key), &registrations);
+            assert_eq!(version(Some(&key), &registrations), first);
+            if first == "2" {
+                on_v2 += 1;
             }
-        } else {
-            false
         }
-    })
+        assert!((50..150).contains(&on_v2), "{} of 1000 on v2", on_v2);
+
+        // Groups without registrations drop out; with none left, everyone serves
+        assert_eq!(version(Some("user-1"), &[&v2, &untagged]), "2");
+        let fallback = select_split(&split, None, &[&untagged]);
+        assert_eq!(fallback.len(), 1);
+        assert_eq!(fallback[0].id, untagged.id);
+
+        // Raising v2's weight only moves users onto v2
+        let raised: WeightedSplit = serde_yaml::from_str(
+            r#"
+attribute: version
+weights: { "1": 80, "2": 20 }
+hash_on: "cookie:session"
+"#,
+        )
+        .unwrap();
+        let mut moved = 0;
+        for user in 0..1000 {
+            let key = format!("user-{}", user);
+            let before = version(Some(&key), &registrations);
+            let after =
+                select_split(&raised, Some(&key), &registrations)[0].attributes["version"].clone();
+            if before != after {
+                assert_eq!((before.as_str(), after.as_str()), ("1", "2"), "{}", key);
+                moved += 1;
+            }
+        }
+        assert!((50..150).contains(&moved), "{} of 1000 moved", moved);
+
+        let headers = HashMap::from([
+            ("Cookie".to_string(), "theme=dark; session=abc".to_string()),
+            (
+                "x-forwarded-for".to_string(),
+                "203.0.113.7, 10.0.0.1".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            split_key(&split.hash_on, &headers, "/").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            split_key(&SplitHashKey::ClientIp, &headers, "/").as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            split_key(&SplitHashKey::Query("u".to_string()), &headers, "/?u=42").as_deref(),
+            Some("42")
+        );
+        assert_eq!(
+            split_key(&SplitHashKey::Header("x-user".to_string()), &headers, "/"),
+            None
+        );
+    }
 }
@@ -0,0 +1,168 @@
+//! HMAC-signed mesh tokens for clients without AWS credentials.
+//!
+//! A token is `mesh1.<payload>.<signature>`, where `payload` is the URL-safe
+//! base64 of the JSON claims and `signature` is the URL-safe base64 of
+//! HMAC-SHA256 over `mesh1.<payload>` keyed with the server-side secret.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+const TOKEN_PREFIX: &str = "mesh1";
+
+/// Shortest secret accepted; shorter keys make tokens guessable
+pub const MIN_SECRET_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Claims carried by a mesh token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// Who the token was issued to
+    pub sub: String,
+
+    /// Host patterns the holder may register, in the same syntax as
+    /// registered hosts (`*`, `**`, `{name}` or `~regex`)
+    pub hosts: Vec<String>,
+
+    /// Issue time, seconds since the Unix epoch
+    pub iat: i64,
+
+    /// Expiry time, seconds since the Unix epoch
+    pub exp: i64,
+}
+
+/// Reasons a token fails verification
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("malformed token")]
+    Malformed,
+
+    #[error("invalid token signature")]
+    InvalidSignature,
+
+    #[error("token expired")]
+    Expired,
+}
+
+/// Check that a secret is long enough to sign tokens with
+pub fn validate_secret(secret: &[u8]) -> Result<(), String> {
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(format!(
+            "token secret must be at least {} bytes, got {}",
+            MIN_SECRET_LEN,
+            secret.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Sign claims into a token
+pub fn issue_token(secret: &[u8], claims: &TokenClaims) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+    let signing_input = format!("{}.{}", TOKEN_PREFIX, payload);
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, &signing_input).finalize().into_bytes());
+
+    format!("{}.{}", signing_input, signature)
+}
+
+/// Verify a token's signature and expiry and return its claims
+pub fn verify_token(secret: &[u8], token: &str, now: i64) -> Result<TokenClaims, TokenError> {
+    let (signing_input, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+    let payload = signing_input
+        .strip_prefix(TOKEN_PREFIX)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .ok_or(TokenError::Malformed)?;
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| TokenError::Malformed)?;
+    // verify_slice compares in constant time
+    mac(secret, signing_input)
+        .verify_slice(&signature)
+        .map_err(|_| TokenError::InvalidSignature)?;
+
+    let claims: TokenClaims = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or(TokenError::Malformed)?;
+
+    if claims.exp <= now {
+        return Err(TokenError::Expired);
+    }
+
+    Ok(claims)
+}
+
+fn mac(secret: &[u8], signing_input: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(signing_input.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> TokenClaims {
+        TokenClaims {
+            sub: "edge-box-1".to_string(),
+            hosts: vec!["*.edge.example.com".to_string()],
+            iat: 1_700_000_000,
+            exp: 1_700_003_600,
+        }
+    }
+
+    #[test]
+    fn test_issue_and_verify_roundtrip() {
+        let token = issue_token(b"secret", &claims());
+        assert!(token.starts_with("mesh1."));
+        assert_eq!(verify_token(b"secret", &token, 1_700_000_100), Ok(claims()));
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_tokens() {
+        let token = issue_token(b"secret", &claims());
+
+        assert_eq!(
+            verify_token(b"other-secret", &token, 1_700_000_100),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_token(b"secret", &token, 1_700_003_600),
+            Err(TokenError::Expired)
+        );
+
+        // Swapping in a different payload invalidates the signature
+        let forged_claims = TokenClaims {
+            hosts: vec!["*".to_string()],
+            ..claims()
+        };
+        let forged_payload = issue_token(b"attacker", &forged_claims);
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let (forged_input, _) = forged_payload.rsplit_once('.').unwrap();
+        assert_eq!(
+            verify_token(
+                b"secret",
+                &format!("{}.{}", forged_input, signature),
+                1_700_000_100
+            ),
+            Err(TokenError::InvalidSignature)
+        );
+
+        assert_eq!(
+            verify_token(b"secret", "not-a-token", 1_700_000_100),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_validate_secret_rejects_short_secrets() {
+        assert!(validate_secret(b"").is_err());
+        assert!(validate_secret(&[b'k'; MIN_SECRET_LEN - 1]).is_err());
+        assert!(validate_secret(&[b'k'; MIN_SECRET_LEN]).is_ok());
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use super::{IamAuthRequest, IamAuthResponse};
+use super::{IamAuthRequest, IamAuthResponse, JwtAuthRequest, TokenAuthRequest};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
@@ -24,6 +24,63 @@ pub struct ServiceRegistration {
     pub task_arn: String,
     pub attributes: HashMap<String, String>,
     pub health_check_path: Option<String>,
+    /// Paths served under `host`; none means every path not claimed by another
+    /// registration
+    #[serde(default)]
+    pub paths: Vec<PathRoute>,
+}
+
+/// How a `PathRoute` matches the request path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathMatchType {
+    /// The whole path equals `path`
+    Exact,
+    /// The path is `path` or continues it with a `/` segment
+    #[default]
+    Prefix,
+    /// `path` is a regular expression matching the whole path
+    Regex,
+}
+
+/// A path a registration serves, matched against the request path without
+/// its query string
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PathRoute {
+    #[serde(rename = "match", default)]
+    pub match_type: PathMatchType,
+    pub path: String,
+}
+
+/// Response header naming the timeout that produced a 504
+pub const MESH_TIMEOUT_HEADER: &str = "x-mesh-timeout";
+
+/// Response header counting the instances the ingress tried for a request
+pub const MESH_ATTEMPTS_HEADER: &str = "x-mesh-attempts";
+
+/// Timeouts applied to a proxied request, in milliseconds
+///
+/// `connect_ms` and `first_byte_ms` are enforced by the client against its local
+/// service; `total_ms` is enforced by the ingress while waiting for the response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyTimeouts {
+    #[serde(default)]
+    pub connect_ms: Option<u64>,
+    #[serde(default)]
+    pub first_byte_ms: Option<u64>,
+    #[serde(default)]
+    pub total_ms: Option<u64>,
+}
+
+impl ProxyTimeouts {
+    /// Fill unset fields from `fallback`
+    pub fn or(self, fallback: ProxyTimeouts) -> ProxyTimeouts {
+        ProxyTimeouts {
+            connect_ms: self.connect_ms.or(fallback.connect_ms),
+            first_byte_ms: self.first_byte_ms.or(fallback.first_byte_ms),
+            total_ms: self.total_ms.or(fallback.total_ms),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +91,8 @@ pub struct ProxyRequest {
     pub headers: HashMap<String, String>,
     pub body: Option<Vec<u8>>,
     pub target_host: String,
+    #[serde(default)]
+    pub timeouts: Option<ProxyTimeouts>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -88,11 +147,21 @@ pub enum IngressMessage {
 
     // Legacy messages (for backward compatibility during transition)
     IamAuth(IamAuthRequest),
+    TokenAuth(TokenAuthRequest),
+    JwtAuth(JwtAuthRequest),
     ServiceRegistration(ServiceRegistration),
     ServiceDeregistration {
         id: Uuid,
     },
     IamAuthResponse(IamAuthResponse),
+    /// Per-connection nonce the client must sign into its presigned STS URL
+    AuthChallenge {
+        nonce: String,
+    },
+    /// Server demands a fresh auth handshake, signing `nonce` like `AuthChallenge`
+    ReauthRequest {
+        nonce: String,
+    },
     RegistrationAck {
         id: Uuid,
         success: bool,
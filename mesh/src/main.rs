@@ -7,8 +7,9 @@ mod client;
 mod commands;
 mod common;
 mod server;
+mod token;
 
-use commands::{ClientCommand, ServerCommand};
+use commands::{ClientCommand, ServerCommand, TokenCommand};
 
 #[derive(Parser)]
 #[command(
@@ -35,7 +36,9 @@ enum Commands {
     /// Start the ingress server
     Server(ServerCommand),
     /// Start the Anywhere Mesh client
-    Client(ClientCommand),
+    Client(Box<ClientCommand>),
+    /// Manage mesh auth tokens
+    Token(TokenCommand),
 }
 
 #[tokio::main]
@@ -45,14 +48,26 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
+    // An audit log on stdout gets stdout to itself
+    let audit_to_stdout = match &cli.command {
+        Commands::Server(server_cmd) => server::audit_to_stdout(server_cmd),
+        _ => false,
+    };
+
     // Initialize logging
     let log_level = if cli.verbose { "debug" } else { &cli.log_level };
     std::env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt().init();
+    if audit_to_stdout {
+        tracing_subscriber::fmt().with_writer(io::stderr).init();
+    } else {
+        tracing_subscriber::fmt().init();
+    }
 
-    // Print banner
-    print_banner();
-    let _ = io::stdout().flush();
+    // Print banner (token output is meant to be captured by scripts, so keep it clean)
+    if !matches!(cli.command, Commands::Token(_)) && !audit_to_stdout {
+        print_banner();
+        let _ = io::stdout().flush();
+    }
 
     match cli.command {
         Commands::Server(server_cmd) => {
@@ -61,7 +76,10 @@ async fn main() -> Result<()> {
         }
         Commands::Client(client_cmd) => {
             info!("Starting Anywhere Mesh Client");
-            client::run(client_cmd).await?;
+            client::run(*client_cmd).await?;
+        }
+        Commands::Token(token_cmd) => {
+            token::run(token_cmd)?;
         }
     }
 
@@ -0,0 +1,318 @@
+//! Per-route API key and basic-auth enforcement at the ingress.
+
+use crate::common::config::RouteAccessConfig;
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hyper::{Body, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+
+/// Check that every API key digest is a hex SHA-256 and every password hash
+/// is bcrypt
+pub fn validate(config: &RouteAccessConfig) -> Result<()> {
+    if config.api_keys.is_none() && config.basic_auth.is_none() {
+        anyhow::bail!("access needs api_keys or basic_auth");
+    }
+
+    let api_key_digests = config
+        .api_keys
+        .iter()
+        .flat_map(|api_keys| api_keys.key_sha256.iter());
+    for digest in api_key_digests {
+        if digest.len() != 64 || hex::decode(digest).is_err() {
+            anyhow::bail!("{} is not a hex SHA-256 digest", digest);
+        }
+    }
+    let password_hashes = config
+        .basic_auth
+        .iter()
+        .flat_map(|basic_auth| basic_auth.users.iter());
+    for (username, hash) in password_hashes {
+        if hash.parse::<bcrypt::HashParts>().is_err() {
+            anyhow::bail!(
+                "Password of basic auth user {} is not a bcrypt hash",
+                username
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether the request carries a valid API key or basic-auth credentials
+pub async fn authorize(
+    config: &RouteAccessConfig,
+    headers: &hyper::HeaderMap,
+    uri: &hyper::Uri,
+) -> bool {
+    if let Some(api_keys) = &config.api_keys {
+        let from_header = headers
+            .get(api_keys.header.as_str())
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        let from_query = api_keys
+            .query_param
+            .as_deref()
+            .and_then(|name| query_param(uri, name));
+
+        if from_header
+            .into_iter()
+            .chain(from_query)
+            .any(|key| digest_matches(&key, &api_keys.key_sha256))
+        {
+            return true;
+        }
+    }
+
+    if let Some(basic_auth) = &config.basic_auth {
+        if let Some((username, password)) = basic_credentials(headers) {
+            // An unknown user is checked against another user's hash, so the
+            // answer takes as long as for a wrong password
+            let (hash, known) = match basic_auth.users.get(&username) {
+                Some(hash) => (hash.clone(), true),
+                None => match basic_auth.users.values().next() {
+                    Some(hash) => (hash.clone(), false),
+                    None => return false,
+                },
+            };
+            // bcrypt is slow on purpose; keep it off the request workers
+            let verified = tokio::task::spawn_blocking(move || {
+                bcrypt::verify(&password, &hash).unwrap_or(false)
+            })
+            .await
+            .unwrap_or(false);
+            return known && verified;
+        }
+    }
+
+    false
+}
+
+/// 401 challenging for the credentials the route accepts
+pub fn unauthorized_response(config: &RouteAccessConfig) -> Response<Body> {
+    let mut response = Response::builder().status(StatusCode::UNAUTHORIZED);
+    if let Some(basic_auth) = &config.basic_auth {
+        response = response.header(
+            "www-authenticate",
+            format!(
+                "Basic realm=\"{}\", charset=\"UTF-8\"",
+                basic_auth.realm.replace('"', "")
+            ),
+        );
+    }
+    if let Some(api_keys) = &config.api_keys {
+        response = response.header(
+            "www-authenticate",
+            format!("ApiKey header=\"{}\"", api_keys.header),
+        );
+    }
+    response.body(Body::from("Unauthorized")).unwrap()
+}
+
+/// Drop the route's credentials so they never reach the backend
+pub fn strip_credentials(
+    config: &RouteAccessConfig,
+    headers: &mut std::collections::HashMap<String, String>,
+    path: &str,
+) -> String {
+    if config.basic_auth.is_some() {
+        headers.remove("authorization");
+    }
+
+    let Some(api_keys) = &config.api_keys else {
+        return path.to_string();
+    };
+    headers.remove(&api_keys.header.to_lowercase());
+
+    let (Some(name), Some((path_only, query))) = (&api_keys.query_param, path.split_once('?'))
+    else {
+        return path.to_string();
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split_once('=').map_or(*pair, |(k, _)| k);
+            percent_decode_str(key).decode_utf8_lossy() != name.as_str()
+        })
+        .collect();
+    if kept.is_empty() {
+        path_only.to_string()
+    } else {
+        format!("{}?{}", path_only, kept.join("&"))
+    }
+}
+
+fn query_param(uri: &hyper::Uri, name: &str) -> Option<String> {
+    uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (percent_decode_str(key).decode_utf8_lossy() == name)
+            .then(|| percent_decode_str(value).decode_utf8_lossy().into_owned())
+    })
+}
+
+fn basic_credentials(headers: &hyper::HeaderMap) -> Option<(String, String)> {
+    let value = headers.get("authorization")?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Compare the secret's digest against every configured digest without
+/// short-circuiting on the first differing byte
+fn digest_matches(secret: &str, digests: &[String]) -> bool {
+    let digest = Sha256::digest(secret.as_bytes());
+    digests.iter().fold(false, |matched, expected| {
+        let equal = hex::decode(expected).is_ok_and(|expected| {
+            expected.len() == digest.len()
+                && expected
+                    .iter()
+                    .zip(digest.iter())
+                    .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        });
+        matched | equal
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::{ApiKeyAccessConfig, BasicAuthConfig};
+    use std::collections::HashMap;
+
+    fn sha256_hex(secret: &str) -> String {
+        hex::encode(Sha256::digest(secret.as_bytes()))
+    }
+
+    fn config() -> RouteAccessConfig {
+        RouteAccessConfig {
+            api_keys: Some(ApiKeyAccessConfig {
+                header: "x-api-key".to_string(),
+                query_param: Some("api_key".to_string()),
+                key_sha256: vec![sha256_hex("key-1"), sha256_hex("key-2")],
+            }),
+            basic_auth: Some(BasicAuthConfig {
+                realm: "tools".to_string(),
+                users: HashMap::from([("alice".to_string(), bcrypt::hash("s3cret", 4).unwrap())]),
+            }),
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> hyper::HeaderMap {
+        let mut headers = hyper::HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                hyper::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_authorize_api_keys_and_basic_auth() {
+        let config = config();
+        let uri: hyper::Uri = "/reports".parse().unwrap();
+
+        assert!(authorize(&config, &headers(&[("x-api-key", "key-2")]), &uri).await);
+        assert!(
+            authorize(
+                &config,
+                &headers(&[]),
+                &"/reports?page=2&api_key=key-1".parse().unwrap()
+            )
+            .await
+        );
+        // "alice:s3cret"
+        assert!(
+            authorize(
+                &config,
+                &headers(&[("authorization", "Basic YWxpY2U6czNjcmV0")]),
+                &uri
+            )
+            .await
+        );
+
+        assert!(!authorize(&config, &headers(&[]), &uri).await);
+        assert!(!authorize(&config, &headers(&[("x-api-key", "key-3")]), &uri).await);
+        // "alice:wrong"
+        assert!(
+            !authorize(
+                &config,
+                &headers(&[("authorization", "Basic YWxpY2U6d3Jvbmc=")]),
+                &uri
+            )
+            .await
+        );
+        // "bob:s3cret"
+        assert!(
+            !authorize(
+                &config,
+                &headers(&[("authorization", "Basic Ym9iOnMzY3JldA==")]),
+                &uri
+            )
+            .await
+        );
+    }
+
+    #[test]
+    fn test_unauthorized_response_challenges() {
+        let response = unauthorized_response(&config());
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let challenges: Vec<&str> = response
+            .headers()
+            .get_all("www-authenticate")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            challenges,
+            vec![
+                "Basic realm=\"tools\", charset=\"UTF-8\"",
+                "ApiKey header=\"x-api-key\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_credentials() {
+        let config = config();
+        let mut headers = HashMap::from([
+            ("authorization".to_string(), "Basic abc".to_string()),
+            ("x-api-key".to_string(), "key-1".to_string()),
+            ("accept".to_string(), "*/*".to_string()),
+        ]);
+
+        let path = strip_credentials(&config, &mut headers, "/reports?page=2&api_key=key-1");
+        assert_eq!(path, "/reports?page=2");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(
+            strip_credentials(&config, &mut headers, "/reports?api_key=key-1"),
+            "/reports"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_plaintext_secrets() {
+        assert!(validate(&config()).is_ok());
+        assert!(validate(&RouteAccessConfig::default()).is_err());
+
+        let mut config = config();
+        config.api_keys.as_mut().unwrap().key_sha256 = vec!["key-1".to_string()];
+        assert!(validate(&config).is_err());
+
+        // Passwords need a slow, salted hash
+        let mut config = self::config();
+        config
+            .basic_auth
+            .as_mut()
+            .unwrap()
+            .users
+            .insert("bob".to_string(), sha256_hex("s3cret"));
+        assert!(validate(&config).is_err());
+    }
+}
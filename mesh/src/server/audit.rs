@@ -0,0 +1,203 @@
+//! Audit log of security-relevant events.
+//!
+//! Events are written as JSON lines, separate from the tracing output. Every
+//! record carries `timestamp` (RFC 3339, UTC), `version` (the schema version)
+//! and `event` (the event type); the remaining fields depend on the type and
+//! are always present, `null` when unknown.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::error;
+use uuid::Uuid;
+
+/// Version of the record schema; bumped on incompatible changes
+pub const AUDIT_SCHEMA_VERSION: u32 = 1;
+
+/// A security-relevant event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    AuthSuccess {
+        connection_id: Uuid,
+        /// `IAM`, `token`, `JWT` or `client_certificate`
+        method: String,
+        arn: String,
+        account_id: String,
+        principal_type: String,
+        source_ip: Option<IpAddr>,
+        /// Whether this renewed an already authenticated connection
+        reauth: bool,
+    },
+    AuthFailure {
+        connection_id: Uuid,
+        method: String,
+        /// Identity the credential resolved to, if it got that far
+        arn: Option<String>,
+        account_id: Option<String>,
+        source_ip: Option<IpAddr>,
+        reason: String,
+        reauth: bool,
+    },
+    Registration {
+        connection_id: Uuid,
+        arn: Option<String>,
+        service_name: String,
+        host: String,
+        cluster_name: String,
+        accepted: bool,
+        /// Why the registration was refused
+        reason: Option<String>,
+    },
+    Deregistration {
+        connection_id: Uuid,
+        arn: Option<String>,
+        service_name: String,
+        host: String,
+        /// `requested` by the client, `disconnected`, or `refused` when the
+        /// connection asked to remove another connection's registration
+        reason: String,
+    },
+    HostConflict {
+        connection_id: Uuid,
+        arn: Option<String>,
+        host: String,
+        service_name: String,
+        cluster_name: String,
+        existing_connection_id: Uuid,
+        existing_service_name: String,
+        existing_cluster_name: String,
+        /// Whether the new registration was refused because of the conflict
+        rejected: bool,
+    },
+    ForcedDisconnect {
+        connection_id: Uuid,
+        arn: Option<String>,
+        source_ip: Option<IpAddr>,
+        reason: String,
+    },
+    ConfigReload {
+        success: bool,
+        allowed_role_arns: Vec<String>,
+        error: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    version: u32,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Writes audit events to a file or stdout; records nothing when disabled
+pub struct AuditLog {
+    sink: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AuditLog {
+    pub fn disabled() -> Self {
+        Self { sink: None }
+    }
+
+    /// Append to the file at `path`, or write to stdout when `path` is `-`
+    pub fn open(path: &Path) -> Result<Self> {
+        let sink: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open audit log {}", path.display()))?,
+            )
+        };
+        Ok(Self {
+            sink: Some(Mutex::new(sink)),
+        })
+    }
+
+    /// Write one event as a JSON line
+    pub fn record(&self, event: AuditEvent) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+
+        let record = AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            version: AUDIT_SCHEMA_VERSION,
+            event: &event,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit event: {}", e);
+                return;
+            }
+        };
+
+        let mut sink = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = writeln!(sink, "{}", line).and_then(|_| sink.flush()) {
+            error!("Failed to write audit event: {}", e);
+        }
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_audit_records_are_json_lines() {
+        let path = std::env::temp_dir().join(format!("mesh-audit-{}.log", Uuid::new_v4()));
+        let audit = AuditLog::open(&path).unwrap();
+        let connection_id = Uuid::new_v4();
+
+        audit.record(AuditEvent::AuthFailure {
+            connection_id,
+            method: "IAM".to_string(),
+            arn: None,
+            account_id: None,
+            source_ip: Some("10.0.0.7".parse().unwrap()),
+            reason: "Role not allowed".to_string(),
+            reauth: false,
+        });
+        audit.record(AuditEvent::ConfigReload {
+            success: true,
+            allowed_role_arns: vec!["*".to_string()],
+            error: None,
+        });
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(records[0]["event"], "auth_failure");
+        assert_eq!(records[0]["version"], AUDIT_SCHEMA_VERSION);
+        assert_eq!(records[0]["connection_id"], connection_id.to_string());
+        assert_eq!(records[0]["source_ip"], "10.0.0.7");
+        assert_eq!(records[0]["reason"], "Role not allowed");
+        assert!(records[0]["arn"].is_null());
+        assert!(records[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+
+        assert_eq!(records[1]["event"], "config_reload");
+        assert_eq!(records[1]["allowed_role_arns"][0], "*");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,508 @@
+mod jwt;
+mod presigned;
+mod sts;
+mod token;
+
+pub use jwt::JwtAuthService;
+pub use presigned::{sts_endpoint_origin, PresignedUrlPolicy};
+use presigned::{validate_presigned_url, PresignedUrlError};
+use sts::{parse_caller_identity, parse_principal_arn, StsError};
+pub use token::TokenAuthService;
+
+use crate::common::auth::{
+    IamAuthRequest, IamAuthResponse, IamIdentity, JwtAuthRequest, TokenAuthRequest,
+    MESH_AUTH_NONCE_HEADER,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use hyper::{Body, Client as HttpClient, Request};
+use hyper_tls::HttpsConnector;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Credentials presented by a connecting client, one variant per auth message
+#[derive(Debug, Clone)]
+pub enum AuthRequest {
+    Iam {
+        request: IamAuthRequest,
+        /// Nonce the server issued to the connection, which the presigned URL must sign
+        challenge: Option<String>,
+    },
+    Token(TokenAuthRequest),
+    Jwt(JwtAuthRequest),
+}
+
+impl AuthRequest {
+    /// Human readable name of the auth method, for logs and errors
+    pub fn method(&self) -> &'static str {
+        match self {
+            AuthRequest::Iam { .. } => "IAM",
+            AuthRequest::Token(_) => "token",
+            AuthRequest::Jwt(_) => "JWT",
+        }
+    }
+}
+
+/// Service for authenticating client connections
+#[async_trait]
+pub trait AuthService: Send + Sync {
+    /// Authenticate the presented credentials; backends reject methods they do not handle
+    async fn authenticate(&self, auth_request: &AuthRequest) -> IamAuthResponse;
+}
+
+/// Build a failed authentication response
+pub(crate) fn auth_failure(error: impl Into<String>) -> IamAuthResponse {
+    IamAuthResponse {
+        success: false,
+        error: Some(error.into()),
+        identity: None,
+    }
+}
+
+/// Runs several auth backends side by side, picking one by auth message variant
+#[derive(Default, Clone)]
+pub struct CompositeAuthService {
+    iam: Option<Arc<dyn AuthService>>,
+    token: Option<Arc<dyn AuthService>>,
+    jwt: Option<Arc<dyn AuthService>>,
+}
+
+impl CompositeAuthService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Backend for `IamAuth` messages
+    pub fn with_iam(mut self, backend: Arc<dyn AuthService>) -> Self {
+        self.iam = Some(backend);
+        self
+    }
+
+    /// Backend for `TokenAuth` messages
+    pub fn with_token(mut self, backend: Arc<dyn AuthService>) -> Self {
+        self.token = Some(backend);
+        self
+    }
+
+    /// Backend for `JwtAuth` messages
+    pub fn with_jwt(mut self, backend: Arc<dyn AuthService>) -> Self {
+        self.jwt = Some(backend);
+        self
+    }
+}
+
+#[async_trait]
+impl AuthService for CompositeAuthService {
+    async fn authenticate(&self, auth_request: &AuthRequest) -> IamAuthResponse {
+        let backend = match auth_request {
+            AuthRequest::Iam { .. } => &self.iam,
+            AuthRequest::Token(_) => &self.token,
+            AuthRequest::Jwt(_) => &self.jwt,
+        };
+
+        match backend {
+            Some(backend) => backend.authenticate(auth_request).await,
+            None => {
+                warn!(
+                    "Rejecting {} auth: backend not enabled",
+                    auth_request.method()
+                );
+                auth_failure(format!(
+                    "{} authentication is not enabled",
+                    auth_request.method()
+                ))
+            }
+        }
+    }
+}
+
+/// Default implementation of AuthService
+pub struct DefaultAuthService {
+    http_client: HttpClient<HttpsConnector<hyper::client::HttpConnector>>,
+    allowed_role_patterns: RwLock<Vec<String>>,
+    skip_validation: bool,
+    presigned_url_policy: PresignedUrlPolicy,
+}
+
+impl DefaultAuthService {
+    pub fn new(
+        http_client: HttpClient<HttpsConnector<hyper::client::HttpConnector>>,
+        allowed_role_patterns: Vec<String>,
+        skip_validation: bool,
+    ) -> Self {
+        Self {
+            http_client,
+            allowed_role_patterns: RwLock::new(allowed_role_patterns),
+            skip_validation,
+            presigned_url_policy: PresignedUrlPolicy::default(),
+        }
+    }
+
+    /// Override the limits applied to client-supplied presigned URLs
+    pub fn with_presigned_url_policy(mut self, policy: PresignedUrlPolicy) -> Self {
+        self.presigned_url_policy = policy;
+        self
+    }
+
+    /// Replace the allow-list; applies to every authentication from now on.
+    /// Returns whether it differs from the previous one
+    pub fn set_allowed_role_patterns(&self, patterns: Vec<String>) -> bool {
+        let mut current = self
+            .allowed_role_patterns
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        if *current == patterns {
+            return false;
+        }
+        *current = patterns;
+        true
+    }
+
+    /// Check if an identity's caller ARN or underlying role ARN matches any allowed pattern
+    fn is_role_allowed(&self, identity: &IamIdentity) -> bool {
+        let patterns = self
+            .allowed_role_patterns
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        if patterns.is_empty() || patterns.contains(&"*".to_string()) {
+            return true;
+        }
+
+        identity.principal_arns().any(|arn| {
+            patterns
+                .iter()
+                .any(|pattern| Self::matches_arn_pattern(arn, pattern))
+        })
+    }
+
+    /// Check if an ARN matches a pattern (supports * wildcards)
+    pub fn matches_arn_pattern(arn: &str, pattern: &str) -> bool {
+        if pattern == "*" {
+            return true;
+        }
+
+        if !pattern.contains('*') {
+            return arn == pattern;
+        }
+
+        // Simple wildcard matching
+        let pattern_parts: Vec<&str> = pattern.split('*').collect();
+        if pattern_parts.is_empty() {
+            return false;
+        }
+
+        let mut arn_remaining = arn;
+
+        for (i, part) in pattern_parts.iter().enumerate() {
+            if part.is_empty() {
+                continue;
+            }
+
+            if i == 0 && !arn_remaining.starts_with(part) {
+                return false;
+            }
+
+            if let Some(pos) = arn_remaining.find(part) {
+                if i == 0 && pos != 0 {
+                    return false;
+                }
+                arn_remaining = &arn_remaining[pos + part.len()..];
+            } else {
+                return false;
+            }
+        }
+
+        pattern.ends_with('*') || arn_remaining.is_empty()
+    }
+
+    /// Build an identity from an STS response body, deriving the principal from its ARN
+    fn identity_from_sts_body(body: &str) -> Result<IamIdentity, StsError> {
+        let caller = parse_caller_identity(body)?;
+        let principal = parse_principal_arn(&caller.arn)?;
+
+        Ok(IamIdentity {
+            arn: caller.arn,
+            account_id: caller.account,
+            user_id: caller.user_id,
+            principal_type: principal.principal_type.as_str().to_string(),
+            role_arn: principal.role_arn,
+            session_name: principal.session_name,
+            expires_at: None,
+            allowed_hosts: None,
+        })
+    }
+
+    /// Validate IAM identity using presigned STS URL
+    ///
+    /// The URL must sign the `x-mesh-auth-nonce` header; it is fetched with the
+    /// connection's challenge as that header, so a URL captured from another
+    /// connection fails the STS signature check.
+    async fn validate_with_sts(
+        &self,
+        presigned_url: &str,
+        region: &str,
+        challenge: &str,
+    ) -> IamAuthResponse {
+        let request = validate_presigned_url(
+            presigned_url,
+            region,
+            &self.presigned_url_policy,
+            Utc::now(),
+        )
+        .and_then(|url| {
+            let expires_at = url.assumed_session_expiry(&self.presigned_url_policy);
+            Request::get(url.uri)
+                .header(MESH_AUTH_NONCE_HEADER, challenge)
+                .body(Body::empty())
+                .map(|request| (request, expires_at))
+                .map_err(|e| PresignedUrlError::Malformed(e.to_string()))
+        });
+
+        match request {
+            Ok((request, expires_at)) => match self.http_client.request(request).await {
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = hyper::body::to_bytes(resp.into_body())
+                        .await
+                        .unwrap_or_default();
+
+                    match Self::identity_from_sts_body(&String::from_utf8_lossy(&body)) {
+                        Ok(mut identity) => {
+                            identity.expires_at = expires_at;
+                            if self.is_role_allowed(&identity) {
+                                info!(
+                                    "IAM auth successful for ARN: {} ({})",
+                                    identity.arn, identity.principal_type
+                                );
+                                IamAuthResponse {
+                                    success: true,
+                                    error: None,
+                                    identity: Some(identity),
+                                }
+                            } else {
+                                warn!("Role not allowed: {}", identity.arn);
+                                auth_failure("Role not allowed")
+                            }
+                        }
+                        Err(StsError::Service { code, message }) => {
+                            warn!("STS returned {} ({}): {}", code, status, message);
+                            auth_failure(format!(
+                                "STS call failed with status {}: {}: {}",
+                                status, code, message
+                            ))
+                        }
+                        Err(e) if !status.is_success() => {
+                            warn!("STS call returned non-success status {}: {}", status, e);
+                            auth_failure(format!("STS call failed with status: {}", status))
+                        }
+                        Err(e) => {
+                            warn!("Failed to parse STS identity from response: {}", e);
+                            auth_failure(format!("Failed to parse STS identity: {}", e))
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed STS presigned call: {}", e);
+                    IamAuthResponse {
+                        success: false,
+                        error: Some(format!("STS call failed: {}", e)),
+                        identity: None,
+                    }
+                }
+            },
+            Err(e) => {
+                warn!("Rejected presigned URL: {}", e);
+                IamAuthResponse {
+                    success: false,
+                    error: Some(format!("Invalid presigned URL: {}", e)),
+                    identity: None,
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AuthService for DefaultAuthService {
+    async fn authenticate(&self, auth_request: &AuthRequest) -> IamAuthResponse {
+        let AuthRequest::Iam {
+            request: auth_request,
+            challenge,
+        } = auth_request
+        else {
+            return auth_failure(format!(
+                "{} authentication is not supported by the IAM backend",
+                auth_request.method()
+            ));
+        };
+
+        if self.skip_validation {
+            info!("IAM validation skipped (skip_validation=true)");
+            return IamAuthResponse {
+                success: true,
+                error: None,
+                identity: Some(IamIdentity {
+                    arn: "arn:aws:iam::000000000000:role/skipped-validation".to_string(),
+                    account_id: "000000000000".to_string(),
+                    user_id: "skipped-validation".to_string(),
+                    principal_type: "AssumedRole".to_string(),
+                    role_arn: None,
+                    session_name: None,
+                    expires_at: None,
+                    allowed_hosts: None,
+                }),
+            };
+        }
+
+        let Some(challenge) = challenge else {
+            warn!("IamAuth received without an outstanding auth challenge");
+            return auth_failure("No auth challenge was issued to this connection");
+        };
+
+        if let Some(presigned_url) = &auth_request.presigned_url {
+            self.validate_with_sts(presigned_url, &auth_request.region, challenge)
+                .await
+        } else {
+            warn!("IamAuth received without presigned_url");
+            IamAuthResponse {
+                success: false,
+                error: Some("No presigned URL provided".to_string()),
+                identity: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(arn: &str) -> IamIdentity {
+        IamIdentity {
+            arn: arn.to_string(),
+            account_id: "123456789012".to_string(),
+            user_id: "AROAEXAMPLE".to_string(),
+            principal_type: "User".to_string(),
+            role_arn: None,
+            session_name: None,
+            expires_at: None,
+            allowed_hosts: None,
+        }
+    }
+
+    #[test]
+    fn test_arn_pattern_matching() {
+        // Test exact match
+        assert!(DefaultAuthService::matches_arn_pattern(
+            "arn:aws:iam::123456789012:role/MyRole",
+            "arn:aws:iam::123456789012:role/MyRole"
+        ));
+
+        // Test wildcard match
+        assert!(DefaultAuthService::matches_arn_pattern(
+            "arn:aws:iam::123456789012:role/MyRole",
+            "arn:aws:iam::*:role/MyRole"
+        ));
+
+        // Test no match
+        assert!(!DefaultAuthService::matches_arn_pattern(
+            "arn:aws:iam::123456789012:role/MyRole",
+            "arn:aws:iam::123456789012:role/OtherRole"
+        ));
+    }
+
+    #[test]
+    fn test_role_allowed() {
+        let auth_service = DefaultAuthService::new(
+            HttpClient::builder().build(HttpsConnector::new()),
+            vec!["arn:aws:iam::*:role/MyRole".to_string()],
+            false,
+        );
+
+        assert!(auth_service.is_role_allowed(&identity("arn:aws:iam::123456789012:role/MyRole")));
+        assert!(
+            !auth_service.is_role_allowed(&identity("arn:aws:iam::123456789012:role/OtherRole"))
+        );
+    }
+
+    #[test]
+    fn test_role_allowed_by_underlying_role_arn() {
+        let auth_service = DefaultAuthService::new(
+            HttpClient::builder().build(HttpsConnector::new()),
+            vec!["arn:aws:iam::123456789012:role/MeshClientRole".to_string()],
+            false,
+        );
+
+        let body = "<GetCallerIdentityResponse><GetCallerIdentityResult>\
+            <Arn>arn:aws:sts::123456789012:assumed-role/MeshClientRole/i-0abc</Arn>\
+            <UserId>AROAEXAMPLE:i-0abc</UserId><Account>123456789012</Account>\
+            </GetCallerIdentityResult></GetCallerIdentityResponse>";
+        let identity = DefaultAuthService::identity_from_sts_body(body).unwrap();
+
+        assert_eq!(identity.principal_type, "AssumedRole");
+        assert_eq!(identity.session_name.as_deref(), Some("i-0abc"));
+        assert!(auth_service.is_role_allowed(&identity));
+
+        let other = DefaultAuthService::identity_from_sts_body(
+            &body.replace("MeshClientRole", "OtherRole"),
+        )
+        .unwrap();
+        assert!(!auth_service.is_role_allowed(&other));
+    }
+
+    #[test]
+    fn test_allow_list_update() {
+        let auth_service = DefaultAuthService::new(
+            HttpClient::builder().build(HttpsConnector::new()),
+            vec!["arn:aws:iam::*:role/MyRole".to_string()],
+            false,
+        );
+        let my_role = identity("arn:aws:iam::123456789012:role/MyRole");
+        assert!(auth_service.is_role_allowed(&my_role));
+
+        assert!(
+            !auth_service.set_allowed_role_patterns(vec!["arn:aws:iam::*:role/MyRole".to_string()])
+        );
+        assert!(auth_service
+            .set_allowed_role_patterns(vec!["arn:aws:iam::*:role/OtherRole".to_string()]));
+        assert!(!auth_service.is_role_allowed(&my_role));
+    }
+
+    #[tokio::test]
+    async fn test_iam_auth_requires_challenge() {
+        let auth_service = DefaultAuthService::new(
+            HttpClient::builder().build(HttpsConnector::new()),
+            vec!["*".to_string()],
+            false,
+        );
+
+        let response = auth_service
+            .authenticate(&AuthRequest::Iam {
+                request: IamAuthRequest {
+                    presigned_url: Some("https://sts.amazonaws.com/".to_string()),
+                    region: "us-east-1".to_string(),
+                    arn: None,
+                    account_id: None,
+                    user_id: None,
+                },
+                challenge: None,
+            })
+            .await;
+        assert!(!response.success);
+        assert_eq!(
+            response.error.as_deref(),
+            Some("No auth challenge was issued to this connection")
+        );
+    }
+
+    #[test]
+    fn test_wildcard_all_allowed() {
+        let auth_service = DefaultAuthService::new(
+            HttpClient::builder().build(HttpsConnector::new()),
+            vec!["*".to_string()],
+            false,
+        );
+
+        assert!(auth_service.is_role_allowed(&identity("arn:aws:iam::123456789012:role/AnyRole")));
+    }
+}
@@ -0,0 +1,630 @@
+//! Validation of client-supplied presigned STS `GetCallerIdentity` URLs.
+//!
+//! The ingress fetches the presigned URL to learn the caller's identity, so the
+//! URL must be checked before any request is made: otherwise a client could
+//! point the ingress at an arbitrary (internal) address.
+
+use crate::common::auth::MESH_AUTH_NONCE_HEADER;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hyper::Uri;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// Region that signs requests to the global `sts.amazonaws.com` endpoint
+const GLOBAL_STS_REGION: &str = "us-east-1";
+
+/// Reasons a presigned URL is rejected before it is fetched
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PresignedUrlError {
+    #[error("malformed URL: {0}")]
+    Malformed(String),
+
+    #[error("scheme must be https, got {0}")]
+    InsecureScheme(String),
+
+    #[error("host {0} is not an official STS endpoint")]
+    UntrustedHost(String),
+
+    #[error("URL must not carry a port or user info: {0}")]
+    UnexpectedAuthority(String),
+
+    #[error("URL path must be /, got {0}")]
+    UnexpectedPath(String),
+
+    #[error("query parameter {0} is repeated")]
+    DuplicateParameter(String),
+
+    #[error("missing query parameter {0}")]
+    MissingParameter(&'static str),
+
+    #[error("signed headers must include {0}")]
+    UnsignedChallenge(&'static str),
+
+    #[error("Action must be GetCallerIdentity, got {0}")]
+    WrongAction(String),
+
+    #[error("unsupported signing algorithm {0}")]
+    UnsupportedAlgorithm(String),
+
+    #[error("malformed credential scope {0}")]
+    InvalidCredentialScope(String),
+
+    #[error("signed for region {signed}, but the request declares {declared}")]
+    RegionMismatch { signed: String, declared: String },
+
+    #[error("host {host} does not serve signing region {signed}")]
+    HostRegionMismatch { host: String, signed: String },
+
+    #[error("malformed X-Amz-Date {0}")]
+    InvalidDate(String),
+
+    #[error("malformed X-Amz-Expires {0}")]
+    InvalidExpires(String),
+
+    #[error("X-Amz-Expires of {expires}s exceeds the maximum of {max}s")]
+    ExpiresTooLong { expires: u64, max: u64 },
+
+    #[error("X-Amz-Date is {0}s in the future")]
+    DateInFuture(i64),
+
+    #[error("URL expired {0}s ago")]
+    Expired(i64),
+}
+
+/// Limits applied to presigned URLs
+#[derive(Debug, Clone)]
+pub struct PresignedUrlPolicy {
+    /// Largest accepted `X-Amz-Expires`, in seconds
+    pub max_expires_secs: u64,
+    /// Tolerated clock difference between client and ingress, in seconds
+    pub clock_skew_secs: u64,
+    /// How long temporary credentials are trusted after signing, in seconds
+    pub session_ttl_secs: u64,
+    /// Origins (`scheme://host[:port]`, see `sts_endpoint_origin`) URLs may
+    /// target besides the official STS hosts, e.g. a VPC endpoint or a mock
+    pub trusted_sts_endpoints: Vec<String>,
+}
+
+impl Default for PresignedUrlPolicy {
+    fn default() -> Self {
+        Self {
+            max_expires_secs: 60,
+            clock_skew_secs: 300,
+            session_ttl_secs: 3600,
+            trusted_sts_endpoints: Vec::new(),
+        }
+    }
+}
+
+/// A presigned URL that passed validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresignedUrl {
+    pub uri: Uri,
+    /// When the URL was signed (`X-Amz-Date`)
+    pub signed_at: DateTime<Utc>,
+    /// Whether it was signed with temporary credentials (`X-Amz-Security-Token`)
+    pub session_credentials: bool,
+}
+
+impl PresignedUrl {
+    /// When the identity proven by this URL stops being trusted, as seconds
+    /// since the Unix epoch
+    ///
+    /// This is a heuristic, not the credentials' real expiry, which the URL
+    /// does not carry: temporary credentials are assumed to last
+    /// `session_ttl_secs` from signing, long-term keys do not expire.
+    pub fn assumed_session_expiry(&self, policy: &PresignedUrlPolicy) -> Option<i64> {
+        self.session_credentials
+            .then(|| self.signed_at.timestamp() + policy.session_ttl_secs as i64)
+    }
+}
+
+/// Validate a presigned `GetCallerIdentity` URL and return it parsed for fetching
+///
+/// `declared_region` is the region the client claims to have signed with
+/// (`IamAuthRequest::region`).
+pub fn validate_presigned_url(
+    url: &str,
+    declared_region: &str,
+    policy: &PresignedUrlPolicy,
+    now: DateTime<Utc>,
+) -> Result<PresignedUrl, PresignedUrlError> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e: hyper::http::uri::InvalidUri| PresignedUrlError::Malformed(e.to_string()))?;
+
+    // An endpoint the operator trusts is taken as it is; anything else must be
+    // an official STS host over https, whose name gives its region
+    let trusted_endpoint =
+        origin(&uri).is_some_and(|origin| policy.trusted_sts_endpoints.contains(&origin));
+    let official_host = if trusted_endpoint {
+        None
+    } else {
+        let scheme = uri.scheme_str().unwrap_or_default();
+        if scheme != "https" {
+            return Err(PresignedUrlError::InsecureScheme(scheme.to_string()));
+        }
+
+        let authority = uri
+            .authority()
+            .ok_or_else(|| PresignedUrlError::Malformed("missing host".to_string()))?;
+        if authority.as_str().contains('@') || authority.port().is_some() {
+            return Err(PresignedUrlError::UnexpectedAuthority(
+                authority.as_str().to_string(),
+            ));
+        }
+
+        let host = authority.host().to_ascii_lowercase();
+        let host_region = sts_host_region(&host)?.to_string();
+        Some((host, host_region))
+    };
+
+    if uri.path() != "/" && !uri.path().is_empty() {
+        return Err(PresignedUrlError::UnexpectedPath(uri.path().to_string()));
+    }
+
+    let params = parse_query(uri.query().unwrap_or_default())?;
+    let param = |name: &'static str| {
+        params
+            .get(name)
+            .map(String::as_str)
+            .ok_or(PresignedUrlError::MissingParameter(name))
+    };
+
+    let action = param("Action")?;
+    if action != "GetCallerIdentity" {
+        return Err(PresignedUrlError::WrongAction(action.to_string()));
+    }
+
+    let algorithm = param("X-Amz-Algorithm")?;
+    if algorithm != "AWS4-HMAC-SHA256" {
+        return Err(PresignedUrlError::UnsupportedAlgorithm(
+            algorithm.to_string(),
+        ));
+    }
+
+    // Credential scope: <access key>/<yyyymmdd>/<region>/sts/aws4_request
+    let credential = param("X-Amz-Credential")?;
+    let scope: Vec<&str> = credential.split('/').collect();
+    if scope.len() != 5 || scope[3] != "sts" || scope[4] != "aws4_request" {
+        return Err(PresignedUrlError::InvalidCredentialScope(
+            scope.get(1..).map(|s| s.join("/")).unwrap_or_default(),
+        ));
+    }
+    let signed_region = scope[2];
+
+    if signed_region != declared_region {
+        return Err(PresignedUrlError::RegionMismatch {
+            signed: signed_region.to_string(),
+            declared: declared_region.to_string(),
+        });
+    }
+    if let Some((host, host_region)) = official_host {
+        if signed_region != host_region {
+            return Err(PresignedUrlError::HostRegionMismatch {
+                host,
+                signed: signed_region.to_string(),
+            });
+        }
+    }
+
+    let amz_date = param("X-Amz-Date")?;
+    let signed_at = NaiveDateTime::parse_from_str(amz_date, "%Y%m%dT%H%M%SZ")
+        .map_err(|_| PresignedUrlError::InvalidDate(amz_date.to_string()))?
+        .and_utc();
+    if !scope[1].is_empty() && !amz_date.starts_with(scope[1]) {
+        return Err(PresignedUrlError::InvalidCredentialScope(format!(
+            "{}/{}/sts/aws4_request",
+            scope[1], signed_region
+        )));
+    }
+
+    let expires_raw = param("X-Amz-Expires")?;
+    let expires: u64 = expires_raw
+        .parse()
+        .map_err(|_| PresignedUrlError::InvalidExpires(expires_raw.to_string()))?;
+    if expires > policy.max_expires_secs {
+        return Err(PresignedUrlError::ExpiresTooLong {
+            expires,
+            max: policy.max_expires_secs,
+        });
+    }
+
+    let age = (now - signed_at).num_seconds();
+    if -age > policy.clock_skew_secs as i64 {
+        return Err(PresignedUrlError::DateInFuture(-age));
+    }
+    if age > expires as i64 {
+        return Err(PresignedUrlError::Expired(age - expires as i64));
+    }
+
+    // The auth challenge only binds the URL to the connection if it is signed
+    let signed_headers = param("X-Amz-SignedHeaders")?;
+    if !signed_headers
+        .split(';')
+        .any(|h| h.eq_ignore_ascii_case(MESH_AUTH_NONCE_HEADER))
+    {
+        return Err(PresignedUrlError::UnsignedChallenge(MESH_AUTH_NONCE_HEADER));
+    }
+
+    param("X-Amz-Signature")?;
+
+    Ok(PresignedUrl {
+        session_credentials: params.contains_key("X-Amz-Security-Token"),
+        uri,
+        signed_at,
+    })
+}
+
+/// Normalize a configured STS endpoint to the origin presigned URLs for it carry
+///
+/// The endpoint is an http or https base URL without user info, path or query.
+pub fn sts_endpoint_origin(endpoint: &str) -> Result<String, PresignedUrlError> {
+    let uri: Uri = endpoint
+        .parse()
+        .map_err(|e: hyper::http::uri::InvalidUri| PresignedUrlError::Malformed(e.to_string()))?;
+    let scheme = uri.scheme_str().unwrap_or_default();
+    if scheme != "https" && scheme != "http" {
+        return Err(PresignedUrlError::Malformed(format!(
+            "{} is not an http(s) URL",
+            endpoint
+        )));
+    }
+    let authority = uri
+        .authority()
+        .ok_or_else(|| PresignedUrlError::Malformed("missing host".to_string()))?;
+    if authority.as_str().contains('@') {
+        return Err(PresignedUrlError::UnexpectedAuthority(
+            authority.as_str().to_string(),
+        ));
+    }
+    if (uri.path() != "/" && !uri.path().is_empty()) || uri.query().is_some() {
+        return Err(PresignedUrlError::UnexpectedPath(
+            uri.path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+        ));
+    }
+    origin(&uri).ok_or_else(|| PresignedUrlError::Malformed(endpoint.to_string()))
+}
+
+/// `scheme://authority` of a URL, lowercased
+fn origin(uri: &Uri) -> Option<String> {
+    Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?).to_ascii_lowercase())
+}
+
+/// Return the region served by an official STS host, or reject the host
+fn sts_host_region(host: &str) -> Result<&str, PresignedUrlError> {
+    static REGIONAL_HOST: OnceLock<Regex> = OnceLock::new();
+
+    if host == "sts.amazonaws.com" {
+        return Ok(GLOBAL_STS_REGION);
+    }
+
+    let regional = REGIONAL_HOST.get_or_init(|| {
+        Regex::new(r"^sts(?:-fips)?\.([a-z]{2}(?:-[a-z]+)+-[0-9]+)\.amazonaws\.com(?:\.cn)?$")
+            .expect("valid STS host regex")
+    });
+
+    regional
+        .captures(host)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+        .ok_or_else(|| PresignedUrlError::UntrustedHost(host.to_string()))
+}
+
+/// Decode query parameters, rejecting repeated names
+fn parse_query(query: &str) -> Result<HashMap<String, String>, PresignedUrlError> {
+    let mut params = HashMap::new();
+
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = percent_decode_str(key)
+            .decode_utf8()
+            .map_err(|e| PresignedUrlError::Malformed(e.to_string()))?
+            .into_owned();
+        let value = percent_decode_str(value)
+            .decode_utf8()
+            .map_err(|e| PresignedUrlError::Malformed(e.to_string()))?
+            .into_owned();
+
+        if params.contains_key(&key) {
+            return Err(PresignedUrlError::DuplicateParameter(key));
+        }
+        params.insert(key, value);
+    }
+
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 30).unwrap()
+    }
+
+    fn presigned_url(host: &str, region: &str, action: &str, date: &str, expires: u64) -> String {
+        format!(
+            "https://{}/?Action={}&Version=2011-06-15&X-Amz-Algorithm=AWS4-HMAC-SHA256\
+             &X-Amz-Credential=AKIDEXAMPLE%2F{}%2F{}%2Fsts%2Faws4_request\
+             &X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host%3Bx-mesh-auth-nonce&X-Amz-Signature=abc123",
+            host,
+            action,
+            &date[..8],
+            region,
+            date,
+            expires
+        )
+    }
+
+    fn validate(url: &str, region: &str) -> Result<Uri, PresignedUrlError> {
+        validate_presigned_url(url, region, &PresignedUrlPolicy::default(), now())
+            .map(|url| url.uri)
+    }
+
+    #[test]
+    fn test_valid_regional_and_global_urls() {
+        let url = presigned_url(
+            "sts.eu-west-1.amazonaws.com",
+            "eu-west-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(validate(&url, "eu-west-1").is_ok());
+
+        let url = presigned_url(
+            "sts.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(validate(&url, "us-east-1").is_ok());
+
+        let url = presigned_url(
+            "sts-fips.us-gov-west-1.amazonaws.com",
+            "us-gov-west-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(validate(&url, "us-gov-west-1").is_ok());
+    }
+
+    #[test]
+    fn test_session_credentials_expire() {
+        let url = presigned_url(
+            "sts.eu-west-1.amazonaws.com",
+            "eu-west-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        let policy = PresignedUrlPolicy::default();
+        let long_term = validate_presigned_url(&url, "eu-west-1", &policy, now()).unwrap();
+        assert_eq!(long_term.assumed_session_expiry(&policy), None);
+
+        let session = validate_presigned_url(
+            &format!("{}&X-Amz-Security-Token=FQoGZXIvYXdzEXAMPLE", url),
+            "eu-west-1",
+            &policy,
+            now(),
+        )
+        .unwrap();
+        assert_eq!(
+            session.assumed_session_expiry(&policy),
+            Some(now().timestamp() - 30 + 3600)
+        );
+    }
+
+    #[test]
+    fn test_rejects_untrusted_hosts_and_schemes() {
+        for host in [
+            "169.254.169.254",
+            "sts.us-east-1.amazonaws.com.evil.example",
+            "internal.service.local",
+            "s3.us-east-1.amazonaws.com",
+        ] {
+            let url = presigned_url(
+                host,
+                "us-east-1",
+                "GetCallerIdentity",
+                "20240501T120000Z",
+                60,
+            );
+            assert!(
+                matches!(
+                    validate(&url, "us-east-1"),
+                    Err(PresignedUrlError::UntrustedHost(_))
+                ),
+                "{} should be rejected",
+                host
+            );
+        }
+
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        )
+        .replacen("https://", "http://", 1);
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::InsecureScheme("http".to_string()))
+        );
+
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com:8443",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(matches!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::UnexpectedAuthority(_))
+        ));
+    }
+
+    #[test]
+    fn test_trusted_sts_endpoints() {
+        let policy = PresignedUrlPolicy {
+            trusted_sts_endpoints: vec![sts_endpoint_origin("HTTP://localhost:4566/").unwrap()],
+            ..Default::default()
+        };
+        let url = presigned_url(
+            "localhost:4566",
+            "eu-west-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        )
+        .replacen("https://", "http://", 1);
+        assert!(validate_presigned_url(&url, "eu-west-1", &policy, now()).is_ok());
+        // The rest of the URL is still checked
+        assert!(matches!(
+            validate_presigned_url(&url, "us-east-1", &policy, now()),
+            Err(PresignedUrlError::RegionMismatch { .. })
+        ));
+        // Only the configured origin is trusted
+        for other in [
+            url.replace(":4566", ":4567"),
+            url.replacen("http://", "https://", 1),
+        ] {
+            assert!(validate_presigned_url(&other, "eu-west-1", &policy, now()).is_err());
+        }
+
+        for endpoint in [
+            "ftp://localhost:4566",
+            "http://user@localhost:4566",
+            "https://sts.example.com/prefix",
+            "localhost:4566",
+        ] {
+            assert!(sts_endpoint_origin(endpoint).is_err(), "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn test_rejects_wrong_action_and_duplicates() {
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "AssumeRole",
+            "20240501T120000Z",
+            60,
+        );
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::WrongAction("AssumeRole".to_string()))
+        );
+
+        let url = format!(
+            "{}&Action=AssumeRole",
+            presigned_url(
+                "sts.us-east-1.amazonaws.com",
+                "us-east-1",
+                "GetCallerIdentity",
+                "20240501T120000Z",
+                60,
+            )
+        );
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::DuplicateParameter("Action".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rejects_url_without_signed_challenge() {
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        )
+        .replace("host%3Bx-mesh-auth-nonce", "host");
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::UnsignedChallenge(MESH_AUTH_NONCE_HEADER))
+        );
+    }
+
+    #[test]
+    fn test_rejects_region_mismatches() {
+        let url = presigned_url(
+            "sts.us-west-2.amazonaws.com",
+            "us-west-2",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(matches!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::RegionMismatch { .. })
+        ));
+
+        let url = presigned_url(
+            "sts.us-west-2.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            60,
+        );
+        assert!(matches!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::HostRegionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_stale_future_and_long_lived_urls() {
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T115800Z",
+            60,
+        );
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::Expired(90))
+        );
+
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T121000Z",
+            60,
+        );
+        assert!(matches!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::DateInFuture(_))
+        ));
+
+        let url = presigned_url(
+            "sts.us-east-1.amazonaws.com",
+            "us-east-1",
+            "GetCallerIdentity",
+            "20240501T120000Z",
+            3600,
+        );
+        assert_eq!(
+            validate(&url, "us-east-1"),
+            Err(PresignedUrlError::ExpiresTooLong {
+                expires: 3600,
+                max: 60
+            })
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Parsing of STS `GetCallerIdentity` responses and caller ARNs.
+//!
+//! The presigned URL is fetched by the ingress, so the body is whatever STS
+//! answered: either a `GetCallerIdentityResponse` or an `ErrorResponse`.
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Reasons an STS response does not yield a caller identity
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StsError {
+    #[error("{code}: {message}")]
+    Service { code: String, message: String },
+
+    #[error("malformed STS response: {0}")]
+    Malformed(String),
+
+    #[error("unrecognized principal ARN {0}")]
+    UnrecognizedArn(String),
+}
+
+/// Identity returned by a successful `GetCallerIdentity` call
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CallerIdentity {
+    #[serde(rename = "Arn")]
+    pub arn: String,
+    #[serde(rename = "Account")]
+    pub account: String,
+    #[serde(rename = "UserId")]
+    pub user_id: String,
+}
+
+#[derive(Deserialize)]
+struct GetCallerIdentityResponse {
+    #[serde(rename = "GetCallerIdentityResult")]
+    result: CallerIdentity,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "Error")]
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "Code")]
+    code: String,
+    #[serde(rename = "Message", default)]
+    message: String,
+}
+
+/// Parse an STS response body, surfacing `ErrorResponse` bodies as `StsError::Service`
+pub fn parse_caller_identity(body: &str) -> Result<CallerIdentity, StsError> {
+    if let Ok(response) = quick_xml::de::from_str::<ErrorResponse>(body) {
+        return Err(StsError::Service {
+            code: response.error.code,
+            message: response.error.message,
+        });
+    }
+
+    quick_xml::de::from_str::<GetCallerIdentityResponse>(body)
+        .map(|response| response.result)
+        .map_err(|e| StsError::Malformed(e.to_string()))
+}
+
+/// Kind of principal behind a caller ARN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrincipalType {
+    User,
+    AssumedRole,
+    FederatedUser,
+    Root,
+}
+
+impl PrincipalType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrincipalType::User => "User",
+            PrincipalType::AssumedRole => "AssumedRole",
+            PrincipalType::FederatedUser => "FederatedUser",
+            PrincipalType::Root => "Root",
+        }
+    }
+}
+
+/// Principal details derived from a caller ARN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub principal_type: PrincipalType,
+    /// IAM role ARN behind an assumed-role session
+    pub role_arn: Option<String>,
+    /// Session name of an assumed role, or the name of a federated user
+    pub session_name: Option<String>,
+}
+
+/// Derive the principal type and role/session names from a caller ARN
+///
+/// Assumed-role ARNs do not carry the role path, so the derived role ARN is
+/// `arn:<partition>:iam::<account>:role/<name>`.
+pub fn parse_principal_arn(arn: &str) -> Result<Principal, StsError> {
+    let unrecognized = || StsError::UnrecognizedArn(arn.to_string());
+
+    let parts: Vec<&str> = arn.splitn(6, ':').collect();
+    let [prefix, partition, service, _region, account, resource] = parts[..] else {
+        return Err(unrecognized());
+    };
+    if prefix != "arn" || partition.is_empty() || account.is_empty() {
+        return Err(unrecognized());
+    }
+
+    match (service, resource.split_once('/')) {
+        ("iam", None) if resource == "root" => Ok(Principal {
+            principal_type: PrincipalType::Root,
+            role_arn: None,
+            session_name: None,
+        }),
+        ("iam", Some(("user", name))) if !name.is_empty() => Ok(Principal {
+            principal_type: PrincipalType::User,
+            role_arn: None,
+            session_name: None,
+        }),
+        ("sts", Some(("assumed-role", rest))) => match rest.split_once('/') {
+            Some((role, session)) if !role.is_empty() && !session.is_empty() => Ok(Principal {
+                principal_type: PrincipalType::AssumedRole,
+                role_arn: Some(format!("arn:{}:iam::{}:role/{}", partition, account, role)),
+                session_name: Some(session.to_string()),
+            }),
+            _ => Err(unrecognized()),
+        },
+        ("sts", Some(("federated-user", name))) if !name.is_empty() => Ok(Principal {
+            principal_type: PrincipalType::FederatedUser,
+            role_arn: None,
+            session_name: Some(name.to_string()),
+        }),
+        _ => Err(unrecognized()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_success_response() {
+        let body = r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetCallerIdentityResult>
+    <Arn>arn:aws:sts::123456789012:assumed-role/MeshClientRole/i-0abc</Arn>
+    <UserId>AROAEXAMPLE:i-0abc</UserId>
+    <Account>123456789012</Account>
+  </GetCallerIdentityResult>
+  <ResponseMetadata>
+    <RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId>
+  </ResponseMetadata>
+</GetCallerIdentityResponse>"#;
+
+        assert_eq!(
+            parse_caller_identity(body).unwrap(),
+            CallerIdentity {
+                arn: "arn:aws:sts::123456789012:assumed-role/MeshClientRole/i-0abc".to_string(),
+                account: "123456789012".to_string(),
+                user_id: "AROAEXAMPLE:i-0abc".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let body = r#"<ErrorResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <Error>
+    <Type>Sender</Type>
+    <Code>SignatureDoesNotMatch</Code>
+    <Message>Signature expired</Message>
+  </Error>
+  <RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId>
+</ErrorResponse>"#;
+
+        let err = parse_caller_identity(body).unwrap_err();
+        assert_eq!(
+            err,
+            StsError::Service {
+                code: "SignatureDoesNotMatch".to_string(),
+                message: "Signature expired".to_string(),
+            }
+        );
+        assert_eq!(err.to_string(), "SignatureDoesNotMatch: Signature expired");
+
+        assert!(matches!(
+            parse_caller_identity("<Arn>arn:aws:iam::1:root</Arn>"),
+            Err(StsError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_principal_arn() {
+        let principal =
+            parse_principal_arn("arn:aws:sts::123456789012:assumed-role/MeshClientRole/i-0abc")
+                .unwrap();
+        assert_eq!(principal.principal_type, PrincipalType::AssumedRole);
+        assert_eq!(
+            principal.role_arn.as_deref(),
+            Some("arn:aws:iam::123456789012:role/MeshClientRole")
+        );
+        assert_eq!(principal.session_name.as_deref(), Some("i-0abc"));
+
+        let principal =
+            parse_principal_arn("arn:aws-us-gov:sts::123456789012:assumed-role/Role/s").unwrap();
+        assert_eq!(
+            principal.role_arn.as_deref(),
+            Some("arn:aws-us-gov:iam::123456789012:role/Role")
+        );
+
+        let principal = parse_principal_arn("arn:aws:iam::123456789012:user/ops/alice").unwrap();
+        assert_eq!(principal.principal_type, PrincipalType::User);
+        assert_eq!(principal.role_arn, None);
+
+        let principal =
+            parse_principal_arn("arn:aws:sts::123456789012:federated-user/bob").unwrap();
+        assert_eq!(principal.principal_type, PrincipalType::FederatedUser);
+        assert_eq!(principal.session_name.as_deref(), Some("bob"));
+
+        let principal = parse_principal_arn("arn:aws:iam::123456789012:root").unwrap();
+        assert_eq!(principal.principal_type, PrincipalType::Root);
+
+        for arn in [
+            "arn:aws:iam::123456789012:role/MeshClientRole",
+            "arn:aws:sts::123456789012:assumed-role/MeshClientRole",
+            "arn:aws:s3:::bucket",
+            "not-an-arn",
+        ] {
+            assert_eq!(
+                parse_principal_arn(arn),
+                Err(StsError::UnrecognizedArn(arn.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use super::{auth_failure, AuthRequest, AuthService};
+use crate::common::auth::{IamAuthResponse, IamIdentity};
+use crate::common::token::verify_token;
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::{info, warn};
+
+/// Principal type reported for identities authenticated with a mesh token
+pub const TOKEN_PRINCIPAL_TYPE: &str = "MeshToken";
+
+/// Verifies HMAC-signed mesh tokens issued with `mesh token issue`
+pub struct TokenAuthService {
+    secret: Vec<u8>,
+}
+
+impl TokenAuthService {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthService for TokenAuthService {
+    async fn authenticate(&self, auth_request: &AuthRequest) -> IamAuthResponse {
+        let AuthRequest::Token(auth_request) = auth_request else {
+            return auth_failure(format!(
+                "{} authentication is not supported by the token backend",
+                auth_request.method()
+            ));
+        };
+
+        match verify_token(&self.secret, &auth_request.token, Utc::now().timestamp()) {
+            Ok(claims) => {
+                info!("Token auth successful for subject: {}", claims.sub);
+                IamAuthResponse {
+                    success: true,
+                    error: None,
+                    identity: Some(IamIdentity {
+                        // Token subjects use a pseudo ARN so ARN patterns in the
+                        // registration policy can target them
+                        arn: format!("mesh:token/{}", claims.sub),
+                        account_id: String::new(),
+                        user_id: claims.sub,
+                        principal_type: TOKEN_PRINCIPAL_TYPE.to_string(),
+                        role_arn: None,
+                        session_name: None,
+                        expires_at: Some(claims.exp),
+                        allowed_hosts: Some(claims.hosts),
+                    }),
+                }
+            }
+            Err(e) => {
+                warn!("Token auth failed: {}", e);
+                auth_failure(format!("Token rejected: {}", e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::auth::{IamAuthRequest, TokenAuthRequest};
+    use crate::common::token::{issue_token, TokenClaims};
+    use crate::server::auth::CompositeAuthService;
+    use std::sync::Arc;
+
+    fn token_request(secret: &[u8], exp: i64) -> AuthRequest {
+        let now = Utc::now().timestamp();
+        AuthRequest::Token(TokenAuthRequest {
+            token: issue_token(
+                secret,
+                &TokenClaims {
+                    sub: "edge-box-1".to_string(),
+                    hosts: vec!["*.edge.example.com".to_string()],
+                    iat: now,
+                    exp: now + exp,
+                },
+            ),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_token_auth() {
+        let service = TokenAuthService::new("secret");
+
+        let response = service.authenticate(&token_request(b"secret", 60)).await;
+        assert!(response.success);
+        let identity = response.identity.unwrap();
+        assert_eq!(identity.arn, "mesh:token/edge-box-1");
+        assert_eq!(
+            identity.allowed_hosts,
+            Some(vec!["*.edge.example.com".to_string()])
+        );
+
+        assert!(
+            !service
+                .authenticate(&token_request(b"wrong", 60))
+                .await
+                .success
+        );
+        assert!(
+            !service
+                .authenticate(&token_request(b"secret", -1))
+                .await
+                .success
+        );
+    }
+
+    #[tokio::test]
+    async fn test_composite_picks_backend_by_variant() {
+        let composite =
+            CompositeAuthService::new().with_token(Arc::new(TokenAuthService::new("secret")));
+
+        assert!(
+            composite
+                .authenticate(&token_request(b"secret", 60))
+                .await
+                .success
+        );
+
+        // No IAM backend configured
+        let iam = AuthRequest::Iam {
+            request: IamAuthRequest {
+                presigned_url: None,
+                region: "us-east-1".to_string(),
+                arn: None,
+                account_id: None,
+                user_id: None,
+            },
+            challenge: None,
+        };
+        let response = composite.authenticate(&iam).await;
+        assert!(!response.success);
+        assert_eq!(
+            response.error.as_deref(),
+            Some("IAM authentication is not enabled")
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use super::auth::DefaultAuthService;
+use crate::common::config::RegistrationGrant;
+use crate::common::routing::pattern_covers;
+use crate::common::{IamIdentity, ServiceRegistration};
+
+/// Decides which hosts, services and clusters an identity may register
+#[derive(Debug, Clone, Default)]
+pub struct RegistrationPolicy {
+    grants: Vec<RegistrationGrant>,
+}
+
+impl RegistrationPolicy {
+    pub fn new(grants: Vec<RegistrationGrant>) -> Self {
+        Self { grants }
+    }
+
+    /// Check a registration against the policy, explaining any rejection
+    ///
+    /// Identities scoped to specific hosts (e.g. mesh tokens) are held to that
+    /// scope first. Beyond that, an empty policy allows everything, matching the
+    /// behaviour before registration policies existed.
+    pub fn check(
+        &self,
+        identity: Option<&IamIdentity>,
+        registration: &ServiceRegistration,
+    ) -> Result<(), String> {
+        if let Some(allowed_hosts) = identity.and_then(|i| i.allowed_hosts.as_ref()) {
+            if !allowed_hosts
+                .iter()
+                .any(|pattern| pattern_covers(pattern, &registration.host))
+            {
+                return Err(format!(
+                    "host {} is outside the credential's scope",
+                    registration.host
+                ));
+            }
+        }
+
+        if self.grants.is_empty() {
+            return Ok(());
+        }
+
+        let identity = identity.ok_or_else(|| "connection has no identity".to_string())?;
+
+        let grants: Vec<&RegistrationGrant> = self
+            .grants
+            .iter()
+            .filter(|g| {
+                identity
+                    .principal_arns()
+                    .any(|arn| DefaultAuthService::matches_arn_pattern(arn, &g.principal))
+            })
+            .collect();
+
+        if grants.is_empty() {
+            return Err(format!("no registration grant for {}", identity.arn));
+        }
+
+        let mut reason = None;
+        for grant in grants {
+            match Self::check_grant(grant, registration) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    reason.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(format!(
+            "{} for {}",
+            reason.unwrap_or_default(),
+            identity.arn
+        ))
+    }
+
+    fn check_grant(
+        grant: &RegistrationGrant,
+        registration: &ServiceRegistration,
+    ) -> Result<(), String> {
+        // The registered host is matched literally, so a wildcard registration is
+        // only allowed if it is covered by an equal or broader wildcard pattern
+        if !grant.hosts.is_empty()
+            && !grant
+                .hosts
+                .iter()
+                .any(|pattern| pattern_covers(pattern, &registration.host))
+        {
+            return Err(format!("host {} is not permitted", registration.host));
+        }
+
+        if !Self::allows(&grant.services, &registration.service_name) {
+            return Err(format!(
+                "service {} is not permitted",
+                registration.service_name
+            ));
+        }
+
+        if !Self::allows(&grant.clusters, &registration.cluster_name) {
+            return Err(format!(
+                "cluster {} is not permitted",
+                registration.cluster_name
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn allows(patterns: &[String], value: &str) -> bool {
+        patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| DefaultAuthService::matches_arn_pattern(value, pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn identity(arn: &str) -> IamIdentity {
+        IamIdentity {
+            arn: arn.to_string(),
+            account_id: "123456789012".to_string(),
+            user_id: "AROAEXAMPLE:session".to_string(),
+            principal_type: "AssumedRole".to_string(),
+            role_arn: None,
+            session_name: None,
+            expires_at: None,
+            allowed_hosts: None,
+        }
+    }
+
+    fn registration(host: &str, service: &str, cluster: &str) -> ServiceRegistration {
+        ServiceRegistration {
+            id: Uuid::new_v4(),
+            host: host.to_string(),
+            port: 8080,
+            service_name: service.to_string(),
+            cluster_name: cluster.to_string(),
+            task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test".to_string(),
+            attributes: HashMap::new(),
+            health_check_path: None,
+            paths: Vec::new(),
+        }
+    }
+
+    fn policy() -> RegistrationPolicy {
+        RegistrationPolicy::new(vec![RegistrationGrant {
+            principal: "arn:aws:sts::123456789012:assumed-role/TeamA/*".to_string(),
+            hosts: vec!["*.team-a.example.com".to_string()],
+            services: vec!["team-a-*".to_string()],
+            clusters: vec!["onprem".to_string()],
+        }])
+    }
+
+    #[test]
+    fn test_empty_policy_allows_everything() {
+        let policy = RegistrationPolicy::default();
+        assert!(policy
+            .check(None, &registration("*", "anything", "any"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_registration_policy() {
+        let policy = policy();
+        let team_a = identity("arn:aws:sts::123456789012:assumed-role/TeamA/i-123");
+        let team_b = identity("arn:aws:sts::123456789012:assumed-role/TeamB/i-456");
+
+        assert!(policy
+            .check(
+                Some(&team_a),
+                &registration("api.team-a.example.com", "team-a-api", "onprem")
+            )
+            .is_ok());
+        assert!(policy
+            .check(
+                Some(&team_a),
+                &registration("*.v2.team-a.example.com", "team-a-api", "onprem")
+            )
+            .is_ok());
+
+        // Wildcards broader than the grant are rejected
+        let err = policy
+            .check(
+                Some(&team_a),
+                &registration("*.example.com", "team-a-api", "onprem"),
+            )
+            .unwrap_err();
+        assert!(err.contains("host *.example.com is not permitted"));
+
+        let err = policy
+            .check(
+                Some(&team_a),
+                &registration("api.team-a.example.com", "billing", "onprem"),
+            )
+            .unwrap_err();
+        assert!(err.contains("service billing"));
+
+        let err = policy
+            .check(
+                Some(&team_a),
+                &registration("api.team-a.example.com", "team-a-api", "cloud"),
+            )
+            .unwrap_err();
+        assert!(err.contains("cluster cloud"));
+
+        let err = policy
+            .check(
+                Some(&team_b),
+                &registration("api.team-a.example.com", "team-a-api", "onprem"),
+            )
+            .unwrap_err();
+        assert!(err.contains("no registration grant"));
+    }
+
+    #[test]
+    fn test_identity_host_scope() {
+        let policy = RegistrationPolicy::default();
+        let scoped = IamIdentity {
+            allowed_hosts: Some(vec!["*.edge.example.com".to_string()]),
+            ..identity("mesh:token/edge-box-1")
+        };
+
+        assert!(policy
+            .check(
+                Some(&scoped),
+                &registration("api.edge.example.com", "api", "edge")
+            )
+            .is_ok());
+        let err = policy
+            .check(Some(&scoped), &registration("*.example.com", "api", "edge"))
+            .unwrap_err();
+        assert!(err.contains("outside the credential's scope"));
+    }
+}
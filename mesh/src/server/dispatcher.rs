@@ -1,12 +1,23 @@
-use super::auth::AuthService;
+use super::audit::{AuditEvent, AuditLog};
+use super::auth::{auth_failure, AuthRequest, AuthService};
+use super::authz::RegistrationPolicy;
 use super::error::{IngressError, IngressResult};
 use super::registry::Registry;
 use super::router::Router;
-use crate::common::{IngressMessage, ProxyResponse};
+use crate::common::config::{HostConflictPolicy, SharedRoutingConfig};
+use crate::common::{routing, IngressMessage, ProxyResponse, ServiceRegistration};
 use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// A fresh nonce for an `AuthChallenge` or `ReauthRequest`
+pub fn new_auth_nonce() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
 /// Service for dispatching and handling WebSocket messages
 #[async_trait]
 pub trait MessageDispatcher: Send + Sync {
@@ -27,15 +38,52 @@ pub trait MessageDispatcher: Send + Sync {
         response: IngressMessage,
         registry: &dyn Registry,
     ) -> IngressResult<()>;
+
+    /// Replace the registration policy; applies to registrations from now on
+    fn update_registration_policy(&self, registration_policy: RegistrationPolicy);
 }
 
 /// Default implementation of MessageDispatcher
 #[derive(Clone)]
-pub struct DefaultMessageDispatcher;
+pub struct DefaultMessageDispatcher {
+    registration_policy: Arc<RwLock<RegistrationPolicy>>,
+    routing_config: SharedRoutingConfig,
+    audit: Arc<AuditLog>,
+}
 
 impl DefaultMessageDispatcher {
     pub fn new() -> Self {
-        Self
+        Self::with_registration_policy(RegistrationPolicy::default())
+    }
+
+    pub fn with_registration_policy(registration_policy: RegistrationPolicy) -> Self {
+        Self {
+            registration_policy: Arc::new(RwLock::new(registration_policy)),
+            routing_config: SharedRoutingConfig::default(),
+            audit: Arc::new(AuditLog::disabled()),
+        }
+    }
+
+    /// Read `routing.host_conflicts` from these settings, to decide whether a
+    /// host already served by another cluster refuses new registrations
+    pub fn with_routing_config(mut self, routing_config: SharedRoutingConfig) -> Self {
+        self.routing_config = routing_config;
+        self
+    }
+
+    /// Record auth, registration and disconnect events in `audit`
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    async fn source_ip(&self, connection_id: Uuid, registry: &dyn Registry) -> Option<IpAddr> {
+        registry
+            .get_peer_addr(connection_id)
+            .await
+            .ok()
+            .flatten()
+            .map(|addr| addr.ip())
     }
 
     /// Parse and validate incoming message
@@ -44,46 +92,306 @@ impl DefaultMessageDispatcher {
             .map_err(|e| IngressError::bad_request(format!("Failed to parse message: {}", e)))
     }
 
-    /// Handle IAM authentication messages
-    async fn handle_iam_auth(
+    /// Handle authentication messages (IAM, token or JWT)
+    ///
+    /// On an already authenticated connection this is a re-authentication: it
+    /// must succeed as the same principal, otherwise the connection's services
+    /// are deregistered and the connection is dropped.
+    async fn handle_auth(
         &self,
         connection_id: Uuid,
-        auth_request: crate::common::auth::IamAuthRequest,
+        auth_request: AuthRequest,
         auth_service: &dyn AuthService,
         registry: &dyn Registry,
     ) -> IngressResult<()> {
-        let response = auth_service.authenticate(&auth_request).await;
+        let previous = registry.get_identity(connection_id).await?;
+        let signs_challenge = matches!(auth_request, AuthRequest::Iam { .. });
+        let mut response = auth_service.authenticate(&auth_request).await;
+        let presented = response.identity.clone();
 
-        self.send_response(
-            connection_id,
-            IngressMessage::IamAuthResponse(response),
-            registry,
-        )
-        .await
+        if let (Some(previous), Some(identity)) = (&previous, &response.identity) {
+            if previous.arn != identity.arn {
+                warn!(
+                    "Connection {} re-authenticated as {} instead of {}",
+                    connection_id, identity.arn, previous.arn
+                );
+                response = auth_failure("Re-authentication must use the same identity");
+            }
+        }
+
+        let source_ip = self.source_ip(connection_id, registry).await;
+        if response.success {
+            if let Some(identity) = response.identity.clone() {
+                self.audit.record(AuditEvent::AuthSuccess {
+                    connection_id,
+                    method: auth_request.method().to_string(),
+                    arn: identity.arn.clone(),
+                    account_id: identity.account_id.clone(),
+                    principal_type: identity.principal_type.clone(),
+                    source_ip,
+                    reauth: previous.is_some(),
+                });
+                registry.set_authenticated(connection_id, identity).await?;
+            }
+        } else {
+            self.audit.record(AuditEvent::AuthFailure {
+                connection_id,
+                method: auth_request.method().to_string(),
+                arn: presented.as_ref().map(|i| i.arn.clone()),
+                account_id: presented.as_ref().map(|i| i.account_id.clone()),
+                source_ip,
+                reason: response.error.clone().unwrap_or_default(),
+                reauth: previous.is_some(),
+            });
+        }
+
+        let success = response.success;
+        let sent = self
+            .send_response(
+                connection_id,
+                IngressMessage::IamAuthResponse(response),
+                registry,
+            )
+            .await;
+
+        if !success {
+            if let Some(previous) = previous {
+                warn!(
+                    "Re-authentication of {} failed; disconnecting {}",
+                    previous.arn, connection_id
+                );
+                self.audit.record(AuditEvent::ForcedDisconnect {
+                    connection_id,
+                    arn: Some(previous.arn.clone()),
+                    source_ip,
+                    reason: "re-authentication failed".to_string(),
+                });
+                // Dropping the sender ends the connection once the response is flushed
+                registry.remove_connection(connection_id).await?;
+            } else if signs_challenge {
+                // The attempt used up the challenge; hand out another so a
+                // failure (an STS hiccup, say) can be retried on this connection
+                let nonce = new_auth_nonce();
+                registry
+                    .set_auth_challenge(connection_id, nonce.clone())
+                    .await?;
+                self.send_response(
+                    connection_id,
+                    IngressMessage::AuthChallenge { nonce },
+                    registry,
+                )
+                .await?;
+            }
+        }
+
+        sent
+    }
+
+    /// Reject a message received before the connection authenticated
+    async fn reject_unauthenticated(
+        &self,
+        connection_id: Uuid,
+        message: &IngressMessage,
+        registry: &dyn Registry,
+    ) -> IngressResult<()> {
+        warn!(
+            "Rejecting message from unauthenticated connection {}",
+            connection_id
+        );
+
+        if let IngressMessage::ServiceRegistration(_) = message {
+            let error_ack = IngressMessage::RegistrationAck {
+                id: connection_id,
+                success: false,
+                message: "Registration rejected: connection is not authenticated".to_string(),
+            };
+            // Best effort; the connection may already be gone
+            let _ = self.send_response(connection_id, error_ack, registry).await;
+        }
+
+        Err(IngressError::unauthenticated(
+            "An auth handshake must succeed before any other message",
+        ))
+    }
+
+    /// Check a registration against the services already registered: its
+    /// paths must not be claimed by another service, and a host another
+    /// cluster serves is audited as a conflict and may be refused
+    fn check_registered_routes(
+        &self,
+        connection_id: Uuid,
+        arn: &Option<String>,
+        registration: &ServiceRegistration,
+        existing_registrations: &HashMap<Uuid, ServiceRegistration>,
+    ) -> Result<(), String> {
+        if let Some(reason) = existing_registrations
+            .iter()
+            .filter(|(existing_id, existing)| {
+                **existing_id != connection_id && existing.service_name != registration.service_name
+            })
+            .find_map(|(_, existing)| {
+                routing::overlapping_route(registration, existing).map(|route| {
+                    format!(
+                        "{:?} path {} on {} is already routed to {}",
+                        route.match_type, route.path, registration.host, existing.service_name
+                    )
+                })
+            })
+        {
+            return Err(reason);
+        }
+
+        // Another service already answering for the whole host is worth a look.
+        // Another cluster conflicts as soon as either pattern covers the other,
+        // paths or not, since the new one would take over some of its traffic;
+        // such registrations may be refused outright
+        let host_pattern = routing::normalize_host_pattern(&registration.host);
+        let mut cross_cluster = None;
+        for (existing_id, existing) in existing_registrations {
+            if *existing_id == connection_id
+                || (existing.service_name == registration.service_name
+                    && existing.cluster_name == registration.cluster_name)
+            {
+                continue;
+            }
+            let same_host = routing::normalize_host_pattern(&existing.host) == host_pattern;
+            let conflicts = if existing.cluster_name != registration.cluster_name {
+                same_host
+                    || routing::pattern_covers(&existing.host, &registration.host)
+                    || routing::pattern_covers(&registration.host, &existing.host)
+            } else {
+                same_host && existing.paths.is_empty() && registration.paths.is_empty()
+            };
+            if !conflicts {
+                continue;
+            }
+
+            let rejected = existing.cluster_name != registration.cluster_name
+                && self.routing_config.load().host_conflicts == HostConflictPolicy::Reject;
+            warn!(
+                "Host {} registered by {} in cluster {} is already served by {} in cluster {}",
+                registration.host,
+                registration.service_name,
+                registration.cluster_name,
+                existing.service_name,
+                existing.cluster_name
+            );
+            self.audit.record(AuditEvent::HostConflict {
+                connection_id,
+                arn: arn.clone(),
+                host: registration.host.clone(),
+                service_name: registration.service_name.clone(),
+                cluster_name: registration.cluster_name.clone(),
+                existing_connection_id: *existing_id,
+                existing_service_name: existing.service_name.clone(),
+                existing_cluster_name: existing.cluster_name.clone(),
+                rejected,
+            });
+            if rejected && cross_cluster.is_none() {
+                cross_cluster = Some(&existing.cluster_name);
+            }
+        }
+        match cross_cluster {
+            Some(existing_cluster) => Err(format!(
+                "host {} is already served by cluster {}",
+                registration.host, existing_cluster
+            )),
+            None => Ok(()),
+        }
     }
 
     /// Handle service registration messages
     async fn handle_service_registration(
         &self,
         connection_id: Uuid,
-        registration: crate::common::ServiceRegistration,
+        registration: ServiceRegistration,
         registry: &dyn Registry,
     ) -> IngressResult<()> {
-        // Register the service with the registry
-        if let Err(e) = registry
-            .register_service(connection_id, registration.clone())
-            .await
-        {
-            error!("Failed to register service: {}", e);
-            // Send error acknowledgment
+        let identity = registry.get_identity(connection_id).await?;
+        if let Some(identity) = &identity {
+            info!(
+                "Registration of {} for host {} by {}",
+                registration.service_name, registration.host, identity.arn
+            );
+        }
+        let arn = identity.as_ref().map(|i| i.arn.clone());
+        let audit_registration = |accepted: bool, reason: Option<String>| {
+            self.audit.record(AuditEvent::Registration {
+                connection_id,
+                arn: arn.clone(),
+                service_name: registration.service_name.clone(),
+                host: registration.host.clone(),
+                cluster_name: registration.cluster_name.clone(),
+                accepted,
+                reason,
+            })
+        };
+
+        let policy_check = self
+            .registration_policy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .check(identity.as_ref(), &registration);
+        if let Err(reason) = policy_check {
+            warn!(
+                "Registration of {} for host {} denied: {}",
+                registration.service_name, registration.host, reason
+            );
+            audit_registration(false, Some(reason.clone()));
             let error_ack = IngressMessage::RegistrationAck {
                 id: connection_id,
                 success: false,
-                message: format!("Registration failed: {}", e),
+                message: format!("Registration denied: {}", reason),
             };
             return self.send_response(connection_id, error_ack, registry).await;
         }
 
+        // Host and paths must be well-formed. Checks against what is already
+        // registered run under the registry's write lock, so two clusters
+        // registering the same host at once cannot both get in
+        let check = |existing: &HashMap<Uuid, ServiceRegistration>| {
+            self.check_registered_routes(connection_id, &arn, &registration, existing)
+        };
+        let registered = match routing::validate_host_pattern(&registration.host)
+            .and_then(|()| routing::validate_paths(&registration.paths))
+        {
+            Err(reason) => Ok(Err(reason)),
+            Ok(()) => {
+                registry
+                    .register_service_if(connection_id, registration.clone(), &check)
+                    .await
+            }
+        };
+        match registered {
+            Ok(Ok(())) => {}
+            Ok(Err(reason)) => {
+                warn!(
+                    "Registration of {} for host {} rejected: {}",
+                    registration.service_name, registration.host, reason
+                );
+                audit_registration(false, Some(reason.clone()));
+                let error_ack = IngressMessage::RegistrationAck {
+                    id: connection_id,
+                    success: false,
+                    message: format!("Registration rejected: {}", reason),
+                };
+                return self.send_response(connection_id, error_ack, registry).await;
+            }
+            Err(e) => {
+                error!("Failed to register service: {}", e);
+                audit_registration(false, Some(e.to_string()));
+                // Send error acknowledgment
+                let error_ack = IngressMessage::RegistrationAck {
+                    id: connection_id,
+                    success: false,
+                    message: format!("Registration failed: {}", e),
+                };
+                return self.send_response(connection_id, error_ack, registry).await;
+            }
+        }
+
+        audit_registration(true, None);
+
         // Send success acknowledgment
         let success_ack = IngressMessage::RegistrationAck {
             id: connection_id,
@@ -134,16 +442,48 @@ impl DefaultMessageDispatcher {
     }
 
     /// Handle service deregistration messages
+    ///
+    /// Registrations are keyed by the connection that made them, so a
+    /// connection may only deregister its own.
     async fn handle_service_deregistration(
         &self,
+        connection_id: Uuid,
         service_id: Uuid,
         registry: &dyn Registry,
     ) -> IngressResult<()> {
+        let registration = registry.get_all_registrations().await?.remove(&service_id);
+        if service_id != connection_id {
+            warn!(
+                "Connection {} tried to deregister service {} of another connection",
+                connection_id, service_id
+            );
+            if let Some(registration) = registration {
+                self.audit.record(AuditEvent::Deregistration {
+                    connection_id,
+                    arn: registry.get_identity(connection_id).await?.map(|i| i.arn),
+                    service_name: registration.service_name,
+                    host: registration.host,
+                    reason: "refused".to_string(),
+                });
+            }
+            return Err(IngressError::bad_request(
+                "Cannot deregister a service registered by another connection",
+            ));
+        }
         if let Err(e) = registry.deregister_service(service_id).await {
             error!("Failed to deregister service {}: {}", service_id, e);
             Err(e)
         } else {
             info!("Service deregistered: {}", service_id);
+            if let Some(registration) = registration {
+                self.audit.record(AuditEvent::Deregistration {
+                    connection_id,
+                    arn: registry.get_identity(connection_id).await?.map(|i| i.arn),
+                    service_name: registration.service_name,
+                    host: registration.host,
+                    reason: "requested".to_string(),
+                });
+            }
             Ok(())
         }
     }
@@ -162,11 +502,49 @@ impl MessageDispatcher for DefaultMessageDispatcher {
         // Parse the incoming message
         let ingress_message = self.parse_message(&message)?;
 
+        // Only the auth handshake is accepted until the connection authenticates
+        if !matches!(
+            ingress_message,
+            IngressMessage::IamAuth(_) | IngressMessage::TokenAuth(_) | IngressMessage::JwtAuth(_)
+        ) && !registry.is_authenticated(connection_id).await?
+        {
+            return self
+                .reject_unauthenticated(connection_id, &ingress_message, registry)
+                .await;
+        }
+
         // Dispatch based on message type
         match ingress_message {
             IngressMessage::IamAuth(auth_request) => {
-                self.handle_iam_auth(connection_id, auth_request, auth_service, registry)
-                    .await
+                let challenge = registry.take_auth_challenge(connection_id).await?;
+                self.handle_auth(
+                    connection_id,
+                    AuthRequest::Iam {
+                        request: auth_request,
+                        challenge,
+                    },
+                    auth_service,
+                    registry,
+                )
+                .await
+            }
+            IngressMessage::TokenAuth(auth_request) => {
+                self.handle_auth(
+                    connection_id,
+                    AuthRequest::Token(auth_request),
+                    auth_service,
+                    registry,
+                )
+                .await
+            }
+            IngressMessage::JwtAuth(auth_request) => {
+                self.handle_auth(
+                    connection_id,
+                    AuthRequest::Jwt(auth_request),
+                    auth_service,
+                    registry,
+                )
+                .await
             }
             IngressMessage::ServiceRegistration(registration) => {
                 self.handle_service_registration(connection_id, registration, registry)
@@ -180,7 +558,8 @@ impl MessageDispatcher for DefaultMessageDispatcher {
                 self.handle_proxy_response(response, router).await
             }
             IngressMessage::ServiceDeregistration { id } => {
-                self.handle_service_deregistration(id, registry).await
+                self.handle_service_deregistration(connection_id, id, registry)
+                    .await
             }
             IngressMessage::WebSocketProxyInitAck {
                 session_id,
@@ -248,6 +627,13 @@ impl MessageDispatcher for DefaultMessageDispatcher {
             Err(IngressError::registry_not_found(connection_id))
         }
     }
+
+    fn update_registration_policy(&self, registration_policy: RegistrationPolicy) {
+        *self
+            .registration_policy
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = registration_policy;
+    }
 }
 
 impl Default for DefaultMessageDispatcher {
@@ -259,7 +645,8 @@ impl Default for DefaultMessageDispatcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::common::ServiceRegistration;
+    use crate::common::config::RoutingConfig;
+    use crate::common::{PathMatchType, PathRoute, ServiceRegistration};
     use crate::server::registry::{DefaultRegistry, Registry};
     use std::collections::HashMap;
     use tokio::sync::mpsc;
@@ -297,6 +684,7 @@ mod tests {
             cluster_name: "test-cluster".to_string(),
             task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
             health_check_path: Some("/health".to_string()),
+            paths: Vec::new(),
             attributes: HashMap::new(),
         };
 
@@ -333,6 +721,7 @@ mod tests {
             cluster_name: "test-cluster".to_string(),
             task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
             health_check_path: Some("/health".to_string()),
+            paths: Vec::new(),
             attributes: HashMap::new(),
         };
 
@@ -347,6 +736,219 @@ mod tests {
         assert_eq!(all_registrations.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_registration_denied_by_policy() {
+        let dispatcher =
+            DefaultMessageDispatcher::with_registration_policy(RegistrationPolicy::new(vec![
+                crate::common::config::RegistrationGrant {
+                    principal: "arn:aws:sts::123456789012:assumed-role/TeamA/*".to_string(),
+                    hosts: vec!["*.team-a.example.com".to_string()],
+                    ..Default::default()
+                },
+            ]));
+        let registry = DefaultRegistry::new();
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .set_authenticated(
+                connection_id,
+                crate::common::IamIdentity {
+                    arn: "arn:aws:sts::123456789012:assumed-role/TeamA/i-123".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_id: "AROAEXAMPLE:i-123".to_string(),
+                    principal_type: "AssumedRole".to_string(),
+                    role_arn: None,
+                    session_name: None,
+                    expires_at: None,
+                    allowed_hosts: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let registration = ServiceRegistration {
+            id: connection_id,
+            service_name: "test-service".to_string(),
+            host: "*.example.com".to_string(),
+            port: 8080,
+            cluster_name: "test-cluster".to_string(),
+            task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
+            health_check_path: None,
+            paths: Vec::new(),
+            attributes: HashMap::new(),
+        };
+
+        dispatcher
+            .handle_service_registration(connection_id, registration, &registry)
+            .await
+            .unwrap();
+
+        assert!(registry.get_all_registrations().await.unwrap().is_empty());
+        match receiver.recv().await {
+            Some(IngressMessage::RegistrationAck {
+                success, message, ..
+            }) => {
+                assert!(!success);
+                assert!(message.contains("host *.example.com is not permitted"));
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registration_rejects_overlapping_paths() {
+        let dispatcher = DefaultMessageDispatcher::new();
+        let registry = DefaultRegistry::new();
+
+        let register = |service_name: &str, paths: Vec<PathRoute>| {
+            let service_name = service_name.to_string();
+            let dispatcher = &dispatcher;
+            let registry = &registry;
+            async move {
+                let connection_id = Uuid::new_v4();
+                let (sender, mut receiver) = mpsc::unbounded_channel();
+                registry
+                    .register_connection(connection_id, sender)
+                    .await
+                    .unwrap();
+                let registration = ServiceRegistration {
+                    id: connection_id,
+                    service_name,
+                    host: "app.example.com".to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task"
+                        .to_string(),
+                    health_check_path: None,
+                    paths,
+                    attributes: HashMap::new(),
+                };
+                dispatcher
+                    .handle_service_registration(connection_id, registration, registry)
+                    .await
+                    .unwrap();
+                match receiver.recv().await {
+                    Some(IngressMessage::RegistrationAck {
+                        success, message, ..
+                    }) => (success, message),
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+        };
+        let prefix = |path: &str| PathRoute {
+            match_type: PathMatchType::Prefix,
+            path: path.to_string(),
+        };
+
+        assert!(register("frontend", Vec::new()).await.0);
+        assert!(register("api", vec![prefix("/api")]).await.0);
+        // Replicas of the same service share its paths
+        assert!(register("api", vec![prefix("/api")]).await.0);
+
+        let (success, message) = register("admin", vec![prefix("/api/")]).await;
+        assert!(!success);
+        assert!(message.contains("already routed to api"), "{}", message);
+
+        let (success, message) = register(
+            "events",
+            vec![PathRoute {
+                match_type: PathMatchType::Regex,
+                path: "/v[0-9+/events".to_string(),
+            }],
+        )
+        .await;
+        assert!(!success);
+        assert!(message.contains("invalid path regex"), "{}", message);
+
+        assert_eq!(registry.get_all_registrations().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_registration_rejects_host_of_another_cluster() {
+        let dispatcher = DefaultMessageDispatcher::new().with_routing_config(
+            SharedRoutingConfig::new(RoutingConfig {
+                host_conflicts: HostConflictPolicy::Reject,
+                ..Default::default()
+            }),
+        );
+        let registry = DefaultRegistry::new();
+
+        let register = |cluster_name: &str, host: &str, paths: &[&str]| {
+            let cluster_name = cluster_name.to_string();
+            let host = host.to_string();
+            let paths: Vec<_> = paths
+                .iter()
+                .map(|path| PathRoute {
+                    match_type: PathMatchType::Prefix,
+                    path: path.to_string(),
+                })
+                .collect();
+            let dispatcher = &dispatcher;
+            let registry = &registry;
+            async move {
+                let connection_id = Uuid::new_v4();
+                let (sender, mut receiver) = mpsc::unbounded_channel();
+                registry
+                    .register_connection(connection_id, sender)
+                    .await
+                    .unwrap();
+                let registration = ServiceRegistration {
+                    id: connection_id,
+                    service_name: "web".to_string(),
+                    host,
+                    port: 8080,
+                    cluster_name,
+                    task_arn: String::new(),
+                    health_check_path: None,
+                    paths,
+                    attributes: HashMap::new(),
+                };
+                dispatcher
+                    .handle_service_registration(connection_id, registration, registry)
+                    .await
+                    .unwrap();
+                match receiver.recv().await {
+                    Some(IngressMessage::RegistrationAck {
+                        success, message, ..
+                    }) => (success, message),
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+        };
+
+        assert!(register("edge-1", "{tenant}.example.com", &[]).await.0);
+        // Another replica in the same cluster
+        assert!(register("edge-1", "{tenant}.example.com", &[]).await.0);
+
+        // The same pattern under a different capture name is still the same host
+        let (success, message) = register("edge-2", "{customer}.example.com", &[]).await;
+        assert!(!success);
+        assert!(message.contains("served by cluster edge-1"), "{}", message);
+
+        // Narrowing, widening or adding paths would still take over traffic
+        for (host, paths) in [
+            ("acme.example.com", &[][..]),
+            ("**.example.com", &[][..]),
+            ("{tenant}.example.com", &["/api"][..]),
+        ] {
+            let (success, message) = register("edge-2", host, paths).await;
+            assert!(!success, "{}", host);
+            assert!(message.contains("served by cluster edge-1"), "{}", message);
+        }
+        assert!(register("edge-2", "edge.example.org", &[]).await.0);
+
+        let (success, message) = register("edge-2", "*.{bad.example.com", &[]).await;
+        assert!(!success);
+        assert!(message.contains("invalid label"), "{}", message);
+
+        assert_eq!(registry.get_all_registrations().await.unwrap().len(), 3);
+    }
+
     #[tokio::test]
     async fn test_handle_service_deregistration() {
         let dispatcher = DefaultMessageDispatcher::new();
@@ -362,6 +964,7 @@ mod tests {
             cluster_name: "test-cluster".to_string(),
             task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
             health_check_path: Some("/health".to_string()),
+            paths: Vec::new(),
             attributes: HashMap::new(),
         };
 
@@ -370,9 +973,17 @@ mod tests {
             .await
             .unwrap();
 
+        // Another connection can't deregister it
+        let other_connection = Uuid::new_v4();
+        assert!(dispatcher
+            .handle_service_deregistration(other_connection, connection_id, &registry)
+            .await
+            .is_err());
+        assert_eq!(registry.get_all_registrations().await.unwrap().len(), 1);
+
         // Deregister the service
         let result = dispatcher
-            .handle_service_deregistration(connection_id, &registry)
+            .handle_service_deregistration(connection_id, connection_id, &registry)
             .await;
 
         assert!(result.is_ok());
@@ -382,6 +993,315 @@ mod tests {
         assert_eq!(all_registrations.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_unauthenticated_registration_rejected() {
+        let dispatcher = DefaultMessageDispatcher::new();
+        let registry = DefaultRegistry::new();
+        let auth_service = crate::server::auth::DefaultAuthService::new(
+            hyper::Client::builder().build(hyper_tls::HttpsConnector::new()),
+            vec!["*".to_string()],
+            true,
+        );
+        let router = crate::server::router::DefaultRouter::default();
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+
+        let registration = IngressMessage::ServiceRegistration(ServiceRegistration {
+            id: connection_id,
+            service_name: "test-service".to_string(),
+            host: "victim.example.com".to_string(),
+            port: 8080,
+            cluster_name: "test-cluster".to_string(),
+            task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
+            health_check_path: None,
+            paths: Vec::new(),
+            attributes: HashMap::new(),
+        });
+        let registration_json = serde_json::to_string(&registration).unwrap();
+
+        // Registration before auth is rejected with a failed ack
+        let result = dispatcher
+            .handle_message(
+                connection_id,
+                registration_json.clone(),
+                &auth_service,
+                &registry,
+                &router,
+            )
+            .await;
+        assert!(matches!(result, Err(IngressError::Unauthenticated { .. })));
+        assert!(registry.get_all_registrations().await.unwrap().is_empty());
+        assert!(matches!(
+            receiver.recv().await,
+            Some(IngressMessage::RegistrationAck { success: false, .. })
+        ));
+
+        // After a successful handshake the same registration is accepted
+        let auth = IngressMessage::IamAuth(crate::common::IamAuthRequest {
+            presigned_url: None,
+            region: "us-east-1".to_string(),
+            arn: None,
+            account_id: None,
+            user_id: None,
+        });
+        dispatcher
+            .handle_message(
+                connection_id,
+                serde_json::to_string(&auth).unwrap(),
+                &auth_service,
+                &registry,
+                &router,
+            )
+            .await
+            .unwrap();
+        assert!(registry.is_authenticated(connection_id).await.unwrap());
+
+        dispatcher
+            .handle_message(
+                connection_id,
+                registration_json,
+                &auth_service,
+                &registry,
+                &router,
+            )
+            .await
+            .unwrap();
+        assert_eq!(registry.get_all_registrations().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_reauthentication_disconnects() {
+        let dispatcher = DefaultMessageDispatcher::new();
+        let registry = DefaultRegistry::new();
+        let auth_service = crate::server::auth::TokenAuthService::new("secret");
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .set_authenticated(
+                connection_id,
+                crate::common::IamIdentity {
+                    arn: "mesh:token/edge-box-1".to_string(),
+                    account_id: String::new(),
+                    user_id: "edge-box-1".to_string(),
+                    principal_type: "MeshToken".to_string(),
+                    role_arn: None,
+                    session_name: None,
+                    expires_at: None,
+                    allowed_hosts: None,
+                },
+            )
+            .await
+            .unwrap();
+        registry
+            .register_service(
+                connection_id,
+                ServiceRegistration {
+                    id: connection_id,
+                    service_name: "test-service".to_string(),
+                    host: "api.example.com".to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test".to_string(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: HashMap::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        dispatcher
+            .handle_auth(
+                connection_id,
+                AuthRequest::Token(crate::common::TokenAuthRequest {
+                    token: "not-a-token".to_string(),
+                }),
+                &auth_service,
+                &registry,
+            )
+            .await
+            .unwrap();
+
+        match receiver.recv().await {
+            Some(IngressMessage::IamAuthResponse(response)) => assert!(!response.success),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        // The sender was dropped, so the connection winds down
+        assert!(receiver.recv().await.is_none());
+        assert!(registry.get_all_registrations().await.unwrap().is_empty());
+        assert!(!registry.is_authenticated(connection_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failed_iam_auth_issues_new_challenge() {
+        let dispatcher = DefaultMessageDispatcher::new();
+        let registry = DefaultRegistry::new();
+        let auth_service = crate::server::auth::DefaultAuthService::new(
+            hyper::Client::builder().build(hyper_tls::HttpsConnector::new()),
+            vec!["*".to_string()],
+            false,
+        );
+        let router = crate::server::router::DefaultRouter::default();
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .set_auth_challenge(connection_id, "first".to_string())
+            .await
+            .unwrap();
+
+        let auth = serde_json::to_string(&IngressMessage::IamAuth(
+            crate::common::auth::IamAuthRequest {
+                presigned_url: Some("not a url".to_string()),
+                region: "us-east-1".to_string(),
+                arn: None,
+                account_id: None,
+                user_id: None,
+            },
+        ))
+        .unwrap();
+        dispatcher
+            .handle_message(connection_id, auth, &auth_service, &registry, &router)
+            .await
+            .unwrap();
+
+        match receiver.recv().await {
+            Some(IngressMessage::IamAuthResponse(response)) => assert!(!response.success),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let nonce = match receiver.recv().await {
+            Some(IngressMessage::AuthChallenge { nonce }) => nonce,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_ne!(nonce, "first");
+        assert_eq!(
+            registry.take_auth_challenge(connection_id).await.unwrap(),
+            Some(nonce)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_security_events_are_audited() {
+        let audit_path =
+            std::env::temp_dir().join(format!("mesh-dispatcher-audit-{}.log", Uuid::new_v4()));
+        let dispatcher = DefaultMessageDispatcher::new()
+            .with_audit_log(Arc::new(AuditLog::open(&audit_path).unwrap()));
+        let registry = DefaultRegistry::new();
+        let auth_service = crate::server::auth::DefaultAuthService::new(
+            hyper::Client::builder().build(hyper_tls::HttpsConnector::new()),
+            vec!["*".to_string()],
+            true,
+        );
+        let token_auth = crate::server::auth::TokenAuthService::new("secret");
+        let iam_auth = AuthRequest::Iam {
+            request: crate::common::IamAuthRequest {
+                presigned_url: None,
+                region: "us-east-1".to_string(),
+                arn: None,
+                account_id: None,
+                user_id: None,
+            },
+            challenge: None,
+        };
+
+        let mut connections = Vec::new();
+        for (service_name, peer_addr) in [
+            ("billing", "10.0.0.1:40000"),
+            ("intruder", "10.0.0.2:40000"),
+        ] {
+            let connection_id = Uuid::new_v4();
+            let (sender, receiver) = mpsc::unbounded_channel();
+            registry
+                .register_connection(connection_id, sender)
+                .await
+                .unwrap();
+            registry
+                .set_peer_addr(connection_id, peer_addr.parse().unwrap())
+                .await
+                .unwrap();
+            dispatcher
+                .handle_auth(connection_id, iam_auth.clone(), &auth_service, &registry)
+                .await
+                .unwrap();
+            dispatcher
+                .handle_service_registration(
+                    connection_id,
+                    ServiceRegistration {
+                        id: connection_id,
+                        service_name: service_name.to_string(),
+                        host: "billing.example.com".to_string(),
+                        port: 8080,
+                        cluster_name: "test-cluster".to_string(),
+                        task_arn: String::new(),
+                        health_check_path: None,
+                        paths: Vec::new(),
+                        attributes: HashMap::new(),
+                    },
+                    &registry,
+                )
+                .await
+                .unwrap();
+            connections.push((connection_id, receiver));
+        }
+
+        let (intruder, _) = &connections[1];
+        dispatcher
+            .handle_auth(
+                *intruder,
+                AuthRequest::Token(crate::common::TokenAuthRequest {
+                    token: "not-a-token".to_string(),
+                }),
+                &token_auth,
+                &registry,
+            )
+            .await
+            .unwrap();
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<&str> = records
+            .iter()
+            .map(|r| r["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "auth_success",
+                "registration",
+                "auth_success",
+                "host_conflict",
+                "registration",
+                "auth_failure",
+                "forced_disconnect",
+            ]
+        );
+        assert_eq!(records[0]["source_ip"], "10.0.0.1");
+        assert_eq!(records[3]["existing_service_name"], "billing");
+        assert_eq!(records[3]["rejected"], false);
+        assert_eq!(records[5]["method"], "token");
+        assert_eq!(records[5]["reauth"], true);
+        assert_eq!(records[6]["source_ip"], "10.0.0.2");
+
+        std::fs::remove_file(audit_path).unwrap();
+    }
+
     #[tokio::test]
     async fn test_send_response_no_connection() {
         let dispatcher = DefaultMessageDispatcher::new();
@@ -7,6 +7,9 @@ pub enum IngressError {
     #[error("Bad request: {message}")]
     BadRequest { message: String },
 
+    #[error("Unauthenticated: {message}")]
+    Unauthenticated { message: String },
+
     #[error("Request timeout for request {request_id}")]
     Timeout { request_id: Uuid },
 
@@ -45,6 +48,12 @@ impl IngressError {
         }
     }
 
+    pub fn unauthenticated(message: impl Into<String>) -> Self {
+        Self::Unauthenticated {
+            message: message.into(),
+        }
+    }
+
     pub fn timeout(request_id: Uuid) -> Self {
         Self::Timeout { request_id }
     }
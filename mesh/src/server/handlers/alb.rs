@@ -1,16 +1,23 @@
-use crate::common::ProxyRequest;
+use crate::common::{routing, ProxyRequest};
 use anyhow::Result;
 use hyper::{Body, Method, Request, Response, StatusCode};
 use std::collections::HashMap;
 use std::convert::Infallible;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
+use crate::common::config::{RouteAccessConfig, RoutingConfig};
+use crate::server::access;
+use crate::server::oidc::{user_headers, OidcError, ALB_OIDC_DATA_HEADER, USER_HEADER_PREFIX};
 use crate::server::CombinedIngressService;
 
 impl CombinedIngressService {
-    /// Filter headers to only include those needed for routing and proxying
-    fn filter_proxy_headers(headers: &hyper::HeaderMap) -> HashMap<String, String> {
+    /// Filter headers to only include those needed for routing and proxying,
+    /// plus the `routing_headers` the host's rules and split read
+    fn filter_proxy_headers(
+        headers: &hyper::HeaderMap,
+        routing_headers: &[String],
+    ) -> HashMap<String, String> {
         let mut filtered = HashMap::new();
 
         // Whitelist of headers to forward (essential for routing/proxying)
@@ -33,7 +40,7 @@ impl CombinedIngressService {
 
         for (name, value) in headers.iter() {
             let name_str = name.as_str().to_lowercase();
-            if whitelist.contains(&name_str.as_str()) {
+            if whitelist.contains(&name_str.as_str()) || routing_headers.contains(&name_str) {
                 if let Ok(value_str) = value.to_str() {
                     filtered.insert(name_str, value_str.to_string());
                 }
@@ -43,6 +50,60 @@ impl CombinedIngressService {
         filtered
     }
 
+    /// Verify the ALB OIDC token when the host's route in `routing_config`
+    /// requires one
+    ///
+    /// Returns the trusted `x-mesh-user-*` headers to forward (none when the
+    /// route does not require an end-user identity).
+    pub(crate) async fn authorize_end_user(
+        &self,
+        routing_config: &RoutingConfig,
+        host: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Result<HashMap<String, String>, OidcError> {
+        let Some(oidc) = routing_config
+            .route_for_host(host)
+            .and_then(|route| route.alb_oidc.as_ref())
+        else {
+            return Ok(HashMap::new());
+        };
+
+        let token = headers
+            .get(ALB_OIDC_DATA_HEADER)
+            .and_then(|h| h.to_str().ok());
+        let claims = self.alb_oidc.verify(oidc, token).await?;
+        Ok(user_headers(&claims, &oidc.forward_claims))
+    }
+
+    /// Credentials the host's route in `routing_config` requires, if any
+    pub(crate) fn route_access<'a>(
+        routing_config: &'a RoutingConfig,
+        host: &str,
+    ) -> Option<&'a RouteAccessConfig> {
+        routing_config
+            .route_for_host(host)
+            .and_then(|route| route.access.as_ref())
+    }
+
+    /// 401 for requests without a valid end-user identity
+    pub(crate) fn unauthorized_response(host: &str, error: &OidcError) -> Response<Body> {
+        warn!("Rejected request for {}: {}", host, error);
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::from("Unauthorized"))
+            .unwrap()
+    }
+
+    /// 400 for hosts that are not plain DNS names, so pattern syntax in a
+    /// request host never reaches routing or policy lookup
+    pub(crate) fn invalid_host_response(host: &str) -> Response<Body> {
+        warn!("Rejected request for invalid host {:?}", host);
+        Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Invalid Host"))
+            .unwrap()
+    }
+
     #[instrument(skip(self, req))]
     pub async fn handle_alb_request(
         &self,
@@ -74,8 +135,8 @@ impl CombinedIngressService {
                 .unwrap_or(0);
 
             let health_info = format!(
-                "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"port\":\"8080\"}}",
-                connections_count, registrations_count
+                "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"port\":\"{}\"}}",
+                connections_count, registrations_count, self.config.server.alb_port
             );
 
             return Ok(Response::builder()
@@ -119,9 +180,45 @@ impl CombinedIngressService {
             .map(|s| s.split(':').next().unwrap_or(s).to_string())
             .or_else(|| parts.uri.authority().map(|a| a.host().to_string()))
             .unwrap_or_else(|| "unknown".to_string());
+        if !routing::is_request_host(&host) {
+            return Ok(Self::invalid_host_response(&host));
+        }
+
+        // One snapshot for the whole request, so a reload can't mix settings
+        let routing_config = self.routing.load();
+        let user_headers = match self
+            .authorize_end_user(&routing_config, &host, &parts.headers)
+            .await
+        {
+            Ok(user_headers) => user_headers,
+            Err(e) => return Ok(Self::unauthorized_response(&host, &e)),
+        };
+
+        let route_access = Self::route_access(&routing_config, &host);
+        if let Some(route_access) = route_access {
+            if !access::authorize(route_access, &parts.headers, &parts.uri).await {
+                warn!("Rejected request for {} without valid credentials", host);
+                return Ok(access::unauthorized_response(route_access));
+            }
+        }
 
         // Filter and convert headers to HashMap (only essential headers)
-        let mut headers = Self::filter_proxy_headers(&parts.headers);
+        let mut headers = Self::filter_proxy_headers(
+            &parts.headers,
+            &routing_config.routing_headers_for_host(&host),
+        );
+        // Only the ingress may assert the end-user identity
+        headers.retain(|name, _| !name.starts_with(USER_HEADER_PREFIX));
+        headers.extend(user_headers);
+
+        let mut path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "/".to_string());
+        if let Some(route_access) = route_access {
+            path = access::strip_credentials(route_access, &mut headers, &path);
+        }
         // Ensure proto is set to https when behind ALB TLS termination
         headers.insert("x-forwarded-proto".to_string(), "https".to_string());
 
@@ -129,11 +226,7 @@ impl CombinedIngressService {
         let proxy_request = ProxyRequest {
             id: Uuid::new_v4(),
             method: parts.method.to_string(),
-            path: parts
-                .uri
-                .path_and_query()
-                .map(|p| p.to_string())
-                .unwrap_or_else(|| "/".to_string()),
+            path,
             headers,
             body: if body_bytes.is_empty() {
                 None
@@ -141,6 +234,7 @@ impl CombinedIngressService {
                 Some(body_bytes.to_vec())
             },
             target_host: host,
+            timeouts: None,
         };
 
         // Route request directly through WebSocket connections
@@ -166,3 +260,215 @@ impl CombinedIngressService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::IngressConfig;
+    use crate::common::{IngressMessage, ProxyResponse, ServiceRegistration};
+    use sha2::{Digest, Sha256};
+
+    fn service(routing_yaml: &str) -> CombinedIngressService {
+        CombinedIngressService::new(IngressConfig {
+            routing: serde_yaml::from_str(routing_yaml).unwrap(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Register a client for `host` that answers every request with its
+    /// service name
+    async fn register(
+        service: &CombinedIngressService,
+        service_name: &str,
+        host: &str,
+        attributes: &[(&str, &str)],
+    ) {
+        let id = Uuid::new_v4();
+        let (sender, mut tunnel) = tokio::sync::mpsc::unbounded_channel();
+        service
+            .registry
+            .register_connection(id, sender)
+            .await
+            .unwrap();
+        service
+            .registry
+            .register_service(
+                id,
+                ServiceRegistration {
+                    id,
+                    service_name: service_name.to_string(),
+                    host: host.to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: String::new(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: attributes
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let router = service.router.clone();
+        let service_name = service_name.to_string();
+        tokio::spawn(async move {
+            while let Some(IngressMessage::ProxyRequestForward(request)) = tunnel.recv().await {
+                let response = ProxyResponse {
+                    id: request.id,
+                    status_code: 200,
+                    headers: Vec::new(),
+                    body: Some(service_name.as_bytes().to_vec()),
+                };
+                router.handle_response(response).await.unwrap();
+            }
+        });
+    }
+
+    async fn send(
+        service: &CombinedIngressService,
+        host: &str,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, String) {
+        let mut request = Request::builder().uri("/").header("host", host);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = service
+            .process_alb_request(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_pattern_host_cannot_bypass_route_access() {
+        let service = service(&format!(
+            r#"
+routes:
+  - host: "*.example.com"
+    access:
+      api_keys:
+        key_sha256: ["{}"]
+"#,
+            hex::encode(Sha256::digest(b"key-1"))
+        ));
+        register(&service, "api", r"~api-[0-9]+\.example\.com", &[]).await;
+        register(&service, "internal", "**.internal.example.com", &[]).await;
+
+        assert_eq!(
+            send(&service, "api-1.example.com", &[]).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&service, "api-1.example.com", &[("x-api-key", "key-1")]).await,
+            (StatusCode::OK, "api".to_string())
+        );
+
+        // A host spelled as the registered pattern matches neither the
+        // registration nor the route's policy, so it must not be proxied
+        for host in [r"~api-[0-9]+\.example\.com", "**.internal.example.com"] {
+            assert_eq!(send(&service, host, &[]).await.0, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rules_and_splits_see_custom_headers() {
+        let service = service(
+            r#"
+routes:
+  - host: shop.example.com
+    rules:
+      - match:
+          headers:
+            X-Canary: "true"
+        attributes:
+          track: canary
+  - host: app.example.com
+    split:
+      attribute: version
+      hash_on: "header:x-user"
+      weights:
+        "1": 50
+        "2": 50
+"#,
+        );
+        register(&service, "stable", "shop.example.com", &[]).await;
+        register(
+            &service,
+            "canary",
+            "shop.example.com",
+            &[("track", "canary")],
+        )
+        .await;
+        assert_eq!(send(&service, "shop.example.com", &[]).await.1, "stable");
+        assert_eq!(
+            send(&service, "shop.example.com", &[("x-canary", "true")])
+                .await
+                .1,
+            "canary"
+        );
+
+        register(&service, "v1", "app.example.com", &[("version", "1")]).await;
+        register(&service, "v2", "app.example.com", &[("version", "2")]).await;
+        for user in ["alice", "bob", "carol", "dave"] {
+            let first = send(&service, "app.example.com", &[("x-user", user)])
+                .await
+                .1;
+            for _ in 0..10 {
+                assert_eq!(
+                    send(&service, "app.example.com", &[("x-user", user)])
+                        .await
+                        .1,
+                    first
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reloaded_access_applies_to_requests() {
+        let service = service("routes: []");
+        register(&service, "api", "api.example.com", &[]).await;
+        assert_eq!(
+            send(&service, "api.example.com", &[]).await.0,
+            StatusCode::OK
+        );
+
+        let routing = |access: &str| -> RoutingConfig {
+            serde_yaml::from_str(&format!(
+                "routes:\n  - host: api.example.com\n    access:\n{}",
+                access
+            ))
+            .unwrap()
+        };
+        service
+            .update_routing(routing(&format!(
+                "      api_keys:\n        key_sha256: [\"{}\"]\n",
+                hex::encode(Sha256::digest(b"key-1"))
+            )))
+            .unwrap();
+        assert_eq!(
+            send(&service, "api.example.com", &[]).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&service, "api.example.com", &[("x-api-key", "key-1")])
+                .await
+                .0,
+            StatusCode::OK
+        );
+
+        // Invalid settings are refused and the current ones stay in force
+        assert!(service.update_routing(routing("      {}\n")).is_err());
+        assert_eq!(
+            send(&service, "api.example.com", &[]).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
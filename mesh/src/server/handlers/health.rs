@@ -28,10 +28,11 @@ impl CombinedIngressService {
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap_or_else(|_| std::time::Duration::from_secs(0))
                     .as_secs();
+                let ejected_count = self.router.ejections().len();
 
                 let health_info = format!(
-                    "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"instance_id\":\"{}\",\"started_at\":{}}}",
-                    connections_count, registrations_count, instance_id, started_at
+                    "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"ejected\":{},\"instance_id\":\"{}\",\"started_at\":{}}}",
+                    connections_count, registrations_count, ejected_count, instance_id, started_at
                 );
 
                 Ok(Response::builder()
@@ -54,10 +55,16 @@ impl CombinedIngressService {
                     .map(|r| r.len())
                     .unwrap_or(0);
 
+                let retry_stats = self.router.retry_stats();
+                let ejected_count = self.router.ejections().len();
+
                 let metrics = format!(
-                    "# HELP connections_total Total number of WebSocket connections\n# TYPE connections_total gauge\nconnections_total {}\n# HELP registrations_total Total number of service registrations\n# TYPE registrations_total gauge\nregistrations_total {}\n",
+                    "# HELP connections_total Total number of WebSocket connections\n# TYPE connections_total gauge\nconnections_total {}\n# HELP registrations_total Total number of service registrations\n# TYPE registrations_total gauge\nregistrations_total {}\n# HELP request_retries_total Requests retried on another instance\n# TYPE request_retries_total counter\nrequest_retries_total {}\n# HELP request_retries_budget_exhausted_total Retries skipped because the retry budget was spent\n# TYPE request_retries_budget_exhausted_total counter\nrequest_retries_budget_exhausted_total {}\n# HELP registrations_ejected Registrations ejected for failing requests\n# TYPE registrations_ejected gauge\nregistrations_ejected {}\n",
                     connections_count,
-                    registrations_count
+                    registrations_count,
+                    retry_stats.retries,
+                    retry_stats.budget_exhausted,
+                    ejected_count
                 );
 
                 Ok(Response::builder()
@@ -66,6 +73,36 @@ impl CombinedIngressService {
                     .body(Body::from(metrics))
                     .unwrap())
             }
+            (&Method::GET, "/outliers") => {
+                let registrations = self
+                    .registry
+                    .get_all_registrations()
+                    .await
+                    .unwrap_or_default();
+                let ejected: Vec<_> = self
+                    .router
+                    .ejections()
+                    .into_iter()
+                    .map(|ejection| {
+                        let registration = registrations.get(&ejection.registration_id);
+                        serde_json::json!({
+                            "registration_id": ejection.registration_id,
+                            "service_name": registration.map(|reg| reg.service_name.as_str()),
+                            "host": registration.map(|reg| reg.host.as_str()),
+                            "remaining_secs": ejection.remaining.as_secs(),
+                            "ejections": ejection.ejections,
+                        })
+                    })
+                    .collect();
+
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "ejected": ejected }).to_string(),
+                    ))
+                    .unwrap())
+            }
             _ => Ok(Response::builder()
                 .status(StatusCode::NOT_FOUND)
                 .body(Body::from("Not Found"))
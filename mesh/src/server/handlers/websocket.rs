@@ -2,6 +2,7 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use std::convert::Infallible;
 use tracing::{error, instrument};
 
+use crate::server::tls::{PeerAddr, PeerIdentity};
 use crate::server::CombinedIngressService;
 
 impl CombinedIngressService {
@@ -34,8 +35,12 @@ impl CombinedIngressService {
 
                 // TODO: add metrics
                 let health_info = format!(
-                    "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"port\":\"8082\",\"instance_id\":\"{}\",\"started_at\":{}}}",
-                    connections_count, registrations_count, instance_id, started_at
+                    "{{\"status\":\"healthy\",\"connections\":{},\"registrations\":{},\"port\":\"{}\",\"instance_id\":\"{}\",\"started_at\":{}}}",
+                    connections_count,
+                    registrations_count,
+                    self.config.server.websocket_port,
+                    instance_id,
+                    started_at
                 );
 
                 Ok(Response::builder()
@@ -65,6 +70,10 @@ impl CombinedIngressService {
                         key.as_bytes(),
                     );
 
+                    // Identity from a verified client certificate, if any
+                    let peer_identity = req.extensions().get::<PeerIdentity>().map(|p| p.0.clone());
+                    let peer_addr = req.extensions().get::<PeerAddr>().map(|p| p.0);
+
                     // Handle WebSocket upgrade
                     let service = self.clone();
                     tokio::spawn(async move {
@@ -77,7 +86,10 @@ impl CombinedIngressService {
                                 )
                                 .await;
 
-                                if let Err(e) = service.handle_websocket_stream(stream).await {
+                                if let Err(e) = service
+                                    .handle_websocket_stream(stream, peer_identity, peer_addr)
+                                    .await
+                                {
                                     error!("WebSocket connection error: {}", e);
                                 }
                             }
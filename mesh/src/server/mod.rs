@@ -1,38 +1,156 @@
 use anyhow::Result;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 
 use crate::commands::ServerCommand;
+use crate::common::config::IngressConfig;
+use audit::AuditEvent;
 use service::CombinedIngressService;
+use tls::PeerAddr;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{error, info};
 // Import handlers to bring the impl blocks into scope
 #[allow(unused_imports)]
 use handlers::{alb, health, websocket};
 
+mod access;
+mod audit;
 pub mod auth;
+mod authz;
 mod dispatcher;
 mod error;
 mod handlers;
+mod oidc;
+mod outlier;
 mod registry;
 mod router;
+mod routing_table;
 mod service;
+mod tls;
 mod ws_proxy;
 
+/// Resolve the effective ingress configuration
+///
+/// Precedence, lowest to highest: built-in defaults, the `--config` YAML file,
+/// environment variables (`SKIP_IAM_VALIDATION`, `ALLOWED_ROLE_ARNS`), CLI flags.
+pub fn load_config(args: &ServerCommand) -> Result<IngressConfig> {
+    let mut config = match &args.config {
+        Some(path) => {
+            info!("📄 Loading configuration from {}", path.display());
+            IngressConfig::from_file(path)?
+        }
+        None => IngressConfig::default(),
+    };
+
+    config.apply_env_overrides();
+
+    if let Some(alb_port) = args.alb_port {
+        config.server.alb_port = alb_port;
+    }
+    if let Some(websocket_port) = args.websocket_port {
+        config.server.websocket_port = websocket_port;
+    }
+    if let Some(health_port) = args.health_port {
+        config.server.health_port = health_port;
+    }
+    if let Some(request_timeout) = args.request_timeout {
+        config.server.request_timeout = request_timeout;
+    }
+
+    Ok(config)
+}
+
+/// Whether the configured audit log writes to stdout
+///
+/// Checked before logging is set up, so a config that fails to load counts as
+/// no; `run` reports the error.
+pub fn audit_to_stdout(args: &ServerCommand) -> bool {
+    load_config(args).is_ok_and(|config| config.logging.audit_to_stdout())
+}
+
 pub async fn run(args: ServerCommand) -> Result<()> {
-    println!("Starting Anywhere Mesh Server...");
+    let config = load_config(&args)?;
+
+    if !config.logging.audit_to_stdout() {
+        println!("Starting Anywhere Mesh Server...");
+    }
 
     info!("🚀 Starting Combined Ingress Service");
-    info!("📡 ALB port: {}", args.alb_port);
-    info!("🔌 WebSocket port: {}", args.websocket_port);
-    info!("⏱️  Request timeout: {}s", args.request_timeout);
+    info!("📡 ALB port: {}", config.server.alb_port);
+    info!("🔌 WebSocket port: {}", config.server.websocket_port);
+    info!("🏥 Health port: {}", config.server.health_port);
+    info!("⏱️  Request timeout: {}s", config.server.request_timeout);
+    info!(
+        "🔐 Auth: skip_validation={}, allowed_role_arns={:?}",
+        config.auth.skip_validation, config.auth.allowed_role_arns
+    );
 
-    let service = CombinedIngressService::new();
+    let alb_port = config.server.alb_port;
+    let health_port = config.server.health_port;
+    let websocket_port = config.server.websocket_port;
+
+    // Fail fast on bad certificate/key files
+    let tls_acceptor = match &config.server.tls {
+        Some(tls_config) => {
+            info!(
+                "🔒 TLS enabled on WebSocket port (client certificates: {})",
+                match (&tls_config.client_ca_file, tls_config.require_client_cert) {
+                    (Some(_), true) => "required",
+                    (Some(_), false) => "optional",
+                    (None, _) => "disabled",
+                }
+            );
+            Some(tls::build_acceptor(tls_config)?)
+        }
+        None => None,
+    };
+    let ws_scheme = if tls_acceptor.is_some() { "wss" } else { "ws" };
+
+    let service = CombinedIngressService::new(config)?;
+
+    // Reload the allow-list, registration policy and routing settings on SIGHUP;
+    // existing connections re-authenticate against the allow-list
+    #[cfg(unix)]
+    {
+        let reload_service = service.clone();
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!("Failed to install SIGHUP handler: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                info!("🔄 SIGHUP received, reloading configuration");
+                let reloaded = load_config(&args).and_then(|config| {
+                    reload_service.update_routing(config.routing)?;
+                    reload_service.update_registration_policy(config.auth.registration_policy)?;
+                    Ok(config.auth.allowed_role_arns)
+                });
+                match reloaded {
+                    Ok(allowed_role_arns) => {
+                        reload_service.update_allowed_role_arns(allowed_role_arns)
+                    }
+                    Err(e) => {
+                        error!("Failed to reload configuration: {}", e);
+                        reload_service.audit.record(AuditEvent::ConfigReload {
+                            success: false,
+                            allowed_role_arns: Vec::new(),
+                            error: Some(e.to_string()),
+                        });
+                    }
+                }
+            }
+        });
+    }
 
     // Start ALB HTTP server
     let alb_service = service.clone();
-    let alb_port = args.alb_port;
     let alb_handle = tokio::spawn(async move {
         let make_svc = make_service_fn(move |_conn| {
             let service = alb_service.clone();
@@ -67,7 +185,7 @@ pub async fn run(args: ServerCommand) -> Result<()> {
             }
         });
 
-        let internal_addr = SocketAddr::from(([0, 0, 0, 0], 8081));
+        let internal_addr = SocketAddr::from(([0, 0, 0, 0], health_port));
         let server = Server::bind(&internal_addr).serve(make_svc);
 
         info!("🏥 Internal HTTP server listening on {}", internal_addr);
@@ -79,19 +197,29 @@ pub async fn run(args: ServerCommand) -> Result<()> {
 
     // Start WebSocket/Health Check server
     let ws_service = service.clone();
-    let websocket_port = args.websocket_port;
     let ws_handle = tokio::spawn(async move {
-        let make_svc = make_service_fn(move |_conn| {
+        let ws_addr = SocketAddr::from(([0, 0, 0, 0], websocket_port));
+
+        if let Some(acceptor) = tls_acceptor {
+            info!("🔌 WebSocket/Health server listening on {} (TLS)", ws_addr);
+            if let Err(e) = tls::serve(ws_addr, acceptor, ws_service).await {
+                error!("WebSocket/Health server error: {}", e);
+            }
+            return;
+        }
+
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
             let service = ws_service.clone();
+            let peer_addr = PeerAddr(conn.remote_addr());
             async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
+                Ok::<_, Infallible>(service_fn(move |mut req| {
                     let service = service.clone();
+                    req.extensions_mut().insert(peer_addr);
                     async move { service.handle_websocket_or_health_request(req).await }
                 }))
             }
         });
 
-        let ws_addr = SocketAddr::from(([0, 0, 0, 0], websocket_port));
         let server = Server::bind(&ws_addr).serve(make_svc);
 
         info!("🔌 WebSocket/Health server listening on {}", ws_addr);
@@ -105,11 +233,11 @@ pub async fn run(args: ServerCommand) -> Result<()> {
 
     println!();
     println!("🎯 Server Endpoints:");
-    println!("   🌐 ALB Traffic:     http://0.0.0.0:{}", args.alb_port);
-    println!("   🏥 Health/Metrics:  http://0.0.0.0:8081");
+    println!("   🌐 ALB Traffic:     http://0.0.0.0:{}", alb_port);
+    println!("   🏥 Health/Metrics:  http://0.0.0.0:{}", health_port);
     println!(
-        "   🔌 WebSocket:       ws://0.0.0.0:{}",
-        args.websocket_port
+        "   🔌 WebSocket:       {}://0.0.0.0:{}",
+        ws_scheme, websocket_port
     );
     println!();
     println!("📊 Ready to accept connections!");
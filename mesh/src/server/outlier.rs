@@ -0,0 +1,287 @@
+//! Per-registration request outcomes, and the temporary ejection of
+//! registrations that keep failing.
+
+use crate::common::config::OutlierDetectionConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long outcomes are kept for a registration no request has gone to
+const IDLE_OUTCOMES_TTL: Duration = Duration::from_secs(600);
+
+/// A registration currently out of rotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ejection {
+    pub registration_id: Uuid,
+    /// Time until it is back in rotation
+    pub remaining: Duration,
+    /// Ejections in a row, this one included
+    pub ejections: u32,
+}
+
+/// Outcomes of the requests sent to one registration
+struct Outcomes {
+    consecutive_failures: u32,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    /// Ejections in a row; each one lasts twice as long as the last
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    last_seen: Instant,
+}
+
+impl Outcomes {
+    fn new(now: Instant) -> Self {
+        Self {
+            consecutive_failures: 0,
+            window_start: now,
+            requests: 0,
+            failures: 0,
+            ejections: 0,
+            ejected_until: None,
+            last_seen: now,
+        }
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+
+    fn reset_counts(&mut self, now: Instant) {
+        self.consecutive_failures = 0;
+        self.window_start = now;
+        self.requests = 0;
+        self.failures = 0;
+    }
+}
+
+struct State {
+    registrations: HashMap<Uuid, Outcomes>,
+    last_sweep: Instant,
+}
+
+/// Tracks request outcomes per registration and ejects the failing ones
+pub struct OutlierDetector {
+    state: Mutex<State>,
+}
+
+impl Default for OutlierDetector {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                registrations: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl OutlierDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of a request to `registration_id`, one of the
+    /// registrations in `pool`; returns how long it is ejected for, if this
+    /// outcome ejected it
+    pub fn record(
+        &self,
+        config: &OutlierDetectionConfig,
+        registration_id: Uuid,
+        success: bool,
+        pool: &[Uuid],
+    ) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        if now.duration_since(state.last_sweep) >= IDLE_OUTCOMES_TTL {
+            state.last_sweep = now;
+            state.registrations.retain(|_, outcomes| {
+                outcomes.is_ejected(now)
+                    || now.duration_since(outcomes.last_seen) < IDLE_OUTCOMES_TTL
+            });
+        }
+
+        let already_ejected = pool
+            .iter()
+            .filter(|id| **id != registration_id)
+            .filter(|id| {
+                state
+                    .registrations
+                    .get(id)
+                    .is_some_and(|outcomes| outcomes.is_ejected(now))
+            })
+            .count();
+
+        let outcomes = state
+            .registrations
+            .entry(registration_id)
+            .or_insert_with(|| Outcomes::new(now));
+        outcomes.last_seen = now;
+        // Requests that were in flight when it was ejected don't count
+        if outcomes.is_ejected(now) {
+            return None;
+        }
+        let max_ejection = Duration::from_secs(config.max_ejection_secs);
+        if let Some(until) = outcomes.ejected_until {
+            // Back in rotation long enough that the next ejection starts short again
+            if now.duration_since(until) >= max_ejection {
+                outcomes.ejections = 0;
+                outcomes.ejected_until = None;
+            }
+        }
+        if now.duration_since(outcomes.window_start) >= Duration::from_secs(config.interval_secs) {
+            outcomes.window_start = now;
+            outcomes.requests = 0;
+            outcomes.failures = 0;
+        }
+
+        outcomes.requests += 1;
+        if success {
+            outcomes.consecutive_failures = 0;
+            return None;
+        }
+        outcomes.consecutive_failures += 1;
+        outcomes.failures += 1;
+
+        let too_many_in_a_row = config.consecutive_failures > 0
+            && outcomes.consecutive_failures >= config.consecutive_failures;
+        let error_rate_too_high = config.error_rate_percent.is_some_and(|percent| {
+            outcomes.requests >= config.min_requests.max(1)
+                && outcomes.failures * 100 >= percent * outcomes.requests
+        });
+        if !too_many_in_a_row && !error_rate_too_high {
+            return None;
+        }
+        // Never take out more of the pool than allowed; the counts carry over,
+        // so it is ejected once room frees up
+        if (already_ejected + 1) * 100 > config.max_ejection_percent as usize * pool.len() {
+            return None;
+        }
+
+        let duration = Duration::from_secs(config.base_ejection_secs)
+            .saturating_mul(2u32.saturating_pow(outcomes.ejections))
+            .min(max_ejection);
+        outcomes.ejections += 1;
+        outcomes.ejected_until = Some(now + duration);
+        outcomes.reset_counts(now);
+        Some(duration)
+    }
+
+    /// Whether `registration_id` is currently out of rotation
+    pub fn is_ejected(&self, registration_id: Uuid) -> bool {
+        self.lock()
+            .registrations
+            .get(&registration_id)
+            .is_some_and(|outcomes| outcomes.is_ejected(Instant::now()))
+    }
+
+    /// Registrations currently out of rotation, ordered by id
+    pub fn ejections(&self) -> Vec<Ejection> {
+        let now = Instant::now();
+        let mut ejections: Vec<_> = self
+            .lock()
+            .registrations
+            .iter()
+            .filter_map(|(id, outcomes)| {
+                let until = outcomes.ejected_until.filter(|until| *until > now)?;
+                Some(Ejection {
+                    registration_id: *id,
+                    remaining: until - now,
+                    ejections: outcomes.ejections,
+                })
+            })
+            .collect();
+        ejections.sort_by_key(|ejection| ejection.registration_id);
+        ejections
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OutlierDetectionConfig {
+        OutlierDetectionConfig {
+            consecutive_failures: 3,
+            ..Default::default()
+        }
+    }
+
+    fn fail(detector: &OutlierDetector, id: Uuid, pool: &[Uuid], times: u32) -> Option<Duration> {
+        (0..times)
+            .map(|_| detector.record(&config(), id, false, pool))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn test_consecutive_failures_eject_with_growing_time() {
+        let detector = OutlierDetector::new();
+        let (id, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let pool = [id, other];
+
+        // A success in between resets the streak
+        fail(&detector, id, &pool, 2);
+        detector.record(&config(), id, true, &pool);
+        assert_eq!(fail(&detector, id, &pool, 2), None);
+        assert!(!detector.is_ejected(id));
+
+        assert_eq!(fail(&detector, id, &pool, 1), Some(Duration::from_secs(30)));
+        assert!(detector.is_ejected(id));
+        assert!(!detector.is_ejected(other));
+        let ejections = detector.ejections();
+        assert_eq!(ejections.len(), 1);
+        assert_eq!(ejections[0].registration_id, id);
+        assert_eq!(ejections[0].ejections, 1);
+
+        // Once back in rotation, the next ejection lasts twice as long
+        detector
+            .lock()
+            .registrations
+            .get_mut(&id)
+            .unwrap()
+            .ejected_until = Some(Instant::now());
+        assert_eq!(fail(&detector, id, &pool, 3), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_error_rate_ejects() {
+        let detector = OutlierDetector::new();
+        let config = OutlierDetectionConfig {
+            consecutive_failures: 0,
+            error_rate_percent: Some(50),
+            min_requests: 4,
+            ..Default::default()
+        };
+        let id = Uuid::from_u128(1);
+        let pool = [id, Uuid::from_u128(2)];
+
+        for success in [false, true, false] {
+            assert_eq!(detector.record(&config, id, success, &pool), None);
+        }
+        assert!(detector.record(&config, id, true, &pool).is_none());
+        assert!(detector.record(&config, id, false, &pool).is_some());
+        assert!(detector.is_ejected(id));
+    }
+
+    #[test]
+    fn test_ejected_percentage_is_capped() {
+        let detector = OutlierDetector::new();
+        let pool = [Uuid::from_u128(1), Uuid::from_u128(2)];
+
+        // A lone registration is never ejected
+        assert_eq!(fail(&detector, pool[0], &pool[..1], 5), None);
+
+        assert!(fail(&detector, pool[0], &pool, 1).is_some());
+        assert_eq!(fail(&detector, pool[1], &pool, 5), None);
+        assert_eq!(detector.ejections().len(), 1);
+    }
+}
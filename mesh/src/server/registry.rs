@@ -1,13 +1,46 @@
 use super::error::{IngressError, IngressResult};
-use crate::common::{ConnectionInfo, IngressMessage, ServiceRegistration};
+use crate::common::{ConnectionInfo, IamIdentity, IngressMessage, ServiceRegistration};
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::{mpsc, RwLock};
+use std::time::{Instant, SystemTime};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Authentication state of a control connection
+#[derive(Debug, Clone)]
+pub enum ConnectionAuthState {
+    /// Connected, but no auth handshake has succeeded yet
+    Pending,
+    /// Authenticated with a validated identity
+    Authenticated {
+        identity: IamIdentity,
+        /// When the most recent auth handshake succeeded
+        authenticated_at: Instant,
+    },
+}
+
+/// Events buffered per subscriber before it is considered lagging
+const EVENT_CAPACITY: usize = 1024;
+
+/// A change to the set of routable registrations
+#[derive(Debug, Clone)]
+pub enum RegistryEvent {
+    /// A service was registered, or re-registered, on its connection
+    Registered(ServiceRegistration),
+    /// A service was deregistered
+    Deregistered(Uuid),
+    /// A connection and any registration on it were removed
+    ConnectionRemoved(Uuid),
+}
+
+/// Decides whether a registration may join the ones already in place,
+/// returning the reason when it may not
+pub type RegistrationCheck<'a> =
+    dyn Fn(&HashMap<Uuid, ServiceRegistration>) -> Result<(), String> + Sync + 'a;
+
 /// Service for managing connections and service registrations
 #[async_trait]
 pub trait Registry: Send + Sync {
@@ -21,16 +54,70 @@ pub trait Registry: Send + Sync {
     /// Remove a connection and clean up all associated data
     async fn remove_connection(&self, connection_id: Uuid) -> IngressResult<()>;
 
-    /// Register a service with connection and registration information
+    /// Register a service unconditionally
+    #[cfg(test)]
     async fn register_service(
         &self,
         connection_id: Uuid,
         registration: ServiceRegistration,
-    ) -> IngressResult<()>;
+    ) -> IngressResult<()> {
+        self.register_service_if(connection_id, registration, &|_| Ok(()))
+            .await?
+            .map_err(IngressError::bad_request)
+    }
+
+    /// Register a service if `check` accepts the registrations already in
+    /// place; the check and the insert happen under one write lock, so
+    /// concurrent registrations see each other. Returns the check's reason
+    /// when it refuses
+    async fn register_service_if(
+        &self,
+        connection_id: Uuid,
+        registration: ServiceRegistration,
+        check: &RegistrationCheck<'_>,
+    ) -> IngressResult<Result<(), String>>;
 
     /// Deregister a service
     async fn deregister_service(&self, service_id: Uuid) -> IngressResult<()>;
 
+    /// Record a successful authentication and the validated identity for a connection
+    async fn set_authenticated(
+        &self,
+        connection_id: Uuid,
+        identity: IamIdentity,
+    ) -> IngressResult<()>;
+
+    /// Remember the auth challenge nonce issued to a connection
+    async fn set_auth_challenge(&self, connection_id: Uuid, nonce: String) -> IngressResult<()>;
+
+    /// Take the auth challenge issued to a connection; each nonce is usable once
+    async fn take_auth_challenge(&self, connection_id: Uuid) -> IngressResult<Option<String>>;
+
+    /// Remember the remote address a connection came from
+    async fn set_peer_addr(&self, connection_id: Uuid, peer_addr: SocketAddr) -> IngressResult<()>;
+
+    /// Remote address of a connection, if known
+    async fn get_peer_addr(&self, connection_id: Uuid) -> IngressResult<Option<SocketAddr>>;
+
+    /// Get the authentication state of a connection (unknown connections are pending)
+    async fn get_auth_state(&self, connection_id: Uuid) -> IngressResult<ConnectionAuthState>;
+
+    /// Whether a connection has completed authentication
+    async fn is_authenticated(&self, connection_id: Uuid) -> IngressResult<bool> {
+        Ok(matches!(
+            self.get_auth_state(connection_id).await?,
+            ConnectionAuthState::Authenticated { .. }
+        ))
+    }
+
+    /// Get the validated identity of an authenticated connection
+    async fn get_identity(&self, connection_id: Uuid) -> IngressResult<Option<IamIdentity>> {
+        match self.get_auth_state(connection_id).await? {
+            ConnectionAuthState::Authenticated { identity, .. } => Ok(Some(identity)),
+            ConnectionAuthState::Pending => Ok(None),
+        }
+    }
+
     /// Update the last heartbeat time for a connection
     async fn update_heartbeat(&self, connection_id: Uuid) -> IngressResult<()>;
 
@@ -45,6 +132,9 @@ pub trait Registry: Send + Sync {
 
     /// Get all current registrations (for health/stats endpoints)
     async fn get_all_registrations(&self) -> IngressResult<HashMap<Uuid, ServiceRegistration>>;
+
+    /// Receive registration changes made from now on
+    fn subscribe(&self) -> broadcast::Receiver<RegistryEvent>;
 }
 
 /// Default implementation of Registry
@@ -53,6 +143,10 @@ pub struct DefaultRegistry {
     connections: Arc<RwLock<HashMap<Uuid, ConnectionInfo>>>,
     registrations: Arc<RwLock<HashMap<Uuid, ServiceRegistration>>>,
     connection_senders: Arc<RwLock<HashMap<Uuid, mpsc::UnboundedSender<IngressMessage>>>>,
+    auth_states: Arc<RwLock<HashMap<Uuid, ConnectionAuthState>>>,
+    auth_challenges: Arc<RwLock<HashMap<Uuid, String>>>,
+    peer_addrs: Arc<RwLock<HashMap<Uuid, SocketAddr>>>,
+    events: broadcast::Sender<RegistryEvent>,
 }
 
 impl DefaultRegistry {
@@ -61,8 +155,17 @@ impl DefaultRegistry {
             connections: Arc::new(RwLock::new(HashMap::new())),
             registrations: Arc::new(RwLock::new(HashMap::new())),
             connection_senders: Arc::new(RwLock::new(HashMap::new())),
+            auth_states: Arc::new(RwLock::new(HashMap::new())),
+            auth_challenges: Arc::new(RwLock::new(HashMap::new())),
+            peer_addrs: Arc::new(RwLock::new(HashMap::new())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
+
+    /// Tell subscribers about a change; nobody listening is fine
+    fn publish(&self, event: RegistryEvent) {
+        let _ = self.events.send(event);
+    }
 }
 
 #[async_trait]
@@ -72,8 +175,14 @@ impl Registry for DefaultRegistry {
         connection_id: Uuid,
         sender: mpsc::UnboundedSender<IngressMessage>,
     ) -> IngressResult<()> {
-        let mut senders = self.connection_senders.write().await;
-        senders.insert(connection_id, sender);
+        {
+            let mut senders = self.connection_senders.write().await;
+            senders.insert(connection_id, sender);
+        }
+        {
+            let mut auth_states = self.auth_states.write().await;
+            auth_states.insert(connection_id, ConnectionAuthState::Pending);
+        }
         info!("Connection registered: {}", connection_id);
         Ok(())
     }
@@ -85,53 +194,71 @@ impl Registry for DefaultRegistry {
             connections.remove(&connection_id);
         }
         {
+            // Published under the lock so subscribers see changes in map order
             let mut registrations = self.registrations.write().await;
             registrations.remove(&connection_id);
+            self.publish(RegistryEvent::ConnectionRemoved(connection_id));
         }
         {
             let mut senders = self.connection_senders.write().await;
             senders.remove(&connection_id);
         }
+        {
+            let mut auth_states = self.auth_states.write().await;
+            auth_states.remove(&connection_id);
+        }
+        {
+            let mut auth_challenges = self.auth_challenges.write().await;
+            auth_challenges.remove(&connection_id);
+        }
+        {
+            let mut peer_addrs = self.peer_addrs.write().await;
+            peer_addrs.remove(&connection_id);
+        }
 
         info!("Connection removed: {}", connection_id);
         Ok(())
     }
 
-    async fn register_service(
+    async fn register_service_if(
         &self,
         connection_id: Uuid,
         registration: ServiceRegistration,
-    ) -> IngressResult<()> {
+        check: &RegistrationCheck<'_>,
+    ) -> IngressResult<Result<(), String>> {
         info!(
             "Service registration received: {}",
             registration.service_name
         );
 
-        // Update connection info
-        {
-            let mut connections = self.connections.write().await;
-            connections.insert(
-                connection_id,
-                ConnectionInfo {
-                    id: connection_id,
-                    service_name: registration.service_name.clone(),
-                    host: registration.host.clone(),
-                    port: registration.port,
-                    last_heartbeat: SystemTime::now(),
-                    attributes: registration.attributes.clone(),
-                },
-            );
+        // Both locks are held so the registration and its connection info
+        // appear together, and in the same order remove_connection takes them
+        let mut connections = self.connections.write().await;
+        let mut registrations = self.registrations.write().await;
+        if let Err(reason) = check(&registrations) {
+            return Ok(Err(reason));
         }
 
+        // Update connection info
+        connections.insert(
+            connection_id,
+            ConnectionInfo {
+                id: connection_id,
+                service_name: registration.service_name.clone(),
+                host: registration.host.clone(),
+                port: registration.port,
+                last_heartbeat: SystemTime::now(),
+                attributes: registration.attributes.clone(),
+            },
+        );
+
         // Store registration with connection_id
-        {
-            let mut registrations = self.registrations.write().await;
-            let mut reg = registration.clone();
-            reg.id = connection_id;
-            registrations.insert(connection_id, reg);
-        }
+        let mut reg = registration;
+        reg.id = connection_id;
+        registrations.insert(connection_id, reg.clone());
+        self.publish(RegistryEvent::Registered(reg));
 
-        Ok(())
+        Ok(Ok(()))
     }
 
     async fn deregister_service(&self, service_id: Uuid) -> IngressResult<()> {
@@ -141,6 +268,7 @@ impl Registry for DefaultRegistry {
         {
             let mut registrations = self.registrations.write().await;
             registrations.remove(&service_id);
+            self.publish(RegistryEvent::Deregistered(service_id));
         }
         {
             let mut connections = self.connections.write().await;
@@ -150,6 +278,64 @@ impl Registry for DefaultRegistry {
         Ok(())
     }
 
+    async fn set_authenticated(
+        &self,
+        connection_id: Uuid,
+        identity: IamIdentity,
+    ) -> IngressResult<()> {
+        let mut auth_states = self.auth_states.write().await;
+        match auth_states.get_mut(&connection_id) {
+            Some(state) => {
+                info!(
+                    "Connection {} authenticated as {}",
+                    connection_id, identity.arn
+                );
+                *state = ConnectionAuthState::Authenticated {
+                    identity,
+                    authenticated_at: Instant::now(),
+                };
+                Ok(())
+            }
+            None => Err(IngressError::registry_not_found(connection_id)),
+        }
+    }
+
+    async fn set_auth_challenge(&self, connection_id: Uuid, nonce: String) -> IngressResult<()> {
+        if !self.auth_states.read().await.contains_key(&connection_id) {
+            return Err(IngressError::registry_not_found(connection_id));
+        }
+        let mut auth_challenges = self.auth_challenges.write().await;
+        auth_challenges.insert(connection_id, nonce);
+        Ok(())
+    }
+
+    async fn take_auth_challenge(&self, connection_id: Uuid) -> IngressResult<Option<String>> {
+        let mut auth_challenges = self.auth_challenges.write().await;
+        Ok(auth_challenges.remove(&connection_id))
+    }
+
+    async fn set_peer_addr(&self, connection_id: Uuid, peer_addr: SocketAddr) -> IngressResult<()> {
+        if !self.auth_states.read().await.contains_key(&connection_id) {
+            return Err(IngressError::registry_not_found(connection_id));
+        }
+        let mut peer_addrs = self.peer_addrs.write().await;
+        peer_addrs.insert(connection_id, peer_addr);
+        Ok(())
+    }
+
+    async fn get_peer_addr(&self, connection_id: Uuid) -> IngressResult<Option<SocketAddr>> {
+        let peer_addrs = self.peer_addrs.read().await;
+        Ok(peer_addrs.get(&connection_id).copied())
+    }
+
+    async fn get_auth_state(&self, connection_id: Uuid) -> IngressResult<ConnectionAuthState> {
+        let auth_states = self.auth_states.read().await;
+        Ok(auth_states
+            .get(&connection_id)
+            .cloned()
+            .unwrap_or(ConnectionAuthState::Pending))
+    }
+
     async fn update_heartbeat(&self, connection_id: Uuid) -> IngressResult<()> {
         let mut connections = self.connections.write().await;
         if let Some(connection) = connections.get_mut(&connection_id) {
@@ -181,6 +367,10 @@ impl Registry for DefaultRegistry {
         let registrations = self.registrations.read().await;
         Ok(registrations.clone())
     }
+
+    fn subscribe(&self) -> broadcast::Receiver<RegistryEvent> {
+        self.events.subscribe()
+    }
 }
 
 impl Default for DefaultRegistry {
@@ -220,6 +410,85 @@ mod tests {
         assert!(sender.is_none());
     }
 
+    #[tokio::test]
+    async fn test_connection_auth_state() {
+        let registry = DefaultRegistry::new();
+        let connection_id = Uuid::new_v4();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        assert!(!registry.is_authenticated(connection_id).await.unwrap());
+
+        let identity = IamIdentity {
+            arn: "arn:aws:sts::123456789012:assumed-role/MeshClient/session".to_string(),
+            account_id: "123456789012".to_string(),
+            user_id: "AROAEXAMPLE:session".to_string(),
+            principal_type: "AssumedRole".to_string(),
+            role_arn: None,
+            session_name: None,
+            expires_at: None,
+            allowed_hosts: None,
+        };
+        registry
+            .set_authenticated(connection_id, identity)
+            .await
+            .unwrap();
+        assert!(registry.is_authenticated(connection_id).await.unwrap());
+
+        // Auth state does not outlive the connection
+        registry.remove_connection(connection_id).await.unwrap();
+        assert!(!registry.is_authenticated(connection_id).await.unwrap());
+
+        // Unknown connections cannot be marked authenticated
+        let identity = IamIdentity {
+            arn: "arn:aws:iam::123456789012:role/Other".to_string(),
+            account_id: "123456789012".to_string(),
+            user_id: "AROAOTHER".to_string(),
+            principal_type: "Role".to_string(),
+            role_arn: None,
+            session_name: None,
+            expires_at: None,
+            allowed_hosts: None,
+        };
+        assert!(registry
+            .set_authenticated(Uuid::new_v4(), identity)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_auth_challenge_is_single_use() {
+        let registry = DefaultRegistry::new();
+        let connection_id = Uuid::new_v4();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+
+        assert!(registry
+            .set_auth_challenge(connection_id, "nonce".to_string())
+            .await
+            .is_err());
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .set_auth_challenge(connection_id, "nonce".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            registry.take_auth_challenge(connection_id).await.unwrap(),
+            Some("nonce".to_string())
+        );
+        assert_eq!(
+            registry.take_auth_challenge(connection_id).await.unwrap(),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn test_service_registration() {
         let registry = DefaultRegistry::new();
@@ -233,6 +502,7 @@ mod tests {
             cluster_name: "test-cluster".to_string(),
             task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
             health_check_path: Some("/health".to_string()),
+            paths: Vec::new(),
             attributes: HashMap::new(),
         };
 
@@ -257,4 +527,52 @@ mod tests {
         let all_registrations = registry.get_all_registrations().await.unwrap();
         assert_eq!(all_registrations.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_conditional_service_registration() {
+        let registry = DefaultRegistry::new();
+        let registration = |cluster_name: &str| ServiceRegistration {
+            id: Uuid::nil(),
+            service_name: "web".to_string(),
+            host: "api.example.com".to_string(),
+            port: 8080,
+            cluster_name: cluster_name.to_string(),
+            task_arn: String::new(),
+            health_check_path: None,
+            paths: Vec::new(),
+            attributes: HashMap::new(),
+        };
+        let host_free = |existing: &HashMap<Uuid, ServiceRegistration>| match existing
+            .values()
+            .find(|r| r.host == "api.example.com")
+        {
+            Some(r) => Err(format!("taken by {}", r.cluster_name)),
+            None => Ok(()),
+        };
+
+        let first = Uuid::new_v4();
+        assert_eq!(
+            registry
+                .register_service_if(first, registration("edge-1"), &host_free)
+                .await
+                .unwrap(),
+            Ok(())
+        );
+
+        // The check sees the first registration and nothing is stored
+        let second = Uuid::new_v4();
+        assert_eq!(
+            registry
+                .register_service_if(second, registration("edge-2"), &host_free)
+                .await
+                .unwrap(),
+            Err("taken by edge-1".to_string())
+        );
+        assert_eq!(registry.get_all_registrations().await.unwrap().len(), 1);
+        assert!(!registry
+            .get_all_connections()
+            .await
+            .unwrap()
+            .contains_key(&second));
+    }
 }
@@ -1,22 +1,25 @@
 use super::error::{IngressError, IngressResult};
+use super::outlier::{Ejection, OutlierDetector};
 use super::registry::Registry;
-use crate::common::{routing, IngressMessage, ProxyRequest, ProxyResponse, ServiceRegistration};
+use super::routing_table::SyncedRoutingTable;
+use crate::common::config::{
+    LoadBalancingStrategy, RetryBudgetConfig, RoutingConfig, SharedRoutingConfig,
+};
+use crate::common::{
+    routing, IngressMessage, ProxyRequest, ProxyResponse, ProxyTimeouts, ServiceRegistration,
+    MESH_ATTEMPTS_HEADER, MESH_TIMEOUT_HEADER,
+};
 use async_trait::async_trait;
+use rand::seq::SliceRandom;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::{oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tokio::time::timeout;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-/// Cache entry for host→service mappings
-#[derive(Clone)]
-struct HostServiceCacheEntry {
-    services: Vec<ServiceRegistration>,
-    timestamp: Instant,
-}
-
 /// Service for routing requests and managing responses
 #[async_trait]
 pub trait Router: Send + Sync {
@@ -27,8 +30,174 @@ pub trait Router: Send + Sync {
         registry: &dyn Registry,
     ) -> IngressResult<ProxyResponse>;
 
+    /// Registrations for the host pattern that best matches `target_host`
+    async fn find_host_services(
+        &self,
+        target_host: &str,
+        registry: &dyn Registry,
+    ) -> IngressResult<Vec<ServiceRegistration>>;
+
+    /// Find the services a request may go to: those registered for its host
+    /// whose paths match best, narrowed by the host's routing rules
+    async fn find_matching_services(
+        &self,
+        proxy_request: &ProxyRequest,
+        registry: &dyn Registry,
+    ) -> IngressResult<Vec<ServiceRegistration>>;
+
+    /// Select a healthy service from matching services, narrowed to the
+    /// request's group when the host splits traffic, using the host's load
+    /// balancing strategy. Ejected registrations are skipped unless nothing
+    /// else is left.
+    async fn select_healthy_service(
+        &self,
+        proxy_request: &ProxyRequest,
+        matching_services: &[ServiceRegistration],
+        registry: &dyn Registry,
+    ) -> IngressResult<Option<ServiceRegistration>>;
+
     /// Handle an incoming proxy response by matching it to a pending request
     async fn handle_response(&self, response: ProxyResponse) -> IngressResult<()>;
+
+    /// Retry counters since startup
+    fn retry_stats(&self) -> RetryStats;
+
+    /// Registrations currently ejected for failing
+    fn ejections(&self) -> Vec<Ejection>;
+}
+
+/// Retry counters since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Requests sent again to another instance
+    pub retries: u64,
+    /// Retries skipped because the retry budget was spent
+    pub budget_exhausted: u64,
+}
+
+/// Window over which the retry budget is counted
+const RETRY_BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// Requests and retries seen in the current budget window
+struct RetryBudget {
+    window_start: Instant,
+    requests: u64,
+    retries: u64,
+}
+
+impl RetryBudget {
+    fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            requests: 0,
+            retries: 0,
+        }
+    }
+
+    fn roll(&mut self) {
+        if self.window_start.elapsed() >= RETRY_BUDGET_WINDOW {
+            *self = Self::new();
+        }
+    }
+
+    fn record_request(&mut self) {
+        self.roll();
+        self.requests += 1;
+    }
+
+    /// Take one retry from the budget, if any is left
+    fn withdraw(&mut self, config: RetryBudgetConfig) -> bool {
+        self.roll();
+        let allowed = (self.requests * u64::from(config.percent) / 100)
+            .max(u64::from(config.min_per_sec) * RETRY_BUDGET_WINDOW.as_secs());
+        if self.retries < allowed {
+            self.retries += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Why an attempt got no response
+enum AttemptFailure {
+    /// The request never reached the client, or its tunnel dropped
+    Unavailable,
+    /// No response within the attempt's timeout
+    TimedOut,
+}
+
+/// Requests in flight per registration
+type InFlightCounts = Arc<Mutex<HashMap<Uuid, usize>>>;
+
+/// Counts a request against its registration until dropped
+struct InFlightGuard {
+    in_flight: InFlightCounts,
+    registration_id: Uuid,
+}
+
+impl InFlightGuard {
+    fn new(in_flight: &InFlightCounts, registration_id: Uuid) -> Self {
+        *lock(in_flight).entry(registration_id).or_insert(0) += 1;
+        Self {
+            in_flight: in_flight.clone(),
+            registration_id,
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut counts = lock(&self.in_flight);
+        if let Some(count) = counts.get_mut(&self.registration_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.registration_id);
+            }
+        }
+    }
+}
+
+/// How long a host pattern no request has gone to keeps its round-robin position
+const IDLE_ROUND_ROBIN_TTL: Duration = Duration::from_secs(600);
+
+/// Round-robin positions per host pattern; idle ones are dropped, so patterns
+/// that were deregistered don't stay around
+struct RoundRobin {
+    positions: HashMap<String, (usize, Instant)>,
+    last_sweep: Instant,
+}
+
+impl RoundRobin {
+    fn new() -> Self {
+        Self {
+            positions: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Advance and return the position for `host_pattern`
+    fn next(&mut self, host_pattern: &str, now: Instant) -> usize {
+        if now.duration_since(self.last_sweep) >= IDLE_ROUND_ROBIN_TTL {
+            self.last_sweep = now;
+            self.positions
+                .retain(|_, (_, last_used)| now.duration_since(*last_used) < IDLE_ROUND_ROBIN_TTL);
+        }
+        let (position, last_used) = self
+            .positions
+            .entry(host_pattern.to_string())
+            .or_insert((0, now));
+        let current = *position;
+        *position = position.wrapping_add(1);
+        *last_used = now;
+        current
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
 /// Default implementation of Router
@@ -36,81 +205,103 @@ pub trait Router: Send + Sync {
 pub struct DefaultRouter {
     pending_requests: Arc<RwLock<HashMap<Uuid, oneshot::Sender<ProxyResponse>>>>,
     request_timeout: Duration,
-    host_service_cache: Arc<RwLock<HashMap<String, HostServiceCacheEntry>>>,
-    cache_ttl: Duration,
+    /// Current routing settings, replaced wholesale on reload
+    routing_config: SharedRoutingConfig,
+    /// Registrations by host, following the registry's change events
+    routing_table: Arc<SyncedRoutingTable>,
+    /// Next round-robin position per registered host pattern
+    round_robin: Arc<Mutex<RoundRobin>>,
+    in_flight: InFlightCounts,
+    retry_budget: Arc<Mutex<RetryBudget>>,
+    retries: Arc<AtomicU64>,
+    retries_budget_exhausted: Arc<AtomicU64>,
+    outliers: Arc<OutlierDetector>,
 }
 
 impl DefaultRouter {
     pub fn new(request_timeout: Duration) -> Self {
+        Self::with_routing_config(request_timeout, RoutingConfig::default())
+    }
+
+    pub fn with_routing_config(request_timeout: Duration, routing_config: RoutingConfig) -> Self {
+        Self::with_shared_routing_config(request_timeout, SharedRoutingConfig::new(routing_config))
+    }
+
+    /// Route with settings others can swap out, e.g. on reload
+    pub fn with_shared_routing_config(
+        request_timeout: Duration,
+        routing_config: SharedRoutingConfig,
+    ) -> Self {
         Self {
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
             request_timeout,
-            host_service_cache: Arc::new(RwLock::new(HashMap::new())),
-            cache_ttl: Duration::from_secs(30), // 30 second TTL
+            routing_config,
+            routing_table: Arc::new(SyncedRoutingTable::new()),
+            round_robin: Arc::new(Mutex::new(RoundRobin::new())),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            retry_budget: Arc::new(Mutex::new(RetryBudget::new())),
+            retries: Arc::new(AtomicU64::new(0)),
+            retries_budget_exhausted: Arc::new(AtomicU64::new(0)),
+            outliers: Arc::new(OutlierDetector::new()),
         }
     }
 
-    /// Find services matching the target host (with caching)
-    async fn find_matching_services(
-        &self,
-        target_host: &str,
-        registry: &dyn Registry,
-    ) -> IngressResult<Vec<ServiceRegistration>> {
-        // Check cache first
-        {
-            let cache = self.host_service_cache.read().await;
-            if let Some(entry) = cache.get(target_host) {
-                if entry.timestamp.elapsed() < self.cache_ttl {
-                    debug!("Cache hit for host: {}", target_host);
-                    return Ok(entry.services.clone());
-                }
-            }
-        }
-
-        debug!("Cache miss for host: {}, computing services", target_host);
-
-        // Cache miss or expired - compute matching services
-        let registrations = registry.get_all_registrations().await?;
+    /// Snapshot of the current routing settings
+    fn routing_config(&self) -> Arc<RoutingConfig> {
+        self.routing_config.load()
+    }
 
-        let matching_services: Vec<_> = registrations
-            .values()
-            .filter(|reg| routing::match_host_to_service(target_host, &[(*reg).clone()]).is_some())
-            .cloned()
-            .collect();
+    /// Resolve the timeouts for a host, defaulting the total to `request_timeout`
+    fn resolve_timeouts(&self, target_host: &str) -> ProxyTimeouts {
+        self.routing_config()
+            .timeouts_for_host(target_host)
+            .or(ProxyTimeouts {
+                total_ms: Some(self.request_timeout.as_millis() as u64),
+                ..Default::default()
+            })
+    }
 
-        // Cache the result
-        {
-            let mut cache = self.host_service_cache.write().await;
-            cache.insert(
-                target_host.to_string(),
-                HostServiceCacheEntry {
-                    services: matching_services.clone(),
-                    timestamp: Instant::now(),
-                },
-            );
+    /// Set the affinity cookie when the host is sticky and the request was not
+    /// already pinned to `service`
+    fn pin_session(
+        &self,
+        proxy_request: &ProxyRequest,
+        service: &ServiceRegistration,
+        response: &mut ProxyResponse,
+    ) {
+        let routing_config = self.routing_config();
+        let Some(affinity) = routing_config.affinity_for_host(&proxy_request.target_host) else {
+            return;
+        };
+        if routing::affinity_target(affinity, &proxy_request.headers) == Some(service.id) {
+            return;
         }
-
-        Ok(matching_services)
+        let secure = proxy_request
+            .headers
+            .get("x-forwarded-proto")
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+        response.headers.push((
+            "set-cookie".to_string(),
+            routing::affinity_cookie(affinity, service.id, secure),
+        ));
     }
 
-    /// Select a healthy service from matching services
-    async fn select_healthy_service(
-        &self,
-        matching_services: &[ServiceRegistration],
-        registry: &dyn Registry,
-    ) -> IngressResult<Option<ServiceRegistration>> {
-        let connections = registry.get_all_connections().await?;
-        let selected = routing::select_healthy_instance(matching_services, &connections);
-        Ok(selected.cloned())
+    /// Advance and return the round-robin position for a host pattern
+    fn next_round_robin(&self, host_pattern: &str) -> usize {
+        lock(&self.round_robin).next(host_pattern, Instant::now())
     }
 
-    /// Forward a request to a service and set up response waiting
+    /// Forward a request to a service and set up response waiting; also returns
+    /// the client's sender, to notice its tunnel dropping
     async fn forward_request(
         &self,
         proxy_request: &ProxyRequest,
         service: &ServiceRegistration,
         registry: &dyn Registry,
-    ) -> IngressResult<oneshot::Receiver<ProxyResponse>> {
+    ) -> IngressResult<(
+        oneshot::Receiver<ProxyResponse>,
+        mpsc::UnboundedSender<IngressMessage>,
+    )> {
         // Create a oneshot channel for the response
         let (response_tx, response_rx) = oneshot::channel();
 
@@ -135,7 +326,7 @@ impl DefaultRouter {
                 )));
             }
 
-            Ok(response_rx)
+            Ok((response_rx, sender))
         } else {
             // Clean up pending request if no sender found
             let mut pending_requests = self.pending_requests.write().await;
@@ -152,8 +343,21 @@ impl DefaultRouter {
         &self,
         proxy_request: &ProxyRequest,
         response_rx: oneshot::Receiver<ProxyResponse>,
+        tunnel: &mpsc::UnboundedSender<IngressMessage>,
+        total_timeout: Duration,
     ) -> IngressResult<ProxyResponse> {
-        match timeout(self.request_timeout, response_rx).await {
+        let result = tokio::select! {
+            biased;
+            result = timeout(total_timeout, response_rx) => result,
+            _ = tunnel.closed() => {
+                warn!("Connection closed before responding to request: {}", proxy_request.id);
+                let mut pending_requests = self.pending_requests.write().await;
+                pending_requests.remove(&proxy_request.id);
+
+                return Err(IngressError::send_failed("Connection closed"));
+            }
+        };
+        match result {
             Ok(Ok(response)) => {
                 debug!("Received response for request: {}", proxy_request.id);
                 Ok(response)
@@ -177,6 +381,67 @@ impl DefaultRouter {
         }
     }
 
+    /// Send one attempt of a request to `service`, waiting up to `try_timeout`
+    async fn try_service(
+        &self,
+        proxy_request: &ProxyRequest,
+        service: &ServiceRegistration,
+        registry: &dyn Registry,
+        try_timeout: Duration,
+    ) -> Result<ProxyResponse, AttemptFailure> {
+        let _in_flight = InFlightGuard::new(&self.in_flight, service.id);
+        let (response_rx, tunnel) = self
+            .forward_request(proxy_request, service, registry)
+            .await
+            .map_err(|e| {
+                warn!("Failed to forward request {}: {}", proxy_request.id, e);
+                AttemptFailure::Unavailable
+            })?;
+        match self
+            .wait_for_response(proxy_request, response_rx, &tunnel, try_timeout)
+            .await
+        {
+            Ok(response) => Ok(response),
+            Err(IngressError::Timeout { .. }) => Err(AttemptFailure::TimedOut),
+            Err(_) => Err(AttemptFailure::Unavailable),
+        }
+    }
+
+    /// Track an attempt's outcome against the registration it went to
+    fn record_outcome(
+        &self,
+        routing_config: &RoutingConfig,
+        service: &ServiceRegistration,
+        success: bool,
+        matching_services: &[ServiceRegistration],
+    ) {
+        let pool: Vec<_> = matching_services.iter().map(|reg| reg.id).collect();
+        if let Some(duration) = self.outliers.record(
+            &routing_config.outlier_detection,
+            service.id,
+            success,
+            &pool,
+        ) {
+            warn!(
+                "Ejecting {} ({}) for {}s after failed requests",
+                service.id,
+                service.service_name,
+                duration.as_secs()
+            );
+        }
+    }
+
+    /// Record how many instances were tried for a request
+    fn with_attempts(mut response: ProxyResponse, attempts: usize) -> ProxyResponse {
+        response
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(MESH_ATTEMPTS_HEADER));
+        response
+            .headers
+            .push((MESH_ATTEMPTS_HEADER.to_string(), attempts.to_string()));
+        response
+    }
+
     /// Create error response for various failure scenarios
     fn create_error_response(request_id: Uuid, status_code: u16, message: &str) -> ProxyResponse {
         ProxyResponse {
@@ -186,18 +451,44 @@ impl DefaultRouter {
             body: Some(message.as_bytes().to_vec()),
         }
     }
+
+    /// Create a 504 response naming the timeout that fired
+    fn create_timeout_response(request_id: Uuid, kind: &str, limit: Duration) -> ProxyResponse {
+        let mut response = Self::create_error_response(
+            request_id,
+            504,
+            &format!(
+                "Gateway Timeout: {} timeout of {}ms exceeded",
+                kind,
+                limit.as_millis()
+            ),
+        );
+        response
+            .headers
+            .push((MESH_TIMEOUT_HEADER.to_string(), kind.to_string()));
+        response
+    }
 }
 
 #[async_trait]
 impl Router for DefaultRouter {
     async fn route_request(
         &self,
-        proxy_request: ProxyRequest,
+        mut proxy_request: ProxyRequest,
         registry: &dyn Registry,
     ) -> IngressResult<ProxyResponse> {
-        // Find matching services for the host
+        // Resolve per-host timeouts; the client enforces connect/first-byte
+        let timeouts = self.resolve_timeouts(&proxy_request.target_host);
+        let total_timeout = Duration::from_millis(
+            timeouts
+                .total_ms
+                .unwrap_or(self.request_timeout.as_millis() as u64),
+        );
+        proxy_request.timeouts = Some(timeouts);
+
+        // Find matching services for the host, path and routing rules
         let matching_services = self
-            .find_matching_services(&proxy_request.target_host, registry)
+            .find_matching_services(&proxy_request, registry)
             .await?;
 
         if matching_services.is_empty() {
@@ -218,57 +509,207 @@ impl Router for DefaultRouter {
             proxy_request.target_host
         );
 
-        // Select a healthy instance
-        let selected_service = self
-            .select_healthy_service(&matching_services, registry)
-            .await?;
+        // Idempotent requests may be retried on another instance
+        let routing_config = self.routing_config();
+        let retry = routing_config.retry_for_host(&proxy_request.target_host);
+        let max_attempts = if retry.retries_method(&proxy_request.method) {
+            retry.max_attempts.max(1) as usize
+        } else {
+            1
+        };
+        let per_try_timeout = retry.per_try_timeout_ms.map(Duration::from_millis);
+        let deadline = Instant::now() + total_timeout;
+        lock(&self.retry_budget).record_request();
 
-        debug!(
-            "Health check result: {}",
-            if selected_service.is_some() {
-                "healthy service found"
-            } else {
-                "no healthy services"
-            }
-        );
+        let request_id = proxy_request.id;
+        let mut tried = Vec::new();
+        let mut failure = None;
+        while tried.len() < max_attempts {
+            let candidates: Vec<_> = matching_services
+                .iter()
+                .filter(|service| !tried.contains(&service.id))
+                .cloned()
+                .collect();
+            let Some(service) = self
+                .select_healthy_service(&proxy_request, &candidates, registry)
+                .await?
+            else {
+                break;
+            };
 
-        if let Some(service) = selected_service {
+            if !tried.is_empty() {
+                if !lock(&self.retry_budget).withdraw(routing_config.retry_budget) {
+                    warn!("Retry budget spent, not retrying request {}", request_id);
+                    self.retries_budget_exhausted
+                        .fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                info!("Retrying request {} on {}", request_id, service.id);
+                self.retries.fetch_add(1, Ordering::Relaxed);
+                // A late answer to the failed attempt must not be taken for this one
+                proxy_request.id = Uuid::new_v4();
+            }
+            tried.push(service.id);
             info!("Routing request to service: {}", service.service_name);
 
-            // Forward request and wait for response
-            match self
-                .forward_request(&proxy_request, &service, registry)
-                .await
+            // Forward the labels the host pattern captured; never trust client-sent ones
+            proxy_request.headers.retain(|name, _| {
+                !name
+                    .to_ascii_lowercase()
+                    .starts_with(routing::HOST_CAPTURE_HEADER_PREFIX)
+            });
+            for (name, value) in routing::host_captures(&service.host, &proxy_request.target_host)
+                .unwrap_or_default()
             {
-                Ok(response_rx) => {
-                    // Wait for response with timeout
-                    match self.wait_for_response(&proxy_request, response_rx).await {
-                        Ok(response) => Ok(response),
-                        Err(IngressError::Timeout { .. }) => Ok(Self::create_error_response(
-                            proxy_request.id,
-                            504,
-                            "Gateway Timeout",
-                        )),
-                        Err(_) => Ok(Self::create_error_response(
-                            proxy_request.id,
-                            503,
-                            "Service Unavailable",
-                        )),
-                    }
+                proxy_request.headers.insert(
+                    format!(
+                        "{}{}",
+                        routing::HOST_CAPTURE_HEADER_PREFIX,
+                        name.to_ascii_lowercase()
+                    ),
+                    value,
+                );
+            }
+
+            // The total timeout bounds all attempts together
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let try_timeout = per_try_timeout.map_or(remaining, |limit| limit.min(remaining));
+            let result = self
+                .try_service(&proxy_request, &service, registry, try_timeout)
+                .await;
+            self.record_outcome(
+                &routing_config,
+                &service,
+                matches!(&result, Ok(response) if response.status_code < 500),
+                &matching_services,
+            );
+            match result {
+                Ok(mut response) => {
+                    response.id = request_id;
+                    self.pin_session(&proxy_request, &service, &mut response);
+                    return Ok(Self::with_attempts(response, tried.len()));
+                }
+                Err(AttemptFailure::TimedOut) if try_timeout == remaining => {
+                    return Ok(Self::with_attempts(
+                        Self::create_timeout_response(request_id, "total", total_timeout),
+                        tried.len(),
+                    ));
+                }
+                Err(AttemptFailure::TimedOut) => {
+                    failure = Some(Self::create_timeout_response(
+                        request_id,
+                        "per-try",
+                        try_timeout,
+                    ));
+                }
+                Err(AttemptFailure::Unavailable) => {
+                    failure = Some(Self::create_error_response(
+                        request_id,
+                        503,
+                        "Service Unavailable",
+                    ));
                 }
-                Err(_) => Ok(Self::create_error_response(
-                    proxy_request.id,
-                    503,
-                    "Service Unavailable",
-                )),
             }
-        } else {
-            Ok(Self::create_error_response(
-                proxy_request.id,
-                503,
-                "No healthy service available",
-            ))
         }
+
+        Ok(match failure {
+            Some(response) => Self::with_attempts(response, tried.len()),
+            None => Self::create_error_response(request_id, 503, "No healthy service available"),
+        })
+    }
+
+    async fn find_host_services(
+        &self,
+        target_host: &str,
+        registry: &dyn Registry,
+    ) -> IngressResult<Vec<ServiceRegistration>> {
+        self.routing_table
+            .services_for_host(target_host, registry)
+            .await
+    }
+
+    async fn find_matching_services(
+        &self,
+        proxy_request: &ProxyRequest,
+        registry: &dyn Registry,
+    ) -> IngressResult<Vec<ServiceRegistration>> {
+        let host_services = self
+            .find_host_services(&proxy_request.target_host, registry)
+            .await?;
+        let path_services: Vec<_> = routing::match_path_routes(&proxy_request.path, &host_services)
+            .into_iter()
+            .cloned()
+            .collect();
+        Ok(routing::select_by_rules(
+            self.routing_config()
+                .rules_for_host(&proxy_request.target_host),
+            &proxy_request.headers,
+            &proxy_request.path,
+            &path_services,
+        )
+        .into_iter()
+        .cloned()
+        .collect())
+    }
+
+    async fn select_healthy_service(
+        &self,
+        proxy_request: &ProxyRequest,
+        matching_services: &[ServiceRegistration],
+        registry: &dyn Registry,
+    ) -> IngressResult<Option<ServiceRegistration>> {
+        let routing_config = self.routing_config();
+        let target_host = proxy_request.target_host.as_str();
+        let connections = registry.get_all_connections().await?;
+        let mut healthy = routing::healthy_instances(
+            matching_services,
+            &connections,
+            Duration::from_secs(routing_config.unhealthy_threshold),
+        );
+        if healthy.iter().any(|reg| !self.outliers.is_ejected(reg.id)) {
+            healthy.retain(|reg| !self.outliers.is_ejected(reg.id));
+        }
+        if let Some(affinity) = routing_config.affinity_for_host(target_host) {
+            let pinned = routing::affinity_target(affinity, &proxy_request.headers)
+                .and_then(|id| healthy.iter().find(|reg| reg.id == id));
+            if let Some(pinned) = pinned {
+                debug!("Session affinity routes to {}", pinned.id);
+                return Ok(Some((*pinned).clone()));
+            }
+        }
+        if let Some(split) = routing_config.split_for_host(target_host) {
+            let key =
+                routing::split_key(&split.hash_on, &proxy_request.headers, &proxy_request.path);
+            healthy = routing::select_split(split, key.as_deref(), &healthy);
+        }
+        if healthy.is_empty() {
+            return Ok(None);
+        }
+
+        // Positions follow the registered pattern, so the hosts a wildcard
+        // serves share one rotation instead of adding an entry each
+        let host_pattern = healthy[0].host.as_str();
+        let selected = match routing_config.load_balancing_for_host(target_host) {
+            LoadBalancingStrategy::RoundRobin => {
+                healthy[self.next_round_robin(host_pattern) % healthy.len()]
+            }
+            LoadBalancingStrategy::Random => *healthy
+                .choose(&mut rand::thread_rng())
+                .expect("healthy instances are not empty"),
+            LoadBalancingStrategy::LeastConnections => {
+                // Ties rotate round-robin instead of always favouring the first instance
+                let start = self.next_round_robin(host_pattern) % healthy.len();
+                let in_flight = lock(&self.in_flight);
+                *healthy
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(healthy.len())
+                    .min_by_key(|reg| in_flight.get(&reg.id).copied().unwrap_or(0))
+                    .expect("healthy instances are not empty")
+            }
+        };
+        Ok(Some(selected.clone()))
     }
 
     async fn handle_response(&self, response: ProxyResponse) -> IngressResult<()> {
@@ -286,16 +727,22 @@ impl Router for DefaultRouter {
             Err(IngressError::registry_not_found(response.id))
         }
     }
+
+    fn retry_stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.retries.load(Ordering::Relaxed),
+            budget_exhausted: self.retries_budget_exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    fn ejections(&self) -> Vec<Ejection> {
+        self.outliers.ejections()
+    }
 }
 
 impl Default for DefaultRouter {
     fn default() -> Self {
-        Self {
-            pending_requests: Arc::new(RwLock::new(HashMap::new())),
-            request_timeout: Duration::from_secs(30),
-            host_service_cache: Arc::new(RwLock::new(HashMap::new())),
-            cache_ttl: Duration::from_secs(30),
-        }
+        Self::new(Duration::from_secs(30))
     }
 }
 
@@ -317,6 +764,7 @@ mod tests {
             headers: HashMap::new(),
             body: None,
             target_host: "nonexistent.example.com".to_string(),
+            timeouts: None,
         };
 
         let response = router.route_request(request, &registry).await.unwrap();
@@ -338,6 +786,7 @@ mod tests {
             cluster_name: "test-cluster".to_string(),
             task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task".to_string(),
             health_check_path: Some("/health".to_string()),
+            paths: Vec::new(),
             attributes: HashMap::new(),
         };
 
@@ -353,12 +802,682 @@ mod tests {
             headers: HashMap::new(),
             body: None,
             target_host: "test.example.com".to_string(),
+            timeouts: None,
         };
 
         let response = router.route_request(request, &registry).await.unwrap();
         assert_eq!(response.status_code, 503);
     }
 
+    #[tokio::test]
+    async fn test_route_request_per_host_total_timeout() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+routes:
+  - host: slow.example.com
+    timeouts:
+      total_ms: 50
+"#,
+        )
+        .unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(30), routing_config);
+        let registry = DefaultRegistry::new();
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .register_service(
+                connection_id,
+                ServiceRegistration {
+                    id: connection_id,
+                    service_name: "slow-service".to_string(),
+                    host: "slow.example.com".to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task"
+                        .to_string(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: HashMap::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let request = ProxyRequest {
+            id: Uuid::new_v4(),
+            method: "GET".to_string(),
+            path: "/report".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            target_host: "slow.example.com".to_string(),
+            timeouts: None,
+        };
+
+        let response = router.route_request(request, &registry).await.unwrap();
+        assert_eq!(response.status_code, 504);
+        assert!(response
+            .headers
+            .contains(&(MESH_TIMEOUT_HEADER.to_string(), "total".to_string())));
+
+        // The forwarded request carries the resolved timeouts for the client
+        match receiver.recv().await {
+            Some(IngressMessage::ProxyRequestForward(forwarded)) => {
+                assert_eq!(forwarded.timeouts.and_then(|t| t.total_ms), Some(50));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_request_forwards_host_captures() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+routes:
+  - host: "**.example.com"
+    timeouts:
+      total_ms: 50
+"#,
+        )
+        .unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(30), routing_config);
+        let registry = DefaultRegistry::new();
+        let connection_id = Uuid::new_v4();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .register_service(
+                connection_id,
+                ServiceRegistration {
+                    id: connection_id,
+                    service_name: "tenants".to_string(),
+                    host: "{tenant}.example.com".to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: String::new(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: HashMap::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        // A client-supplied capture header must not reach the backend
+        let request = request(
+            "acme.example.com",
+            "/",
+            &[("X-Mesh-Host-Tenant", "evil"), ("x-mesh-host-region", "eu")],
+        );
+        router.route_request(request, &registry).await.unwrap();
+
+        match receiver.recv().await {
+            Some(IngressMessage::ProxyRequestForward(forwarded)) => {
+                let captured: Vec<_> = forwarded
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| name.starts_with(routing::HOST_CAPTURE_HEADER_PREFIX))
+                    .collect();
+                assert_eq!(
+                    captured,
+                    vec![(&"x-mesh-host-tenant".to_string(), &"acme".to_string())]
+                );
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    async fn register_healthy(registry: &DefaultRegistry, host: &str) -> Uuid {
+        register_with_attributes(registry, host, &[]).await
+    }
+
+    async fn register_with_attributes(
+        registry: &DefaultRegistry,
+        host: &str,
+        attributes: &[(&str, &str)],
+    ) -> Uuid {
+        let connection_id = Uuid::new_v4();
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .register_service(
+                connection_id,
+                ServiceRegistration {
+                    id: connection_id,
+                    service_name: "web".to_string(),
+                    host: host.to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: "arn:aws:ecs:us-east-1:123456789012:task/test-cluster/test-task"
+                        .to_string(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: attributes
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                },
+            )
+            .await
+            .unwrap();
+        connection_id
+    }
+
+    fn request(host: &str, path: &str, headers: &[(&str, &str)]) -> ProxyRequest {
+        ProxyRequest {
+            id: Uuid::new_v4(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            target_host: host.to_string(),
+            timeouts: None,
+        }
+    }
+
+    async fn select(router: &DefaultRouter, registry: &DefaultRegistry, host: &str) -> Uuid {
+        select_request(router, registry, request(host, "/", &[])).await
+    }
+
+    async fn select_request(
+        router: &DefaultRouter,
+        registry: &DefaultRegistry,
+        request: ProxyRequest,
+    ) -> Uuid {
+        let services = router
+            .find_matching_services(&request, registry)
+            .await
+            .unwrap();
+        router
+            .select_healthy_service(&request, &services, registry)
+            .await
+            .unwrap()
+            .unwrap()
+            .id
+    }
+
+    #[tokio::test]
+    async fn test_routing_rules_select_by_attributes() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+routes:
+  - host: web.example.com
+    rules:
+      - match:
+          headers:
+            X-Canary: "true"
+        attributes:
+          version: v2
+      - match:
+          query:
+            beta: "1"
+        attributes:
+          version: v2
+      - match:
+          cookies:
+            tenant: acme
+        attributes:
+          tenant: acme
+      - match:
+          headers:
+            x-tenant: globex
+        attributes:
+          tenant: globex
+"#,
+        )
+        .unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(1), routing_config);
+        let registry = DefaultRegistry::new();
+        let host = "web.example.com";
+        let stable = register_with_attributes(&registry, host, &[("version", "v1")]).await;
+        let canary = register_with_attributes(&registry, host, &[("version", "v2")]).await;
+        let acme = register_with_attributes(&registry, host, &[("tenant", "acme")]).await;
+
+        let cases = [
+            (request(host, "/", &[]), stable),
+            (request(host, "/", &[("x-canary", "true")]), canary),
+            (request(host, "/", &[("x-canary", "false")]), stable),
+            (request(host, "/search?q=x&beta=1", &[]), canary),
+            (
+                request(host, "/", &[("cookie", "session=abc; tenant=acme")]),
+                acme,
+            ),
+            // No globex registrations, so the default pool serves it
+            (request(host, "/", &[("x-tenant", "globex")]), stable),
+        ];
+        for (request, expected) in cases {
+            for _ in 0..3 {
+                assert_eq!(
+                    select_request(&router, &registry, request.clone()).await,
+                    expected,
+                    "{:?}",
+                    request
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_weights_change_at_runtime() {
+        let split_config = |v1: u32, v2: u32| -> RoutingConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+routes:
+  - host: web.example.com
+    split:
+      attribute: version
+      weights: {{ "1": {}, "2": {} }}
+      hash_on: "header:x-user"
+"#,
+                v1, v2
+            ))
+            .unwrap()
+        };
+        let router =
+            DefaultRouter::with_routing_config(Duration::from_secs(1), split_config(100, 0));
+        let registry = DefaultRegistry::new();
+        let host = "web.example.com";
+        let v1 = register_with_attributes(&registry, host, &[("version", "1")]).await;
+        let v2 = register_with_attributes(&registry, host, &[("version", "2")]).await;
+        let user = request(host, "/", &[("x-user", "alice")]);
+
+        assert_eq!(select_request(&router, &registry, user.clone()).await, v1);
+
+        router.routing_config.store(split_config(0, 100));
+        assert_eq!(select_request(&router, &registry, user.clone()).await, v2);
+        assert_eq!(
+            select_request(&router, &registry, request(host, "/", &[])).await,
+            v2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_affinity_pins_users() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+routes:
+  - host: web.example.com
+    affinity:
+      max_age_secs: 3600
+"#,
+        )
+        .unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(1), routing_config);
+        let registry = DefaultRegistry::new();
+        let host = "web.example.com";
+        register_healthy(&registry, host).await;
+        let pinned = register_healthy(&registry, host).await;
+
+        let set_cookie = |request: &ProxyRequest, service_id: Uuid| {
+            let mut response = DefaultRouter::create_error_response(request.id, 200, "ok");
+            let service = ServiceRegistration {
+                id: service_id,
+                service_name: "web".to_string(),
+                host: host.to_string(),
+                port: 8080,
+                cluster_name: "test-cluster".to_string(),
+                task_arn: String::new(),
+                attributes: HashMap::new(),
+                health_check_path: None,
+                paths: Vec::new(),
+            };
+            router.pin_session(request, &service, &mut response);
+            response
+                .headers
+                .into_iter()
+                .find(|(name, _)| name == "set-cookie")
+                .map(|(_, value)| value)
+        };
+
+        // A pinned user keeps its registration and is not re-pinned
+        let cookie = format!("theme=dark; mesh-affinity={}", pinned);
+        let request_pinned = request(host, "/", &[("cookie", &cookie)]);
+        for _ in 0..4 {
+            assert_eq!(
+                select_request(&router, &registry, request_pinned.clone()).await,
+                pinned
+            );
+        }
+        assert_eq!(set_cookie(&request_pinned, pinned), None);
+
+        // A new user, or one pinned to a registration that is gone, gets a cookie
+        let gone = format!("mesh-affinity={}", Uuid::new_v4());
+        let request_new = request(
+            host,
+            "/",
+            &[("cookie", &gone), ("x-forwarded-proto", "https")],
+        );
+        let selected = select_request(&router, &registry, request_new.clone()).await;
+        assert_eq!(
+            set_cookie(&request_new, selected),
+            Some(format!(
+                "mesh-affinity={}; Path=/; HttpOnly; SameSite=Lax; Max-Age=3600; Secure",
+                selected
+            ))
+        );
+
+        // Hosts without affinity never set the cookie
+        assert_eq!(
+            set_cookie(&request("other.example.com", "/", &[]), selected),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_rotates_through_instances() {
+        let router = DefaultRouter::new(Duration::from_secs(1));
+        let registry = DefaultRegistry::new();
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(register_healthy(&registry, "web.example.com").await);
+        }
+        ids.sort();
+
+        let mut picked = Vec::new();
+        for _ in 0..6 {
+            picked.push(select(&router, &registry, "web.example.com").await);
+        }
+        assert_eq!(picked[..3], ids[..]);
+        assert_eq!(picked[3..], ids[..]);
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_is_kept_per_host_pattern() {
+        let router = DefaultRouter::new(Duration::from_secs(1));
+        let registry = DefaultRegistry::new();
+        for _ in 0..2 {
+            register_healthy(&registry, "*.example.com").await;
+        }
+
+        // Every host the wildcard serves advances the same rotation
+        for n in 0..20 {
+            select(&router, &registry, &format!("tenant-{}.example.com", n)).await;
+        }
+        let round_robin = lock(&router.round_robin);
+        assert_eq!(round_robin.positions.len(), 1);
+        assert_eq!(round_robin.positions["*.example.com"].0, 20);
+    }
+
+    #[test]
+    fn test_round_robin_drops_idle_patterns() {
+        let mut round_robin = RoundRobin::new();
+        let start = round_robin.last_sweep;
+        assert_eq!(round_robin.next("old.example.com", start), 0);
+        assert_eq!(round_robin.next("new.example.com", start), 0);
+        assert_eq!(
+            round_robin.next("new.example.com", start + IDLE_ROUND_ROBIN_TTL / 2),
+            1
+        );
+
+        let later = start + IDLE_ROUND_ROBIN_TTL;
+        assert_eq!(round_robin.next("new.example.com", later), 2);
+        assert!(!round_robin.positions.contains_key("old.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_registration_changes_route_immediately() {
+        let router = DefaultRouter::new(Duration::from_secs(1));
+        let registry = DefaultRegistry::new();
+        let first = register_healthy(&registry, "web.example.com").await;
+        assert_eq!(select(&router, &registry, "web.example.com").await, first);
+
+        let second = register_healthy(&registry, "web.example.com").await;
+        registry.deregister_service(first).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(select(&router, &registry, "web.example.com").await, second);
+        }
+
+        registry.remove_connection(second).await.unwrap();
+        let services = router
+            .find_matching_services(&request("web.example.com", "/", &[]), &registry)
+            .await
+            .unwrap();
+        assert!(services.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_least_connections_per_host_override() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+load_balancing: random
+routes:
+  - host: web.example.com
+    load_balancing: least_connections
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            routing_config.load_balancing_for_host("other.example.com"),
+            LoadBalancingStrategy::Random
+        );
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(1), routing_config);
+        let registry = DefaultRegistry::new();
+        let busy = register_healthy(&registry, "web.example.com").await;
+        let idle = register_healthy(&registry, "web.example.com").await;
+
+        let _first = InFlightGuard::new(&router.in_flight, busy);
+        let _second = InFlightGuard::new(&router.in_flight, busy);
+        let _third = InFlightGuard::new(&router.in_flight, idle);
+        for _ in 0..4 {
+            assert_eq!(select(&router, &registry, "web.example.com").await, idle);
+        }
+
+        // Released requests stop counting
+        drop(_first);
+        drop(_second);
+        assert_eq!(select(&router, &registry, "web.example.com").await, busy);
+        drop(_third);
+        assert!(lock(&router.in_flight).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_random_reaches_every_instance() {
+        let routing_config: RoutingConfig = serde_yaml::from_str("load_balancing: random").unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(1), routing_config);
+        let registry = DefaultRegistry::new();
+        let first = register_healthy(&registry, "web.example.com").await;
+        let second = register_healthy(&registry, "web.example.com").await;
+
+        let mut picked = std::collections::HashSet::new();
+        for _ in 0..64 {
+            picked.insert(select(&router, &registry, "web.example.com").await);
+        }
+        assert_eq!(picked, std::collections::HashSet::from([first, second]));
+    }
+
+    /// Register a live instance with a chosen id, returning its tunnel
+    async fn connect(
+        registry: &DefaultRegistry,
+        id: u128,
+    ) -> tokio::sync::mpsc::UnboundedReceiver<IngressMessage> {
+        let connection_id = Uuid::from_u128(id);
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        registry
+            .register_connection(connection_id, sender)
+            .await
+            .unwrap();
+        registry
+            .register_service(
+                connection_id,
+                ServiceRegistration {
+                    id: connection_id,
+                    service_name: "web".to_string(),
+                    host: "web.example.com".to_string(),
+                    port: 8080,
+                    cluster_name: "test-cluster".to_string(),
+                    task_arn: String::new(),
+                    health_check_path: None,
+                    paths: Vec::new(),
+                    attributes: HashMap::new(),
+                },
+            )
+            .await
+            .unwrap();
+        receiver
+    }
+
+    /// Answer every request arriving on a tunnel with a 200
+    fn answer(
+        router: &DefaultRouter,
+        tunnel: tokio::sync::mpsc::UnboundedReceiver<IngressMessage>,
+    ) {
+        answer_with(router, tunnel, 200);
+    }
+
+    fn answer_with(
+        router: &DefaultRouter,
+        mut tunnel: tokio::sync::mpsc::UnboundedReceiver<IngressMessage>,
+        status_code: u16,
+    ) {
+        let router = router.clone();
+        tokio::spawn(async move {
+            while let Some(IngressMessage::ProxyRequestForward(request)) = tunnel.recv().await {
+                let response = DefaultRouter::create_error_response(request.id, status_code, "ok");
+                router.handle_response(response).await.unwrap();
+            }
+        });
+    }
+
+    fn attempts(response: &ProxyResponse) -> Option<&str> {
+        response
+            .headers
+            .iter()
+            .find(|(name, _)| name == MESH_ATTEMPTS_HEADER)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_retry_when_tunnel_drops_mid_request() {
+        let router = DefaultRouter::new(Duration::from_secs(5));
+        let registry = DefaultRegistry::new();
+        // Round-robin tries the lowest id first
+        let mut dropping = connect(&registry, 1).await;
+        answer(&router, connect(&registry, 2).await);
+        tokio::spawn(async move {
+            dropping.recv().await;
+        });
+
+        let request = request("web.example.com", "/", &[]);
+        let request_id = request.id;
+        let response = router.route_request(request, &registry).await.unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.id, request_id);
+        assert_eq!(attempts(&response), Some("2"));
+        assert_eq!(
+            router.retry_stats(),
+            RetryStats {
+                retries: 1,
+                budget_exhausted: 0
+            }
+        );
+        assert!(router.pending_requests.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_only_idempotent_requests_after_per_try_timeout() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+retry:
+  max_attempts: 3
+  per_try_timeout_ms: 50
+"#,
+        )
+        .unwrap();
+        let registry = DefaultRegistry::new();
+        let _silent = connect(&registry, 1).await;
+        let answering = connect(&registry, 2).await;
+
+        let router =
+            DefaultRouter::with_routing_config(Duration::from_secs(5), routing_config.clone());
+        answer(&router, answering);
+        let response = router
+            .route_request(request("web.example.com", "/", &[]), &registry)
+            .await
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(attempts(&response), Some("2"));
+
+        let mut post = request("web.example.com", "/", &[]);
+        post.method = "POST".to_string();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(5), routing_config);
+        let response = router.route_request(post, &registry).await.unwrap();
+        assert_eq!(response.status_code, 504);
+        assert!(response
+            .headers
+            .contains(&(MESH_TIMEOUT_HEADER.to_string(), "per-try".to_string())));
+        assert_eq!(attempts(&response), Some("1"));
+        assert_eq!(router.retry_stats().retries, 0);
+    }
+
+    #[test]
+    fn test_retry_budget_caps_retries() {
+        let config = RetryBudgetConfig {
+            percent: 20,
+            min_per_sec: 0,
+        };
+        let mut budget = RetryBudget::new();
+        assert!(!budget.withdraw(config));
+        for _ in 0..10 {
+            budget.record_request();
+        }
+        assert!(budget.withdraw(config));
+        assert!(budget.withdraw(config));
+        assert!(!budget.withdraw(config));
+
+        // The floor allows retries even without traffic
+        let mut budget = RetryBudget::new();
+        assert!(budget.withdraw(RetryBudgetConfig {
+            percent: 20,
+            min_per_sec: 1,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_failing_registration_is_ejected() {
+        let routing_config: RoutingConfig = serde_yaml::from_str(
+            r#"
+outlier_detection:
+  consecutive_failures: 2
+"#,
+        )
+        .unwrap();
+        let router = DefaultRouter::with_routing_config(Duration::from_secs(5), routing_config);
+        let registry = DefaultRegistry::new();
+        answer_with(&router, connect(&registry, 1).await, 500);
+        answer(&router, connect(&registry, 2).await);
+
+        // Round-robin alternates until the failing instance is ejected
+        let mut statuses = Vec::new();
+        for _ in 0..6 {
+            let response = router
+                .route_request(request("web.example.com", "/", &[]), &registry)
+                .await
+                .unwrap();
+            statuses.push(response.status_code);
+        }
+        assert_eq!(statuses, [500, 200, 500, 200, 200, 200]);
+
+        let ejections = router.ejections();
+        assert_eq!(ejections.len(), 1);
+        assert_eq!(ejections[0].registration_id, Uuid::from_u128(1));
+        assert_eq!(ejections[0].ejections, 1);
+        assert!(ejections[0].remaining <= Duration::from_secs(30));
+    }
+
     #[tokio::test]
     async fn test_handle_response() {
         let router = DefaultRouter::new(Duration::from_secs(1));
@@ -0,0 +1,259 @@
+//! Registrations indexed by host, kept in step with the registry's change events.
+
+use super::error::IngressResult;
+use super::registry::{Registry, RegistryEvent};
+use crate::common::{routing, ServiceRegistration};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Registrations sharing a host pattern
+struct HostEntry {
+    pattern: String,
+    /// Ordered by id, so round-robin positions mean the same thing across lookups
+    services: Vec<ServiceRegistration>,
+}
+
+/// Registrations indexed by the host pattern they registered
+#[derive(Default)]
+pub struct RoutingTable {
+    /// Exact hosts, keyed lowercased
+    exact: HashMap<String, HostEntry>,
+    /// Wildcard and regex patterns, keyed as registered
+    patterns: HashMap<String, HostEntry>,
+    /// Index key of each registration
+    keys: HashMap<Uuid, String>,
+}
+
+impl RoutingTable {
+    pub fn from_registrations(
+        registrations: impl IntoIterator<Item = ServiceRegistration>,
+    ) -> Self {
+        let mut table = Self::default();
+        for registration in registrations {
+            table.insert(registration);
+        }
+        table
+    }
+
+    /// Apply one registry change
+    pub fn apply(&mut self, event: RegistryEvent) {
+        match event {
+            RegistryEvent::Registered(registration) => self.insert(registration),
+            RegistryEvent::Deregistered(id) | RegistryEvent::ConnectionRemoved(id) => {
+                self.remove(id)
+            }
+        }
+    }
+
+    /// Registrations for the host pattern that matches `host` most specifically
+    pub fn services_for_host(&self, host: &str) -> Vec<ServiceRegistration> {
+        if let Some(entry) = self.exact.get(&host.to_ascii_lowercase()) {
+            return entry.services.clone();
+        }
+
+        let mut services: Vec<_> =
+            routing::best_host_matches(host, self.patterns.values(), |entry| {
+                entry.pattern.as_str()
+            })
+            .into_iter()
+            .flat_map(|entry| entry.services.iter().cloned())
+            .collect();
+        services.sort_by_key(|reg| reg.id);
+        services
+    }
+
+    fn insert(&mut self, registration: ServiceRegistration) {
+        // A re-registration may have moved to another host
+        self.remove(registration.id);
+
+        let (index, key) = if routing::is_exact_host(&registration.host) {
+            (&mut self.exact, registration.host.to_ascii_lowercase())
+        } else {
+            (&mut self.patterns, registration.host.clone())
+        };
+        let entry = index.entry(key.clone()).or_insert_with(|| HostEntry {
+            pattern: registration.host.clone(),
+            services: Vec::new(),
+        });
+        let position = entry
+            .services
+            .partition_point(|reg| reg.id < registration.id);
+        self.keys.insert(registration.id, key);
+        entry.services.insert(position, registration);
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        let Some(key) = self.keys.remove(&id) else {
+            return;
+        };
+        for index in [&mut self.exact, &mut self.patterns] {
+            if let Some(entry) = index.get_mut(&key) {
+                entry.services.retain(|reg| reg.id != id);
+                if entry.services.is_empty() {
+                    index.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// A routing table following a registry
+///
+/// Pending events are applied before every lookup, so a registration is
+/// routable as soon as the registry call that made it returns. The table is
+/// rebuilt from a registry snapshot on first use and whenever it falls too
+/// far behind the events.
+#[derive(Default)]
+pub struct SyncedRoutingTable {
+    state: Mutex<Option<Synced>>,
+}
+
+struct Synced {
+    table: RoutingTable,
+    events: broadcast::Receiver<RegistryEvent>,
+}
+
+impl SyncedRoutingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registrations for the host pattern that matches `host` most specifically
+    pub async fn services_for_host(
+        &self,
+        host: &str,
+        registry: &dyn Registry,
+    ) -> IngressResult<Vec<ServiceRegistration>> {
+        loop {
+            if let Some(services) = self.lookup(host) {
+                return Ok(services);
+            }
+
+            debug!("Rebuilding routing table from the registry");
+            // Subscribe before the snapshot so no change falls in between
+            let events = registry.subscribe();
+            let table = RoutingTable::from_registrations(
+                registry.get_all_registrations().await?.into_values(),
+            );
+            *self.lock() = Some(Synced { table, events });
+        }
+    }
+
+    /// Catch up on events and look up `host`; `None` when a rebuild is needed
+    fn lookup(&self, host: &str) -> Option<Vec<ServiceRegistration>> {
+        let mut state = self.lock();
+        let synced = state.as_mut()?;
+        loop {
+            match synced.events.try_recv() {
+                Ok(event) => synced.table.apply(event),
+                Err(TryRecvError::Empty) => return Some(synced.table.services_for_host(host)),
+                Err(TryRecvError::Lagged(missed)) => {
+                    warn!("Routing table missed {} registry events", missed);
+                    *state = None;
+                    return None;
+                }
+                Err(TryRecvError::Closed) => {
+                    *state = None;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Synced>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::registry::DefaultRegistry;
+
+    fn registration(host: &str) -> ServiceRegistration {
+        ServiceRegistration {
+            id: Uuid::new_v4(),
+            service_name: "web".to_string(),
+            host: host.to_string(),
+            port: 8080,
+            cluster_name: "test-cluster".to_string(),
+            task_arn: String::new(),
+            health_check_path: None,
+            paths: Vec::new(),
+            attributes: HashMap::new(),
+        }
+    }
+
+    fn ids(services: &[ServiceRegistration]) -> Vec<Uuid> {
+        services.iter().map(|reg| reg.id).collect()
+    }
+
+    #[test]
+    fn test_routing_table_applies_events() {
+        let exact = registration("App.example.com");
+        let wildcard = registration("*.example.com");
+        let mut table = RoutingTable::from_registrations([exact.clone(), wildcard.clone()]);
+
+        assert_eq!(ids(&table.services_for_host("app.example.com")), [exact.id]);
+        assert_eq!(
+            ids(&table.services_for_host("web.example.com")),
+            [wildcard.id]
+        );
+
+        // Re-registering under another host moves the registration
+        let mut moved = exact.clone();
+        moved.host = "web.example.com".to_string();
+        table.apply(RegistryEvent::Registered(moved));
+        assert_eq!(
+            ids(&table.services_for_host("app.example.com")),
+            [wildcard.id]
+        );
+        assert_eq!(ids(&table.services_for_host("web.example.com")), [exact.id]);
+
+        table.apply(RegistryEvent::ConnectionRemoved(exact.id));
+        table.apply(RegistryEvent::Deregistered(wildcard.id));
+        assert!(table.services_for_host("web.example.com").is_empty());
+        assert!(table.exact.is_empty() && table.patterns.is_empty() && table.keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_synced_table_sees_changes_immediately() {
+        let registry = DefaultRegistry::new();
+        let table = SyncedRoutingTable::new();
+        let first = registration("app.example.com");
+        let second = registration("app.example.com");
+
+        registry
+            .register_service(first.id, first.clone())
+            .await
+            .unwrap();
+        let services = table
+            .services_for_host("app.example.com", &registry)
+            .await
+            .unwrap();
+        assert_eq!(ids(&services), [first.id]);
+
+        registry
+            .register_service(second.id, second.clone())
+            .await
+            .unwrap();
+        registry.deregister_service(first.id).await.unwrap();
+        let services = table
+            .services_for_host("app.example.com", &registry)
+            .await
+            .unwrap();
+        assert_eq!(ids(&services), [second.id]);
+
+        registry.remove_connection(second.id).await.unwrap();
+        assert!(table
+            .services_for_host("app.example.com", &registry)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,16 +1,25 @@
-use super::auth::{AuthService, DefaultAuthService};
-use super::dispatcher::{DefaultMessageDispatcher, MessageDispatcher};
-use super::registry::{DefaultRegistry, Registry};
+use super::audit::{AuditEvent, AuditLog};
+use super::auth::{
+    sts_endpoint_origin, AuthService, CompositeAuthService, DefaultAuthService, JwtAuthService,
+    PresignedUrlPolicy, TokenAuthService,
+};
+use super::authz::RegistrationPolicy;
+use super::dispatcher::{new_auth_nonce, DefaultMessageDispatcher, MessageDispatcher};
+use super::oidc::AlbOidcVerifier;
+use super::registry::{ConnectionAuthState, DefaultRegistry, Registry};
 use super::router::{DefaultRouter, Router};
-use crate::common::{ProxyRequest, ProxyResponse};
-use anyhow::Result;
+use crate::common::config::{IngressConfig, RegistrationGrant, RoutingConfig, SharedRoutingConfig};
+use crate::common::{IamAuthResponse, IamIdentity, IngressMessage, ProxyRequest, ProxyResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
 
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, watch};
 use tokio_tungstenite::tungstenite::Message;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
 use hyper::Client as HttpClient;
@@ -20,15 +29,26 @@ use hyper_tls::HttpsConnector;
 pub struct CombinedIngressService {
     pub server_instance_id: Uuid,
     pub started_at: SystemTime,
+    pub config: Arc<IngressConfig>,
+    // Live routing settings; `config.routing` is only the startup copy
+    pub routing: SharedRoutingConfig,
 
     // Auth service for IAM authentication
     pub auth_service: Arc<dyn AuthService>,
+    // IAM backend, kept to update its allow-list at runtime
+    pub iam_auth: Arc<DefaultAuthService>,
+    // Bumped to make every connection re-authenticate
+    pub reauth_epoch: Arc<watch::Sender<u64>>,
     // Registry for managing connections and services
     pub registry: Arc<dyn Registry>,
     // Router for request routing and response handling
     pub router: Arc<dyn Router>,
     // Dispatcher for message handling
     pub dispatcher: Arc<dyn MessageDispatcher>,
+    // Security audit events (auth, registrations, disconnects, reloads)
+    pub audit: Arc<AuditLog>,
+    // Verifier for ALB OIDC end-user tokens on routes that require them
+    pub alb_oidc: Arc<AlbOidcVerifier>,
     pub ws_sessions:
         Arc<tokio::sync::RwLock<std::collections::HashMap<Uuid, super::ws_proxy::WsSession>>>,
     pub ws_init_waiters: Arc<
@@ -42,41 +62,107 @@ pub struct CombinedIngressService {
 }
 
 impl CombinedIngressService {
-    pub fn new() -> Self {
+    pub fn new(config: IngressConfig) -> Result<Self> {
+        let trusted_sts_endpoints = config
+            .auth
+            .trusted_sts_endpoints
+            .iter()
+            .map(|endpoint| {
+                sts_endpoint_origin(endpoint).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid auth.trusted_sts_endpoints entry {}: {}",
+                        endpoint,
+                        e
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !trusted_sts_endpoints.is_empty() {
+            info!("Trusting STS endpoints {:?}", trusted_sts_endpoints);
+        }
+
         let https = HttpsConnector::new();
         let http_client = HttpClient::builder().build::<_, hyper::Body>(https);
 
-        // Load configuration for auth service
-        let skip_iam_validation = std::env::var("SKIP_IAM_VALIDATION")
-            .ok()
-            .map(|s| s.to_lowercase() == "true")
-            .unwrap_or(false);
-
-        let allowed_role_patterns = std::env::var("ALLOWED_ROLE_ARNS")
-            .ok()
-            .map(|s| s.split(',').map(|p| p.trim().to_string()).collect())
-            .unwrap_or_else(|| vec!["*".to_string()]);
+        let iam_auth = Arc::new(
+            DefaultAuthService::new(
+                http_client.clone(),
+                config.auth.allowed_role_arns.clone(),
+                config.auth.skip_validation,
+            )
+            .with_presigned_url_policy(PresignedUrlPolicy {
+                max_expires_secs: config.auth.presigned_url_max_expires_secs,
+                clock_skew_secs: config.auth.presigned_url_clock_skew_secs,
+                session_ttl_secs: config.auth.iam_session_ttl_secs,
+                trusted_sts_endpoints,
+            }),
+        );
+        let mut auth_service = CompositeAuthService::new().with_iam(iam_auth.clone());
+        if let Some(secret) = &config.auth.token_secret {
+            // An empty or short key would let anyone forge tokens
+            crate::common::token::validate_secret(secret.as_bytes())
+                .map_err(|e| anyhow::anyhow!("Invalid auth.token_secret: {}", e))?;
+            info!("Token authentication enabled");
+            auth_service =
+                auth_service.with_token(Arc::new(TokenAuthService::new(secret.as_bytes())));
+        }
+        if let Some(jwt) = &config.auth.jwt {
+            info!("JWT authentication enabled for issuer {}", jwt.issuer);
+            auth_service = auth_service.with_jwt(Arc::new(JwtAuthService::new(
+                http_client.clone(),
+                jwt.clone(),
+            )?));
+        }
+        let auth_service = Arc::new(auth_service);
 
-        let auth_service = Arc::new(DefaultAuthService::new(
-            http_client.clone(),
-            allowed_role_patterns,
-            skip_iam_validation,
-        ));
+        validate_routing(&config.routing)?;
+        validate_registration_policy(&config.auth.registration_policy)?;
+        for route in &config.routing.routes {
+            if route.alb_oidc.is_some() {
+                info!("ALB OIDC user verification enabled for {}", route.host);
+            }
+            if route.access.is_some() {
+                info!("Access control enabled for {}", route.host);
+            }
+        }
 
+        let routing = SharedRoutingConfig::new(config.routing.clone());
         let registry = Arc::new(DefaultRegistry::new());
-        let router = Arc::new(DefaultRouter::new(Duration::from_secs(30))); // Default 30 second timeout
-        let dispatcher = Arc::new(DefaultMessageDispatcher::new());
+        let router = Arc::new(DefaultRouter::with_shared_routing_config(
+            Duration::from_secs(config.server.request_timeout),
+            routing.clone(),
+        ));
+        let audit = Arc::new(match &config.logging.audit_log {
+            Some(path) => {
+                info!("Audit log enabled: {}", path.display());
+                AuditLog::open(path)?
+            }
+            None => AuditLog::disabled(),
+        });
+        let dispatcher = Arc::new(
+            DefaultMessageDispatcher::with_registration_policy(RegistrationPolicy::new(
+                config.auth.registration_policy.clone(),
+            ))
+            .with_routing_config(routing.clone())
+            .with_audit_log(audit.clone()),
+        );
 
-        Self {
+        Ok(Self {
             server_instance_id: Uuid::new_v4(),
             started_at: SystemTime::now(),
+            config: Arc::new(config),
+            routing,
             auth_service,
+            iam_auth,
+            reauth_epoch: Arc::new(watch::channel(0).0),
             registry,
             router,
             dispatcher,
+            audit,
+            alb_oidc: Arc::new(AlbOidcVerifier::new(http_client)),
             ws_sessions: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
             ws_init_waiters: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
-        }
+        })
     }
 
     #[instrument(skip(self, proxy_request))]
@@ -94,10 +180,12 @@ impl CombinedIngressService {
         }
     }
 
-    #[instrument(skip(self, stream))]
+    #[instrument(skip(self, stream, peer_identity))]
     pub async fn handle_websocket_stream(
         &self,
         stream: tokio_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
+        peer_identity: Option<IamIdentity>,
+        peer_addr: Option<SocketAddr>,
     ) -> Result<()> {
         let (mut ws_sender, mut ws_receiver) = stream.split();
 
@@ -108,12 +196,60 @@ impl CombinedIngressService {
 
         // Store the sender for this connection
         self.registry.register_connection(connection_id, tx).await?;
+        if let Some(peer_addr) = peer_addr {
+            self.registry
+                .set_peer_addr(connection_id, peer_addr)
+                .await?;
+        }
+
+        // A verified client certificate authenticates the connection up front;
+        // tell the client so it can skip the auth handshake. Otherwise issue the
+        // nonce an IAM client has to sign into its presigned STS URL.
+        let cert_authenticated = peer_identity.is_some();
+        if let Some(identity) = peer_identity {
+            info!(
+                "Connection {} authenticated by client certificate: {}",
+                connection_id, identity.arn
+            );
+            self.audit.record(AuditEvent::AuthSuccess {
+                connection_id,
+                method: "client_certificate".to_string(),
+                arn: identity.arn.clone(),
+                account_id: identity.account_id.clone(),
+                principal_type: identity.principal_type.clone(),
+                source_ip: peer_addr.map(|addr| addr.ip()),
+                reauth: false,
+            });
+            self.registry
+                .set_authenticated(connection_id, identity.clone())
+                .await?;
+            self.dispatcher
+                .send_response(
+                    connection_id,
+                    IngressMessage::IamAuthResponse(IamAuthResponse {
+                        success: true,
+                        error: None,
+                        identity: Some(identity),
+                    }),
+                    self.registry.as_ref(),
+                )
+                .await?;
+        } else {
+            let nonce = self.issue_auth_challenge(connection_id).await?;
+            self.dispatcher
+                .send_response(
+                    connection_id,
+                    IngressMessage::AuthChallenge { nonce },
+                    self.registry.as_ref(),
+                )
+                .await?;
+        }
 
         // Clone service for message handling
         let service = self.clone();
 
         // Handle incoming messages
-        let incoming_handle = tokio::spawn(async move {
+        let mut incoming_handle = tokio::spawn(async move {
             while let Some(msg) = ws_receiver.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
@@ -136,7 +272,7 @@ impl CombinedIngressService {
         });
 
         // Handle outgoing messages
-        let outgoing_handle = tokio::spawn(async move {
+        let mut outgoing_handle = tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
                 let json_message = match serde_json::to_string(&message) {
                     Ok(json) => json,
@@ -153,13 +289,37 @@ impl CombinedIngressService {
             }
         });
 
+        // Close connections that do not authenticate, or re-authenticate, in time
+        let auth_watchdog = self.auth_watchdog(connection_id, cert_authenticated);
+
         // Wait for either handle to complete
         tokio::select! {
-            _ = incoming_handle => {},
-            _ = outgoing_handle => {},
+            _ = &mut incoming_handle => {},
+            _ = &mut outgoing_handle => {},
+            _ = auth_watchdog => {},
         }
+        incoming_handle.abort();
+        outgoing_handle.abort();
 
         // Clean up connection and associated services
+        if let Some(registration) = self
+            .registry
+            .get_all_registrations()
+            .await?
+            .remove(&connection_id)
+        {
+            self.audit.record(AuditEvent::Deregistration {
+                connection_id,
+                arn: self
+                    .registry
+                    .get_identity(connection_id)
+                    .await?
+                    .map(|i| i.arn),
+                service_name: registration.service_name,
+                host: registration.host,
+                reason: "disconnected".to_string(),
+            });
+        }
         if let Err(e) = self.registry.remove_connection(connection_id).await {
             error!("Failed to clean up connection {}: {}", connection_id, e);
         }
@@ -168,14 +328,243 @@ impl CombinedIngressService {
         Ok(())
     }
 
+    /// Generate and remember a fresh auth challenge nonce for a connection
+    async fn issue_auth_challenge(&self, connection_id: Uuid) -> Result<String> {
+        let nonce = new_auth_nonce();
+        self.registry
+            .set_auth_challenge(connection_id, nonce.clone())
+            .await?;
+        Ok(nonce)
+    }
+
+    /// Completes when a connection has to be closed for lack of authentication
+    ///
+    /// The initial handshake must finish within `handshake_timeout_secs`. After
+    /// that the connection is asked to re-authenticate every
+    /// `reauth_interval_secs`, when its credential expires and when the
+    /// allow-list changes, with the same window to answer. A certificate
+    /// cannot be re-verified within its TLS session, so certificate
+    /// authenticated connections are closed when the certificate expires and
+    /// have to reconnect with a renewed one.
+    async fn auth_watchdog(&self, connection_id: Uuid, cert_authenticated: bool) {
+        let window = Duration::from_secs(self.config.auth.handshake_timeout_secs);
+        let interval = Duration::from_secs(self.config.auth.reauth_interval_secs);
+        let mut reauth_epoch = self.reauth_epoch.subscribe();
+
+        if cert_authenticated {
+            self.certificate_expiry(connection_id).await;
+            return;
+        }
+        tokio::time::sleep(window).await;
+
+        loop {
+            let (identity, authenticated_at) =
+                match self.registry.get_auth_state(connection_id).await {
+                    Ok(ConnectionAuthState::Authenticated {
+                        identity,
+                        authenticated_at,
+                    }) => (identity, authenticated_at),
+                    _ => {
+                        warn!(
+                            "Closing connection {}: not authenticated within {}s",
+                            connection_id,
+                            window.as_secs()
+                        );
+                        self.record_forced_disconnect(connection_id, None, "handshake timeout")
+                            .await;
+                        return;
+                    }
+                };
+
+            let due = reauth_due(
+                authenticated_at,
+                interval,
+                identity.expires_at,
+                Instant::now(),
+                Utc::now().timestamp(),
+            );
+
+            tokio::select! {
+                _ = async {
+                    match due {
+                        Some(due) => tokio::time::sleep_until(due.into()).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+                changed = reauth_epoch.changed() => {
+                    if changed.is_err() {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+
+            let requested_at = Instant::now();
+            let nonce = match self.issue_auth_challenge(connection_id).await {
+                Ok(nonce) => nonce,
+                Err(_) => return,
+            };
+            info!(
+                "Requesting re-authentication of {} on connection {}",
+                identity.arn, connection_id
+            );
+            if self
+                .dispatcher
+                .send_response(
+                    connection_id,
+                    IngressMessage::ReauthRequest { nonce },
+                    self.registry.as_ref(),
+                )
+                .await
+                .is_err()
+            {
+                return;
+            }
+
+            tokio::time::sleep(window).await;
+            match self.registry.get_auth_state(connection_id).await {
+                Ok(ConnectionAuthState::Authenticated {
+                    authenticated_at, ..
+                }) if authenticated_at >= requested_at => {}
+                _ => {
+                    warn!(
+                        "Closing connection {}: not re-authenticated within {}s",
+                        connection_id,
+                        window.as_secs()
+                    );
+                    self.record_forced_disconnect(
+                        connection_id,
+                        Some(identity.arn),
+                        "re-authentication timeout",
+                    )
+                    .await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Completes, recording the disconnect, once the client certificate that
+    /// authenticated a connection expires
+    async fn certificate_expiry(&self, connection_id: Uuid) {
+        let identity = match self.registry.get_auth_state(connection_id).await {
+            Ok(ConnectionAuthState::Authenticated { identity, .. }) => identity,
+            _ => return,
+        };
+        let Some(expires_at) = identity.expires_at else {
+            return std::future::pending().await;
+        };
+
+        let remaining = (expires_at - Utc::now().timestamp()).max(0) as u64;
+        tokio::time::sleep(Duration::from_secs(remaining)).await;
+        warn!(
+            "Closing connection {}: client certificate of {} expired",
+            connection_id, identity.arn
+        );
+        self.record_forced_disconnect(
+            connection_id,
+            Some(identity.arn),
+            "client certificate expired",
+        )
+        .await;
+    }
+
+    async fn record_forced_disconnect(
+        &self,
+        connection_id: Uuid,
+        arn: Option<String>,
+        reason: &str,
+    ) {
+        let source_ip = self
+            .registry
+            .get_peer_addr(connection_id)
+            .await
+            .ok()
+            .flatten()
+            .map(|addr| addr.ip());
+        self.audit.record(AuditEvent::ForcedDisconnect {
+            connection_id,
+            arn,
+            source_ip,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Replace the IAM allow-list and, if it changed, re-check every
+    /// connection against it
+    pub fn update_allowed_role_arns(&self, allowed_role_arns: Vec<String>) {
+        self.audit.record(AuditEvent::ConfigReload {
+            success: true,
+            allowed_role_arns: allowed_role_arns.clone(),
+            error: None,
+        });
+        if !self
+            .iam_auth
+            .set_allowed_role_patterns(allowed_role_arns.clone())
+        {
+            info!("🔐 Allow-list unchanged; connections keep their authentication");
+            return;
+        }
+        info!(
+            "🔐 Allow-list updated: {:?}; re-authenticating connections",
+            allowed_role_arns
+        );
+        self.reauth_epoch.send_modify(|epoch| *epoch += 1);
+    }
+
+    /// Replace the registration policy; registrations already accepted stay
+    pub fn update_registration_policy(&self, grants: Vec<RegistrationGrant>) -> Result<()> {
+        validate_registration_policy(&grants)?;
+        info!("📜 Registration policy reloaded ({} grants)", grants.len());
+        self.dispatcher
+            .update_registration_policy(RegistrationPolicy::new(grants));
+        Ok(())
+    }
+
+    /// Apply reloaded routing settings (load balancing, rules, splits,
+    /// timeouts, access and ALB OIDC) without disturbing connected clients
+    pub fn update_routing(&self, routing: RoutingConfig) -> Result<()> {
+        validate_routing(&routing)?;
+        for route in &routing.routes {
+            if let Some(split) = &route.split {
+                info!(
+                    "🔀 Traffic split for {} on {}: {:?}",
+                    route.host, split.attribute, split.weights
+                );
+            }
+        }
+        self.routing.store(routing);
+        Ok(())
+    }
+
     #[instrument(skip(self, message))]
     pub async fn handle_websocket_message(
         &self,
         connection_id: Uuid,
         message: String,
     ) -> Result<()> {
-        // First, try to parse as IngressMessage to intercept WS proxy messages
+        // First, try to parse as IngressMessage to intercept WS proxy messages.
+        // Unauthenticated connections fall through to the dispatcher, which rejects them.
         if let Ok(parsed) = serde_json::from_str::<crate::common::IngressMessage>(&message) {
+            let is_ws_proxy_message = matches!(
+                parsed,
+                crate::common::IngressMessage::WebSocketProxyInitAck { .. }
+                    | crate::common::IngressMessage::WebSocketProxyData { .. }
+                    | crate::common::IngressMessage::WebSocketProxyClose { .. }
+            );
+            if is_ws_proxy_message && !self.registry.is_authenticated(connection_id).await? {
+                return self
+                    .dispatcher
+                    .handle_message(
+                        connection_id,
+                        message,
+                        self.auth_service.as_ref(),
+                        self.registry.as_ref(),
+                        self.router.as_ref(),
+                    )
+                    .await
+                    .map_err(Into::into);
+            }
+
             match parsed {
                 crate::common::IngressMessage::WebSocketProxyInitAck {
                     session_id,
@@ -226,3 +615,129 @@ impl CombinedIngressService {
         }
     }
 }
+
+/// Shortest wait before demanding re-authentication, so credentials that are
+/// already expired but still accepted (e.g. within a JWT leeway) do not
+/// cause back-to-back re-authentication
+const MIN_REAUTH_DELAY: Duration = Duration::from_secs(30);
+
+/// When a connection authenticated at `authenticated_at` has to
+/// re-authenticate: after `interval` (0 disables it) or when its credential
+/// expires at `expires_at` (Unix seconds), whichever comes first, but no
+/// sooner than `MIN_REAUTH_DELAY` from `now`
+fn reauth_due(
+    authenticated_at: Instant,
+    interval: Duration,
+    expires_at: Option<i64>,
+    now: Instant,
+    unix_now: i64,
+) -> Option<Instant> {
+    let by_interval = (!interval.is_zero()).then(|| authenticated_at + interval);
+    let by_expiry = expires_at
+        .map(|expires_at| now + Duration::from_secs((expires_at - unix_now).max(0) as u64));
+    let due = match (by_interval, by_expiry) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }?;
+    Some(due.max(now + MIN_REAUTH_DELAY))
+}
+
+/// Check the host patterns of every registration grant
+fn validate_registration_policy(grants: &[RegistrationGrant]) -> Result<()> {
+    for grant in grants {
+        for host in &grant.hosts {
+            crate::common::routing::validate_host_pattern(host).map_err(|reason| {
+                anyhow::anyhow!(
+                    "Registration grant for {}: host {}: {}",
+                    grant.principal,
+                    host,
+                    reason
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Check the host pattern, ALB OIDC and access settings, routing rules,
+/// weighted splits and retry policies of every route
+fn validate_routing(routing: &RoutingConfig) -> Result<()> {
+    if routing.retry.max_attempts == 0 {
+        anyhow::bail!("routing.retry.max_attempts must be at least 1");
+    }
+    for route in &routing.routes {
+        if route
+            .retry
+            .as_ref()
+            .is_some_and(|retry| retry.max_attempts == 0)
+        {
+            anyhow::bail!(
+                "Retry policy on route {} needs max_attempts of at least 1",
+                route.host
+            );
+        }
+        crate::common::routing::validate_host_pattern(&route.host)
+            .map_err(|reason| anyhow::anyhow!("Route host {}: {}", route.host, reason))?;
+        if route
+            .alb_oidc
+            .as_ref()
+            .is_some_and(|oidc| oidc.signer_arns.is_empty())
+        {
+            anyhow::bail!(
+                "ALB OIDC on route {} needs at least one signer ARN",
+                route.host
+            );
+        }
+        if let Some(access) = &route.access {
+            super::access::validate(access)
+                .with_context(|| format!("Invalid access settings on route {}", route.host))?;
+        }
+        if route.rules.iter().any(|rule| rule.attributes.is_empty()) {
+            anyhow::bail!(
+                "Routing rules on route {} need at least one attribute",
+                route.host
+            );
+        }
+        if let Some(split) = &route.split {
+            if split.attribute.is_empty() || split.weights.values().all(|weight| *weight == 0) {
+                anyhow::bail!(
+                    "Traffic split on route {} needs an attribute and a non-zero weight",
+                    route.host
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reauth_due() {
+        let now = Instant::now();
+        let unix_now = 1_700_000_000;
+        let interval = Duration::from_secs(900);
+
+        assert_eq!(
+            reauth_due(now, interval, None, now, unix_now),
+            Some(now + interval)
+        );
+        assert_eq!(reauth_due(now, Duration::ZERO, None, now, unix_now), None);
+        // The credential expiring first wins
+        assert_eq!(
+            reauth_due(now, interval, Some(unix_now + 120), now, unix_now),
+            Some(now + Duration::from_secs(120))
+        );
+        assert_eq!(
+            reauth_due(now, Duration::ZERO, Some(unix_now + 120), now, unix_now),
+            Some(now + Duration::from_secs(120))
+        );
+        // An already expired credential still gets a breather
+        assert_eq!(
+            reauth_due(now, interval, Some(unix_now - 10), now, unix_now),
+            Some(now + MIN_REAUTH_DELAY)
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use super::CombinedIngressService;
+use crate::common::config::TlsConfig;
+use crate::common::IamIdentity;
+use anyhow::{Context, Result};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+use x509_parser::extensions::GeneralName;
+
+/// Principal type reported for identities authenticated with a client certificate
+pub const CERT_PRINCIPAL_TYPE: &str = "ClientCertificate";
+
+/// Identity established by a verified client certificate, attached to upgrade
+/// requests on the control listener
+#[derive(Debug, Clone)]
+pub struct PeerIdentity(pub IamIdentity);
+
+/// Remote address of the control connection, attached to upgrade requests
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Build the TLS acceptor for the control listener
+pub fn build_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    let certs = load_certs(&config.cert_file)?;
+    let key = load_private_key(&config.key_file)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_file)? {
+                roots
+                    .add(&cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_file.display()))?;
+            }
+
+            let verifier = if config.require_client_cert {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => {
+            if config.require_client_cert {
+                anyhow::bail!("require_client_cert needs client_ca_file to be set");
+            }
+            builder.with_no_client_auth()
+        }
+    };
+
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .context("Invalid server certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Wait after a failed accept before trying again
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serve the WebSocket/health listener over TLS
+///
+/// Identities from verified client certificates are attached to each request
+/// as a `PeerIdentity` extension, and the remote address as a `PeerAddr`.
+pub async fn serve(
+    addr: SocketAddr,
+    acceptor: TlsAcceptor,
+    service: CombinedIngressService,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let handshake_timeout = Duration::from_secs(service.config.auth.handshake_timeout_secs);
+
+    loop {
+        // Accept errors (e.g. running out of file descriptors) are usually
+        // transient; back off instead of taking the listener down
+        let (tcp, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept TLS connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+
+        tokio::spawn(async move {
+            // A peer that never finishes the handshake must not hold the socket
+            let stream = match tokio::time::timeout(handshake_timeout, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    warn!("TLS handshake with {} failed: {}", peer_addr, e);
+                    return;
+                }
+                Err(_) => {
+                    warn!(
+                        "TLS handshake with {} timed out after {:?}",
+                        peer_addr, handshake_timeout
+                    );
+                    return;
+                }
+            };
+
+            let peer_certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first());
+            let peer_identity = peer_certificate
+                .and_then(|cert| identity_from_certificate(&cert.0))
+                .map(PeerIdentity);
+            if peer_certificate.is_some() && peer_identity.is_none() {
+                warn!(
+                    "Client certificate from {} has no SAN or common name; \
+                     the connection must authenticate another way",
+                    peer_addr
+                );
+            }
+            if let Some(PeerIdentity(identity)) = &peer_identity {
+                info!(
+                    "Client certificate from {} identifies {}",
+                    peer_addr, identity.arn
+                );
+            }
+
+            let svc = service_fn(move |mut req| {
+                let service = service.clone();
+                if let Some(identity) = &peer_identity {
+                    req.extensions_mut().insert(identity.clone());
+                }
+                req.extensions_mut().insert(PeerAddr(peer_addr));
+                async move { service.handle_websocket_or_health_request(req).await }
+            });
+
+            if let Err(e) = Http::new()
+                .serve_connection(stream, svc)
+                .with_upgrades()
+                .await
+            {
+                debug!("TLS connection from {} ended: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+/// Derive the connection identity from a verified client certificate
+///
+/// The name is the first URI or DNS subject alternative name, falling back to
+/// the subject common name; it is exposed as the pseudo ARN `mesh:cert/<name>`
+/// so registration policies can target it. The identity expires with the
+/// certificate.
+pub fn identity_from_certificate(der: &[u8]) -> Option<IamIdentity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+
+    let san_name = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::URI(uri) => Some(uri.to_string()),
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                _ => None,
+            })
+        });
+    let common_name = || {
+        cert.subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string)
+    };
+    let name = san_name.or_else(common_name)?;
+
+    Some(IamIdentity {
+        arn: format!("mesh:cert/{}", name),
+        account_id: String::new(),
+        user_id: cert.subject().to_string(),
+        principal_type: CERT_PRINCIPAL_TYPE.to_string(),
+        role_arn: None,
+        session_name: None,
+        expires_at: Some(cert.validity().not_after.timestamp()),
+        allowed_hosts: None,
+    })
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader)
+        .with_context(|| format!("Failed to parse certificates in {}", path.display()))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+
+    for item in rustls_pemfile::read_all(&mut reader)
+        .with_context(|| format!("Failed to parse private key in {}", path.display()))?
+    {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    anyhow::bail!("No private key found in {}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+
+    // Self-signed, CN=edge-box-1, SAN DNS:edge-box-1.mesh.internal
+    const SAN_CERT_B64: &str = "\
+    MIIBxDCCAWqgAwIBAgIUBVsMZ3yCJ7t6voaMxpMWH1wzZJ0wCgYIKoZIzj0EAwIwJDETMBEGA1UE\
+    AwwKZWRnZS1ib3gtMTENMAsGA1UECgwETWVzaDAgFw0yNjEwMTYyMDU5NDVaGA8yMTI2MDkyMjIw\
+    NTk0NVowJDETMBEGA1UEAwwKZWRnZS1ib3gtMTENMAsGA1UECgwETWVzaDBZMBMGByqGSM49AgEG\
+    CCqGSM49AwEHA0IABNUkMZw6dhVfb6+sVgfBB+4JbKvh5UeDEgM8wBIm9TffY6Il7cYOVmmVYRvw\
+    ybvTsWEr2qbzbyWf89RNsmeHq8ujeDB2MB0GA1UdDgQWBBSzzVRg+iapHps6PsHWqj6Q2fWV5zAf\
+    BgNVHSMEGDAWgBSzzVRg+iapHps6PsHWqj6Q2fWV5zAPBgNVHRMBAf8EBTADAQH/MCMGA1UdEQQc\
+    MBqCGGVkZ2UtYm94LTEubWVzaC5pbnRlcm5hbDAKBggqhkjOPQQDAgNIADBFAiEAvRHMm+USuKEH\
+    0i86/hGdOgbIHruMUX1hjJIfCW88tmMCIA1rsHptCC3Z34d4b4r99soPZJVVCe7UjA/gUEIyvJ6b";
+    // Self-signed, CN=edge-box-2, no SAN
+    const CN_CERT_B64: &str = "\
+    MIIBgTCCASegAwIBAgIUBw+0vf5CzgOue5r3Ck5dWLjZ+WwwCgYIKoZIzj0EAwIwFTETMBEGA1UE\
+    AwwKZWRnZS1ib3gtMjAgFw0yNjEwMTYyMDU5NDVaGA8yMTI2MDkyMjIwNTk0NVowFTETMBEGA1UE\
+    AwwKZWRnZS1ib3gtMjBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABEufk+opOUkUeEwMZiFQwNJ8\
+    Nul5Nhtr+3JPY4Q4GjtlmyAz2ip9XUqOepeuzhbTE1mqt0PkHq1ZrhLyHJWbd0yjUzBRMB0GA1Ud\
+    DgQWBBQZDDQdbpyqVBLQRf/qbp7zBP/RTTAfBgNVHSMEGDAWgBQZDDQdbpyqVBLQRf/qbp7zBP/R\
+    TTAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQCfD3JlbfORZ2kc52ZFpetRF70d\
+    C6mFX0FqLlh0qHG9GgIgSsk7a3xD0Y572qHiL2q192n2YXrWvq+6QAHRZnz1oDw=";
+
+    fn der(b64: &str) -> Vec<u8> {
+        general_purpose::STANDARD.decode(b64).unwrap()
+    }
+
+    #[test]
+    fn test_identity_from_certificate() {
+        let identity = identity_from_certificate(&der(SAN_CERT_B64)).unwrap();
+        assert_eq!(identity.arn, "mesh:cert/edge-box-1.mesh.internal");
+        assert_eq!(identity.principal_type, CERT_PRINCIPAL_TYPE);
+        assert!(identity.user_id.contains("CN=edge-box-1"));
+        // notAfter 2126-09-22T20:59:45Z
+        assert_eq!(identity.expires_at, Some(4_945_784_385));
+
+        let identity = identity_from_certificate(&der(CN_CERT_B64)).unwrap();
+        assert_eq!(identity.arn, "mesh:cert/edge-box-2");
+
+        assert!(identity_from_certificate(b"not a certificate").is_none());
+    }
+}
@@ -10,7 +10,7 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use super::service::CombinedIngressService;
-use crate::common::{routing, IngressMessage};
+use crate::common::{routing, IngressMessage, ProxyRequest};
 
 #[derive(Clone, Debug)]
 pub struct WsInitAck {
@@ -84,13 +84,36 @@ impl CombinedIngressService {
             .map(|s| s.split(':').next().unwrap_or(s).to_string())
             .or_else(|| req.uri().authority().map(|a| a.host().to_string()))
             .unwrap_or_else(|| "unknown".to_string());
+        if !routing::is_request_host(&host) {
+            return Ok(Self::invalid_host_response(&host));
+        }
 
-        let path = req
+        let mut path = req
             .uri()
             .path_and_query()
             .map(|p| p.to_string())
             .unwrap_or_else(|| "/".to_string());
 
+        let routing_config = self.routing.load();
+        let user_headers = match self
+            .authorize_end_user(&routing_config, &host, req.headers())
+            .await
+        {
+            Ok(user_headers) => user_headers,
+            Err(e) => return Ok(Self::unauthorized_response(&host, &e)),
+        };
+
+        let route_access = Self::route_access(&routing_config, &host);
+        if let Some(route_access) = route_access {
+            if !super::access::authorize(route_access, req.headers(), req.uri()).await {
+                warn!(
+                    "Rejected WebSocket upgrade for {} without valid credentials",
+                    host
+                );
+                return Ok(super::access::unauthorized_response(route_access));
+            }
+        }
+
         // Collect minimal headers to forward
         let mut fwd_headers: HashMap<String, String> = HashMap::new();
         for name in [
@@ -108,10 +131,34 @@ impl CombinedIngressService {
                 }
             }
         }
+        fwd_headers.extend(user_headers);
+        if let Some(route_access) = route_access {
+            path = super::access::strip_credentials(route_access, &mut fwd_headers, &path);
+        }
 
-        // Choose a target agent by host
-        let registrations = match self.registry.get_all_registrations().await {
-            Ok(map) => map,
+        // Choose a target agent the way HTTP requests to the host are routed:
+        // path and rules, then affinity, split, ejections and load balancing
+        let selection_request = ProxyRequest {
+            id: Uuid::new_v4(),
+            method: req.method().to_string(),
+            path: path.clone(),
+            headers: req
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            body: None,
+            target_host: host.clone(),
+            timeouts: None,
+        };
+        let matches = match self
+            .router
+            .find_matching_services(&selection_request, self.registry.as_ref())
+            .await
+        {
+            Ok(matches) => matches,
             Err(e) => {
                 error!("Failed to fetch registrations: {}", e);
                 return Ok(Response::builder()
@@ -120,9 +167,27 @@ impl CombinedIngressService {
                     .unwrap());
             }
         };
+        if matches.is_empty() {
+            warn!("No matching service for host {} path {}", host, path);
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Service Not Found"))
+                .unwrap());
+        }
 
-        let connections = match self.registry.get_all_connections().await {
-            Ok(map) => map,
+        let matched_service = match self
+            .router
+            .select_healthy_service(&selection_request, &matches, self.registry.as_ref())
+            .await
+        {
+            Ok(Some(service)) => service,
+            Ok(None) => {
+                warn!("Matched service unhealthy for host {}", host);
+                return Ok(Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("No healthy service available"))
+                    .unwrap());
+            }
             Err(e) => {
                 error!("Failed to fetch connections: {}", e);
                 return Ok(Response::builder()
@@ -132,25 +197,18 @@ impl CombinedIngressService {
             }
         };
 
-        let regs: Vec<_> = registrations.values().cloned().collect();
-        let maybe_match = routing::match_host_to_service(&host, &regs);
-        let Some(matched_service) = maybe_match else {
-            warn!("No matching service for host {}", host);
-            return Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from("Service Not Found"))
-                .unwrap());
-        };
-
-        // Check health
-        if routing::select_healthy_instance(std::slice::from_ref(matched_service), &connections)
-            .is_none()
+        // Forward the labels the host pattern captured
+        for (name, value) in
+            routing::host_captures(&matched_service.host, &host).unwrap_or_default()
         {
-            warn!("Matched service unhealthy for host {}", host);
-            return Ok(Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .body(Body::from("No healthy service available"))
-                .unwrap());
+            fwd_headers.insert(
+                format!(
+                    "{}{}",
+                    routing::HOST_CAPTURE_HEADER_PREFIX,
+                    name.to_ascii_lowercase()
+                ),
+                value,
+            );
         }
 
         // Prepare and send init message to the agent connection
@@ -367,13 +425,27 @@ impl CombinedIngressService {
             }
         });
 
-        Ok(Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::SWITCHING_PROTOCOLS)
             .header("upgrade", "websocket")
             .header("connection", "upgrade")
-            .header("sec-websocket-accept", accept_key)
-            .body(Body::empty())
-            .unwrap())
+            .header("sec-websocket-accept", accept_key);
+        // Pin the session unless the request was already pinned to this registration
+        let affinity = routing_config.affinity_for_host(&host).filter(|affinity| {
+            routing::affinity_target(affinity, &selection_request.headers)
+                != Some(matched_service.id)
+        });
+        if let Some(affinity) = affinity {
+            let secure = selection_request
+                .headers
+                .get("x-forwarded-proto")
+                .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+            response = response.header(
+                "set-cookie",
+                routing::affinity_cookie(affinity, matched_service.id, secure),
+            );
+        }
+        Ok(response.body(Body::empty()).unwrap())
     }
 
     pub async fn handle_ws_proxy_init_ack(
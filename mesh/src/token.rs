@@ -0,0 +1,30 @@
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::commands::{TokenCommand, TokenSubcommand};
+use crate::common::routing::validate_host_pattern;
+use crate::common::token::{issue_token, validate_secret, TokenClaims};
+
+pub fn run(args: TokenCommand) -> Result<()> {
+    match args.command {
+        TokenSubcommand::Issue(issue) => {
+            validate_secret(issue.secret.as_bytes()).map_err(|e| anyhow::anyhow!(e))?;
+            for host in &issue.hosts {
+                validate_host_pattern(host)
+                    .map_err(|reason| anyhow::anyhow!("Host {}: {}", host, reason))?;
+            }
+
+            let now = Utc::now().timestamp();
+            let claims = TokenClaims {
+                sub: issue.subject,
+                hosts: issue.hosts,
+                iat: now,
+                exp: now + issue.ttl_secs as i64,
+            };
+
+            println!("{}", issue_token(issue.secret.as_bytes(), &claims));
+        }
+    }
+
+    Ok(())
+}